@@ -24,6 +24,7 @@ use std::io::{self, Read, Write};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool};
+use std::time::Duration;
 use rustc_serialize::hex::FromHex;
 
 use hyper;
@@ -34,19 +35,25 @@ use SyncStatus;
 use util::{Mutex, H256};
 use util::sha3::sha3;
 use page::LocalPageEndpoint;
-use handlers::{ContentHandler, ContentFetcherHandler, ContentValidator};
+use handlers::{ContentHandler, ContentFetcherHandler, ContentValidator, FetchMode, FetchOptions, FetchLimit, BandwidthLimit};
+use handlers::client::{validate_headers, FetchError};
 use endpoint::{Endpoint, EndpointPath, Handler};
 use apps::cache::{ContentCache, ContentStatus};
 use apps::manifest::{MANIFEST_FILENAME, deserialize_manifest, serialize_manifest, Manifest};
 use apps::urlhint::{URLHintContract, URLHint, URLHintResult};
 
 const MAX_CACHED_DAPPS: usize = 10;
+const FETCH_RETRY_DELAY_MS: u64 = 500;
+const MAX_CONCURRENT_FETCHES: usize = 8;
+const MAX_FETCHED_BYTES: u64 = 1024 * 1024 * 1024;
+const FETCHED_BYTES_WINDOW_SECS: u64 = 60 * 60;
 
 pub struct ContentFetcher<R: URLHint = URLHintContract> {
 	dapps_path: PathBuf,
 	resolver: R,
 	cache: Arc<Mutex<ContentCache>>,
 	sync: Arc<SyncStatus>,
+	fetch_options: FetchOptions,
 }
 
 impl<R: URLHint> Drop for ContentFetcher<R> {
@@ -67,9 +74,44 @@ impl<R: URLHint> ContentFetcher<R> {
 			resolver: resolver,
 			sync: sync_status,
 			cache: Arc::new(Mutex::new(ContentCache::default())),
+			fetch_options: FetchOptions {
+				mode: FetchMode::Redirect,
+				retry_delay: Duration::from_millis(FETCH_RETRY_DELAY_MS),
+				headers: Vec::new(),
+				temp_dir: env::temp_dir(),
+				limit: FetchLimit::new(MAX_CONCURRENT_FETCHES),
+				bandwidth: Arc::new(BandwidthLimit::new(MAX_FETCHED_BYTES, Duration::from_secs(FETCHED_BYTES_WINDOW_SECS))),
+				redirect_base: None,
+			},
 		}
 	}
 
+	/// Redirects to fetched content relative to `base` instead of the local dapps address.
+	pub fn with_redirect_base(mut self, base: Option<String>) -> Self {
+		self.fetch_options.redirect_base = base;
+		self
+	}
+
+	/// Changes how the fetched content is handed to the client.
+	pub fn with_mode(mut self, mode: FetchMode) -> Self {
+		self.fetch_options.mode = mode;
+		self
+	}
+
+	/// Downloads content to `dir` instead of the system temp dir.
+	pub fn with_temp_dir(mut self, dir: PathBuf) -> Self {
+		self.fetch_options.temp_dir = dir;
+		self
+	}
+
+	/// Attaches `headers` to every request fetching content.
+	/// Fails if any of the headers has an invalid name or value.
+	pub fn with_headers(mut self, headers: Vec<(String, String)>) -> Result<Self, FetchError> {
+		try!(validate_headers(&headers));
+		self.fetch_options.headers = headers;
+		Ok(self)
+	}
+
 	#[cfg(test)]
 	fn set_status(&self, content_id: &str, status: ContentStatus) {
 		self.cache.lock().insert(content_id.to_owned(), status);
@@ -137,11 +179,13 @@ impl<R: URLHint> ContentFetcher<R> {
 								abort,
 								control,
 								path.using_dapps_domains,
+								self.fetch_options.clone(),
 								DappInstaller {
 									id: content_id.clone(),
 									dapps_path: self.dapps_path.clone(),
 									cache: self.cache.clone(),
-								})) as Box<Handler>
+								}
+							).expect("Headers are validated when they are set; qed")) as Box<Handler>
 						),
 						Some(URLHintResult::Content(content)) => (
 							Some(ContentStatus::Fetching(abort.clone())),
@@ -150,13 +194,14 @@ impl<R: URLHint> ContentFetcher<R> {
 								abort,
 								control,
 								path.using_dapps_domains,
+								self.fetch_options.clone(),
 								ContentInstaller {
 									id: content_id.clone(),
 									mime: content.mime,
 									content_path: self.dapps_path.clone(),
 									cache: self.cache.clone(),
 								}
-							)) as Box<Handler>,
+							).expect("Headers are validated when they are set; qed")) as Box<Handler>,
 						),
 						None => {
 							// This may happen when sync status changes in between
@@ -250,6 +295,10 @@ impl ContentValidator for ContentInstaller {
 		Ok((self.id.clone(), content_path))
 	}
 
+	fn inline_content(&self, result: &PathBuf) -> Option<(String, PathBuf)> {
+		Some((self.mime.clone(), result.clone()))
+	}
+
 	fn done(&self, result: Option<&PathBuf>) {
 		let mut cache = self.cache.lock();
 		match result {
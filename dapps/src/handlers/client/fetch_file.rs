@@ -16,18 +16,19 @@
 
 //! Hyper Client Handler to Fetch File
 
-use std::{env, io, fs, fmt};
-use std::path::PathBuf;
+use std::{io, fs, fmt};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::{mpsc, Arc};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
-use random_filename;
 
 use hyper::status::StatusCode;
 use hyper::client::{Request, Response, DefaultTransport as HttpStream};
-use hyper::header::Connection;
+use hyper::header::{Connection, Range, ByteRangeSpec, ContentRange, ContentRangeSpec};
 use hyper::{self, Decoder, Encoder, Next};
 
+use handlers::BandwidthLimit;
 use super::FetchError;
 
 #[derive(Debug)]
@@ -35,6 +36,8 @@ pub enum Error {
 	Aborted,
 	NotStarted,
 	UnexpectedStatus(StatusCode),
+	/// Partial content doesn't start where the download stopped.
+	UnexpectedRange(Option<u64>),
 	IoError(io::Error),
 	HyperError(hyper::Error),
 }
@@ -44,10 +47,14 @@ pub type OnDone = Box<Fn() + Send>;
 
 pub struct Fetch {
 	path: PathBuf,
+	resume: bool,
+	offset: u64,
 	abort: Arc<AtomicBool>,
 	file: Option<fs::File>,
 	result: Option<FetchResult>,
 	sender: mpsc::Sender<FetchResult>,
+	headers: Vec<(String, String)>,
+	bandwidth: Arc<BandwidthLimit>,
 	on_done: Option<OnDone>,
 }
 
@@ -60,8 +67,8 @@ impl fmt::Debug for Fetch {
 impl Drop for Fetch {
     fn drop(&mut self) {
 		let res = self.result.take().unwrap_or(Err(Error::NotStarted.into()));
-		// Remove file if there was an error
-		if res.is_err() || self.is_aborted() {
+		// Remove file if there was an error, unless it's kept to resume the download later
+		if (res.is_err() && !self.resume) || self.is_aborted() {
 			if let Some(file) = self.file.take() {
 				drop(file);
 				// Remove file
@@ -76,17 +83,50 @@ impl Drop for Fetch {
     }
 }
 
-impl Fetch {
-	pub fn new(sender: mpsc::Sender<FetchResult>, abort: Arc<AtomicBool>, on_done: OnDone) -> Self {
-		let mut dir = env::temp_dir();
-		dir.push(random_filename());
+/// Number of bytes already downloaded to `path` by previous attempt.
+pub fn resume_offset(path: &Path) -> u64 {
+	fs::metadata(path).map(|meta| meta.len()).unwrap_or(0)
+}
+
+/// Start of the byte range of partial content, if the response has one.
+pub fn range_start(res: &Response) -> Option<u64> {
+	match res.headers().get::<ContentRange>() {
+		Some(&ContentRange(ContentRangeSpec::Bytes { range: Some((start, _)), .. })) => Some(start),
+		_ => None,
+	}
+}
 
+/// Opens the download file for the response with given status.
+/// Partial content is appended to the bytes already there, full content replaces them.
+/// Partial content starting anywhere else than `offset` can't be used, so the file is truncated
+/// and the next attempt downloads the full content.
+pub fn open_for_response(path: &Path, status: StatusCode, range_start: Option<u64>, offset: u64) -> Result<fs::File, Error> {
+	match status {
+		StatusCode::PartialContent if offset > 0 && range_start == Some(offset) => fs::OpenOptions::new().append(true).open(path).map_err(Error::IoError),
+		StatusCode::PartialContent if offset > 0 => {
+			try!(fs::File::create(path).map_err(Error::IoError));
+			Err(Error::UnexpectedRange(range_start))
+		},
+		StatusCode::Ok => fs::File::create(path).map_err(Error::IoError),
+		status => Err(Error::UnexpectedStatus(status)),
+	}
+}
+
+impl Fetch {
+	/// Creates a handler downloading to `path`. With `resume` the file is kept after failure
+	/// and the next attempt only requests the missing bytes.
+	/// Bytes are counted in `bandwidth` as they are written, whether the download completes or not.
+	pub fn new(sender: mpsc::Sender<FetchResult>, path: PathBuf, resume: bool, headers: Vec<(String, String)>, bandwidth: Arc<BandwidthLimit>, abort: Arc<AtomicBool>, on_done: OnDone) -> Self {
 		Fetch {
-			path: dir,
+			path: path,
+			resume: resume,
+			offset: 0,
 			abort: abort,
 			file: None,
 			result: None,
 			sender: sender,
+			headers: headers,
+			bandwidth: bandwidth,
 			on_done: Some(on_done),
 		}
 	}
@@ -100,6 +140,28 @@ impl Fetch {
 		self.result = Some(Err(Error::Aborted.into()));
 		Next::end()
 	}
+
+	// writes the content received so far to the file, counting the written bytes.
+	fn write_available(&mut self, decoder: &mut Decoder<HttpStream>, written: &mut u64) -> Next {
+		let mut buf = [0u8; 4096];
+		loop {
+			let read = match decoder.read(&mut buf) {
+				Ok(0) => return Next::end(),
+				Ok(read) => read,
+				Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Next::read(),
+				Err(e) => {
+					self.result = Some(Err(Error::IoError(e).into()));
+					return Next::end();
+				},
+			};
+			let file = self.file.as_mut().expect("File is there because on_response has created it.");
+			if let Err(e) = file.write_all(&buf[..read]) {
+				self.result = Some(Err(Error::IoError(e).into()));
+				return Next::end();
+			}
+			*written += read as u64;
+		}
+	}
 }
 
 impl hyper::client::Handler<HttpStream> for Fetch {
@@ -108,6 +170,16 @@ impl hyper::client::Handler<HttpStream> for Fetch {
 			return self.mark_aborted();
 		}
         req.headers_mut().set(Connection::close());
+		for &(ref name, ref value) in &self.headers {
+			req.headers_mut().set_raw(name.clone(), vec![value.clone().into_bytes()]);
+		}
+		if self.resume {
+			self.offset = resume_offset(&self.path);
+			if self.offset > 0 {
+				trace!(target: "dapps", "Resuming download from byte {}", self.offset);
+				req.headers_mut().set(Range::Bytes(vec![ByteRangeSpec::AllFrom(self.offset)]));
+			}
+		}
         read()
     }
 
@@ -122,20 +194,15 @@ impl hyper::client::Handler<HttpStream> for Fetch {
 		if self.is_aborted() {
 			return self.mark_aborted();
 		}
-		if *res.status() != StatusCode::Ok {
-			self.result = Some(Err(Error::UnexpectedStatus(*res.status()).into()));
-			return Next::end();
-		}
-
-		// Open file to write
-		match fs::File::create(&self.path) {
+		// Servers not supporting ranges respond with full content
+		match open_for_response(&self.path, *res.status(), range_start(&res), self.offset) {
 			Ok(file) => {
 				self.file = Some(file);
 				self.result = Some(Ok(self.path.clone()));
 				read()
 			},
 			Err(err) => {
-				self.result = Some(Err(Error::IoError(err).into()));
+				self.result = Some(Err(err.into()));
 				Next::end()
 			},
 		}
@@ -145,17 +212,10 @@ impl hyper::client::Handler<HttpStream> for Fetch {
 		if self.is_aborted() {
 			return self.mark_aborted();
 		}
-        match io::copy(decoder, self.file.as_mut().expect("File is there because on_response has created it.")) {
-            Ok(0) => Next::end(),
-            Ok(_) => read(),
-            Err(e) => match e.kind() {
-                io::ErrorKind::WouldBlock => Next::read(),
-                _ => {
-					self.result = Some(Err(Error::IoError(e).into()));
-                    Next::end()
-                }
-            }
-        }
+		let mut written = 0;
+		let next = self.write_available(decoder, &mut written);
+		self.bandwidth.record(written);
+		next
     }
 
     fn on_error(&mut self, err: hyper::Error) -> Next {
@@ -167,3 +227,83 @@ impl hyper::client::Handler<HttpStream> for Fetch {
 fn read() -> Next {
     Next::read().timeout(Duration::from_secs(15))
 }
+
+#[cfg(test)]
+mod tests {
+	use std::{env, fs};
+	use std::io::{Read, Write};
+	use hyper::status::StatusCode;
+	use random_filename;
+	use super::{resume_offset, open_for_response, Error};
+
+	fn read_file(path: &::std::path::Path) -> String {
+		let mut content = String::new();
+		fs::File::open(path).unwrap().read_to_string(&mut content).unwrap();
+		content
+	}
+
+	#[test]
+	fn should_resume_partial_download() {
+		// given
+		let mut path = env::temp_dir();
+		path.push(random_filename());
+		fs::File::create(&path).unwrap().write_all(b"Hello ").unwrap();
+		let offset = resume_offset(&path);
+
+		// when
+		open_for_response(&path, StatusCode::PartialContent, Some(offset), offset).unwrap().write_all(b"World!").unwrap();
+
+		// then
+		assert_eq!(offset, 6);
+		assert_eq!(read_file(&path), "Hello World!");
+		fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn should_restart_when_range_is_not_supported() {
+		// given
+		let mut path = env::temp_dir();
+		path.push(random_filename());
+		fs::File::create(&path).unwrap().write_all(b"Hello ").unwrap();
+		let offset = resume_offset(&path);
+
+		// when
+		open_for_response(&path, StatusCode::Ok, None, offset).unwrap().write_all(b"Hello World!").unwrap();
+
+		// then
+		assert_eq!(read_file(&path), "Hello World!");
+		fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn should_reject_unexpected_status() {
+		let mut path = env::temp_dir();
+		path.push(random_filename());
+
+		match open_for_response(&path, StatusCode::NotFound, None, 0) {
+			Err(Error::UnexpectedStatus(StatusCode::NotFound)) => {},
+			other => panic!("Expected unexpected status error, got {:?}", other.map(|_| ())),
+		}
+		assert_eq!(resume_offset(&path), 0);
+	}
+
+	#[test]
+	fn should_restart_when_range_does_not_match() {
+		// given
+		let mut path = env::temp_dir();
+		path.push(random_filename());
+		fs::File::create(&path).unwrap().write_all(b"Hello ").unwrap();
+		let offset = resume_offset(&path);
+
+		// when
+		let res = open_for_response(&path, StatusCode::PartialContent, Some(2), offset);
+
+		// then
+		match res {
+			Err(Error::UnexpectedRange(Some(2))) => {},
+			other => panic!("Expected unexpected range error, got {:?}", other.map(|_| ())),
+		}
+		assert_eq!(resume_offset(&path), 0);
+		fs::remove_file(&path).unwrap();
+	}
+}
@@ -0,0 +1,129 @@
+// Copyright 2015, 2016 Ethcore (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Hyper Client Handler to Fetch Content Metadata
+
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use hyper::status::StatusCode;
+use hyper::client::{Request, Response, DefaultTransport as HttpStream};
+use hyper::header::{Connection, ContentLength, ContentType};
+use hyper::{self, Encoder, Method, Next};
+
+use super::FetchError;
+use super::fetch_file::{Error, OnDone};
+
+/// Size and type of the remote content.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+	pub content_length: Option<u64>,
+	pub content_type: Option<String>,
+}
+
+pub type MetadataResult = Result<Metadata, FetchError>;
+
+pub struct Head {
+	abort: Arc<AtomicBool>,
+	result: Option<MetadataResult>,
+	sender: mpsc::Sender<MetadataResult>,
+	headers: Vec<(String, String)>,
+	on_done: Option<OnDone>,
+}
+
+impl Drop for Head {
+	fn drop(&mut self) {
+		let res = self.result.take().unwrap_or(Err(Error::NotStarted.into()));
+		// send result
+		let _ = self.sender.send(res);
+		if let Some(f) = self.on_done.take() {
+			f();
+		}
+	}
+}
+
+impl Head {
+	pub fn new(sender: mpsc::Sender<MetadataResult>, headers: Vec<(String, String)>, abort: Arc<AtomicBool>, on_done: OnDone) -> Self {
+		Head {
+			abort: abort,
+			result: None,
+			sender: sender,
+			headers: headers,
+			on_done: Some(on_done),
+		}
+	}
+
+	fn is_aborted(&self) -> bool {
+		self.abort.load(Ordering::SeqCst)
+	}
+
+	fn mark_aborted(&mut self) -> Next {
+		self.result = Some(Err(Error::Aborted.into()));
+		Next::end()
+	}
+}
+
+impl hyper::client::Handler<HttpStream> for Head {
+	fn on_request(&mut self, req: &mut Request) -> Next {
+		if self.is_aborted() {
+			return self.mark_aborted();
+		}
+		req.set_method(Method::Head);
+		req.headers_mut().set(Connection::close());
+		for &(ref name, ref value) in &self.headers {
+			req.headers_mut().set_raw(name.clone(), vec![value.clone().into_bytes()]);
+		}
+		read()
+	}
+
+	fn on_request_writable(&mut self, _encoder: &mut Encoder<HttpStream>) -> Next {
+		if self.is_aborted() {
+			return self.mark_aborted();
+		}
+		read()
+	}
+
+	fn on_response(&mut self, res: Response) -> Next {
+		if self.is_aborted() {
+			return self.mark_aborted();
+		}
+		if *res.status() != StatusCode::Ok {
+			self.result = Some(Err(Error::UnexpectedStatus(*res.status()).into()));
+			return Next::end();
+		}
+
+		self.result = Some(Ok(Metadata {
+			content_length: res.headers().get::<ContentLength>().map(|len| len.0),
+			content_type: res.headers().get::<ContentType>().map(|mime| format!("{}", mime.0)),
+		}));
+		// There is no body in response to HEAD
+		Next::end()
+	}
+
+	fn on_response_readable(&mut self, _decoder: &mut hyper::Decoder<HttpStream>) -> Next {
+		Next::end()
+	}
+
+	fn on_error(&mut self, err: hyper::Error) -> Next {
+		self.result = Some(Err(Error::HyperError(err).into()));
+		Next::remove()
+	}
+}
+
+fn read() -> Next {
+	Next::read().timeout(Duration::from_secs(15))
+}
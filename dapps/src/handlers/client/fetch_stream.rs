@@ -0,0 +1,161 @@
+// Copyright 2015, 2016 Ethcore (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Hyper Client Handler to Stream Content without storing it
+
+use std::io::{self, Read};
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use hyper::status::StatusCode;
+use hyper::client::{Request, Response, DefaultTransport as HttpStream};
+use hyper::header::{Connection, ContentLength, ContentType};
+use hyper::{self, Decoder, Encoder, Next};
+
+use super::FetchError;
+use super::fetch_file::{Error, OnDone};
+use super::fetch_head::Metadata;
+
+const BUFFER_SIZE: usize = 8192;
+
+/// Parts of the streamed response, in order of arrival.
+#[derive(Debug)]
+pub enum StreamChunk {
+	/// Response was accepted, the body follows.
+	Metadata(Metadata),
+	/// Next part of the body.
+	Data(Vec<u8>),
+	/// Whole body was received.
+	Done,
+	/// Fetching failed, possibly in the middle of the body.
+	Failed(FetchError),
+}
+
+pub struct Stream {
+	abort: Arc<AtomicBool>,
+	result: Option<Result<(), FetchError>>,
+	sender: mpsc::SyncSender<StreamChunk>,
+	headers: Vec<(String, String)>,
+	on_chunk: OnDone,
+}
+
+impl Drop for Stream {
+	fn drop(&mut self) {
+		let chunk = match self.result.take().unwrap_or(Err(Error::NotStarted.into())) {
+			Ok(()) => StreamChunk::Done,
+			Err(e) => StreamChunk::Failed(e),
+		};
+		self.send(chunk);
+	}
+}
+
+impl Stream {
+	/// Creates a handler sending the response body through `sender`.
+	/// `on_chunk` is called after every chunk sent. Sending blocks while `sender` is full,
+	/// which pauses reading the response until the receiver catches up.
+	pub fn new(sender: mpsc::SyncSender<StreamChunk>, headers: Vec<(String, String)>, abort: Arc<AtomicBool>, on_chunk: OnDone) -> Self {
+		Stream {
+			abort: abort,
+			result: None,
+			sender: sender,
+			headers: headers,
+			on_chunk: on_chunk,
+		}
+	}
+
+	fn send(&self, chunk: StreamChunk) {
+		// Ignoring send errors, no one is listening anymore
+		let _ = self.sender.send(chunk);
+		(self.on_chunk)();
+	}
+
+	fn is_aborted(&self) -> bool {
+		self.abort.load(Ordering::SeqCst)
+	}
+
+	fn mark_aborted(&mut self) -> Next {
+		self.result = Some(Err(Error::Aborted.into()));
+		Next::end()
+	}
+}
+
+impl hyper::client::Handler<HttpStream> for Stream {
+	fn on_request(&mut self, req: &mut Request) -> Next {
+		if self.is_aborted() {
+			return self.mark_aborted();
+		}
+		req.headers_mut().set(Connection::close());
+		for &(ref name, ref value) in &self.headers {
+			req.headers_mut().set_raw(name.clone(), vec![value.clone().into_bytes()]);
+		}
+		read()
+	}
+
+	fn on_request_writable(&mut self, _encoder: &mut Encoder<HttpStream>) -> Next {
+		if self.is_aborted() {
+			return self.mark_aborted();
+		}
+		read()
+	}
+
+	fn on_response(&mut self, res: Response) -> Next {
+		if self.is_aborted() {
+			return self.mark_aborted();
+		}
+		if *res.status() != StatusCode::Ok {
+			self.result = Some(Err(Error::UnexpectedStatus(*res.status()).into()));
+			return Next::end();
+		}
+
+		self.send(StreamChunk::Metadata(Metadata {
+			content_length: res.headers().get::<ContentLength>().map(|len| len.0),
+			content_type: res.headers().get::<ContentType>().map(|mime| format!("{}", mime.0)),
+		}));
+		self.result = Some(Ok(()));
+		read()
+	}
+
+	fn on_response_readable(&mut self, decoder: &mut Decoder<HttpStream>) -> Next {
+		if self.is_aborted() {
+			return self.mark_aborted();
+		}
+		let mut buffer = [0u8; BUFFER_SIZE];
+		match decoder.read(&mut buffer) {
+			Ok(0) => Next::end(),
+			Ok(read_bytes) => {
+				self.send(StreamChunk::Data(buffer[..read_bytes].to_vec()));
+				read()
+			},
+			Err(e) => match e.kind() {
+				io::ErrorKind::WouldBlock => Next::read(),
+				_ => {
+					self.result = Some(Err(Error::IoError(e).into()));
+					Next::end()
+				}
+			}
+		}
+	}
+
+	fn on_error(&mut self, err: hyper::Error) -> Next {
+		self.result = Some(Err(Error::HyperError(err).into()));
+		Next::remove()
+	}
+}
+
+fn read() -> Next {
+	Next::read().timeout(Duration::from_secs(15))
+}
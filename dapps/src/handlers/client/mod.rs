@@ -17,23 +17,33 @@
 //! Hyper Client Handlers
 
 pub mod fetch_file;
+pub mod fetch_head;
+pub mod fetch_stream;
 
-use std::env;
+use std::ascii::AsciiExt;
+use std::fs;
 use std::sync::{mpsc, Arc};
 use std::sync::atomic::AtomicBool;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use hyper;
 use https_fetch as https;
 
 use random_filename;
+use handlers::BandwidthLimit;
 use self::fetch_file::{Fetch, Error as HttpFetchError};
+use self::fetch_head::{Head, MetadataResult};
+use self::fetch_stream::{Stream, StreamChunk};
+
+/// Number of streamed chunks waiting to be received, before reading more of the content pauses.
+const STREAM_BUFFERED_CHUNKS: usize = 16;
 
 pub type FetchResult = Result<PathBuf, FetchError>;
 
 #[derive(Debug)]
 pub enum FetchError {
 	InvalidUrl,
+	InvalidHeader(String),
 	Http(HttpFetchError),
 	Https(https::FetchError),
 	Other(String),
@@ -45,8 +55,27 @@ impl From<HttpFetchError> for FetchError {
 	}
 }
 
+/// Checks that extra request headers have valid names and values.
+pub fn validate_headers(headers: &[(String, String)]) -> Result<(), FetchError> {
+	fn is_token_char(c: char) -> bool {
+		c.is_ascii() && (c.is_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+	}
+
+	for &(ref name, ref value) in headers {
+		if name.is_empty() || !name.chars().all(is_token_char) {
+			return Err(FetchError::InvalidHeader(format!("Invalid header name: {:?}", name)));
+		}
+		if value.contains('\r') || value.contains('\n') {
+			return Err(FetchError::InvalidHeader(format!("Invalid value of header {}: {:?}", name, value)));
+		}
+	}
+	Ok(())
+}
+
 pub struct Client {
 	http_client: hyper::Client<Fetch>,
+	head_client: hyper::Client<Head>,
+	stream_client: hyper::Client<Stream>,
 	https_client: https::Client,
 }
 
@@ -54,16 +83,62 @@ impl Client {
 	pub fn new() -> Self {
 		Client {
 			http_client: hyper::Client::new().expect("Unable to initialize http client."),
+			head_client: hyper::Client::new().expect("Unable to initialize http client."),
+			stream_client: hyper::Client::new().expect("Unable to initialize http client."),
 			https_client: https::Client::new().expect("Unable to initialize https client."),
 		}
 	}
 
 	pub fn close(self) {
 		self.http_client.close();
+		self.head_client.close();
+		self.stream_client.close();
 		self.https_client.close();
 	}
 
-	pub fn request(&mut self, url: &str, abort: Arc<AtomicBool>, on_done: Box<Fn() + Send>) -> Result<mpsc::Receiver<FetchResult>, FetchError> {
+	/// Fetches only the size and type of the content, without downloading it.
+	/// Only supported over plain http.
+	pub fn head(&mut self, url: &str, headers: &[(String, String)], abort: Arc<AtomicBool>, on_done: Box<Fn() + Send>) -> Result<mpsc::Receiver<MetadataResult>, FetchError> {
+		if url.starts_with("https://") {
+			return Err(FetchError::Other("HEAD requests are not supported over https".into()));
+		}
+		let url = try!(url.parse().map_err(|_| FetchError::InvalidUrl));
+		trace!(target: "dapps", "Fetching metadata from: {:?}", url);
+
+		let (tx, rx) = mpsc::channel();
+		let res = self.head_client.request(url, Head::new(tx, headers.to_vec(), abort, on_done));
+
+		match res {
+			Ok(_) => Ok(rx),
+			Err(e) => Err(FetchError::Other(format!("{:?}", e))),
+		}
+	}
+
+	/// Streams the content through the returned receiver as it arrives, without storing it.
+	/// `on_chunk` is called after each received chunk. Reading pauses while `STREAM_BUFFERED_CHUNKS` chunks
+	/// are waiting, so a slow receiver holds back the download instead of buffering it in memory.
+	/// Only supported over plain http, the https client doesn't expose the response status nor headers.
+	pub fn stream(&mut self, url: &str, headers: &[(String, String)], abort: Arc<AtomicBool>, on_chunk: Box<Fn() + Send>) -> Result<mpsc::Receiver<StreamChunk>, FetchError> {
+		if url.starts_with("https://") {
+			return Err(FetchError::Other("Streaming is not supported over https".into()));
+		}
+		let url = try!(url.parse().map_err(|_| FetchError::InvalidUrl));
+		trace!(target: "dapps", "Streaming from: {:?}", url);
+
+		let (tx, rx) = mpsc::sync_channel(STREAM_BUFFERED_CHUNKS);
+		let res = self.stream_client.request(url, Stream::new(tx, headers.to_vec(), abort, on_chunk));
+
+		match res {
+			Ok(_) => Ok(rx),
+			Err(e) => Err(FetchError::Other(format!("{:?}", e))),
+		}
+	}
+
+	/// Downloads the content to `path`. With `resume` the partially downloaded file is kept
+	/// after a failure and subsequent request fetches only the remaining bytes (http only).
+	/// Downloaded bytes are counted in `bandwidth`, over http as they arrive, over https once the download ends.
+	#[cfg_attr(feature="dev", allow(too_many_arguments))]
+	pub fn request(&mut self, url: &str, headers: &[(String, String)], path: PathBuf, resume: bool, bandwidth: Arc<BandwidthLimit>, abort: Arc<AtomicBool>, on_done: Box<Fn() + Send>) -> Result<mpsc::Receiver<FetchResult>, FetchError> {
 		let is_https = url.starts_with("https://");
 		let url = try!(url.parse().map_err(|_| FetchError::InvalidUrl));
 		trace!(target: "dapps", "Fetching from: {:?}", url);
@@ -71,10 +146,10 @@ impl Client {
 			let url = try!(Self::convert_url(url));
 
 			let (tx, rx) = mpsc::channel();
-			let temp_path = Self::temp_path();
-			let res = self.https_client.fetch_to_file(url, temp_path.clone(), abort, move |result| {
+			let res = self.https_client.fetch_to_file(url, headers.to_vec(), path.clone(), abort, move |result| {
+				bandwidth.record(fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0));
 				let res = tx.send(
-					result.map(|_| temp_path).map_err(FetchError::Https)
+					result.map(|_| path).map_err(FetchError::Https)
 				);
 				if let Err(_) = res {
 					warn!("Fetch finished, but no one was listening");
@@ -88,7 +163,7 @@ impl Client {
 			}
 		} else {
 			let (tx, rx) = mpsc::channel();
-			let res = self.http_client.request(url, Fetch::new(tx, abort, on_done));
+			let res = self.http_client.request(url, Fetch::new(tx, path, resume, headers.to_vec(), bandwidth, abort, on_done));
 
 			match res {
 				Ok(_) => Ok(rx),
@@ -103,11 +178,40 @@ impl Client {
 		https::Url::new(&host, port, url.path()).map_err(|_| FetchError::InvalidUrl)
 	}
 
-	fn temp_path() -> PathBuf {
-		let mut dir = env::temp_dir();
-		dir.push(random_filename());
-		dir
+	/// Unique path for downloaded content inside `dir`.
+	pub fn temp_path(dir: &Path) -> PathBuf {
+		dir.join(random_filename())
 	}
 }
 
+#[cfg(test)]
+mod tests {
+	use super::{validate_headers, FetchError};
+
+	#[test]
+	fn should_accept_valid_headers() {
+		let headers = vec![
+			("Authorization".to_owned(), "Basic dXNlcjpwYXNz".to_owned()),
+			("User-Agent".to_owned(), "Parity/1.4".to_owned()),
+		];
+
+		assert!(validate_headers(&headers).is_ok());
+	}
+
+	#[test]
+	fn should_reject_invalid_header_names() {
+		for name in vec!["", "User Agent", "X-Header:", "Zażółć"] {
+			match validate_headers(&[(name.to_owned(), "value".to_owned())]) {
+				Err(FetchError::InvalidHeader(_)) => {},
+				other => panic!("Expected invalid header error for {:?}, got {:?}", name, other),
+			}
+		}
+	}
+
+	#[test]
+	fn should_reject_header_values_with_newlines() {
+		let headers = vec![("X-Header".to_owned(), "value\r\nHost: evil.com".to_owned())];
 
+		assert!(validate_headers(&headers).is_err());
+	}
+}
@@ -25,7 +25,7 @@ use util::version;
 
 pub struct ContentHandler {
 	code: StatusCode,
-	content: String,
+	content: Vec<u8>,
 	mimetype: String,
 	write_pos: usize,
 }
@@ -34,7 +34,7 @@ impl ContentHandler {
 	pub fn ok(content: String, mimetype: String) -> Self {
 		ContentHandler {
 			code: StatusCode::Ok,
-			content: content,
+			content: content.into_bytes(),
 			mimetype: mimetype,
 			write_pos: 0
 		}
@@ -43,7 +43,7 @@ impl ContentHandler {
 	pub fn not_found(content: String, mimetype: String) -> Self {
 		ContentHandler {
 			code: StatusCode::NotFound,
-			content: content,
+			content: content.into_bytes(),
 			mimetype: mimetype,
 			write_pos: 0
 		}
@@ -75,7 +75,16 @@ impl ContentHandler {
 		))
 	}
 
+	#[cfg(test)]
+	pub fn status(&self) -> StatusCode {
+		self.code
+	}
+
 	pub fn new(code: StatusCode, content: String, mimetype: String) -> Self {
+		Self::binary(code, content.into_bytes(), mimetype)
+	}
+
+	pub fn binary(code: StatusCode, content: Vec<u8>, mimetype: String) -> Self {
 		ContentHandler {
 			code: code,
 			content: content,
@@ -101,7 +110,7 @@ impl server::Handler<HttpStream> for ContentHandler {
 	}
 
 	fn on_response_writable(&mut self, encoder: &mut Encoder<HttpStream>) -> Next {
-		let bytes = self.content.as_bytes();
+		let bytes = &self.content;
 		if self.write_pos == bytes.len() {
 			return Next::end();
 		}
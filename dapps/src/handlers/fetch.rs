@@ -16,30 +16,233 @@
 
 //! Hyper Server Handler that fetches a file during a request (proxy).
 
-use std::{fs, fmt};
-use std::path::PathBuf;
+use std::{fs, fmt, io};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
 use std::sync::{mpsc, Arc};
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::time::{Instant, Duration};
 
-use hyper::{header, server, Decoder, Encoder, Next, Method, Control};
+use hyper::{self, header, server, Decoder, Encoder, Next, Method, Control};
 use hyper::net::HttpStream;
 use hyper::status::StatusCode;
+use util::Mutex;
 
 use handlers::ContentHandler;
-use handlers::client::{Client, FetchResult};
+use handlers::client::{self, Client, FetchResult, FetchError};
+use handlers::client::fetch_head::{Metadata, MetadataResult};
+use handlers::client::fetch_stream::StreamChunk;
 use apps::redirection_address;
+use page::{LocalPageEndpoint, LocalSingleFile, PageHandler};
 
 const FETCH_TIMEOUT: u64 = 30;
+const FETCH_RETRIES: usize = 3;
+
+/// Exponential backoff between consecutive fetch attempts.
+#[derive(Debug)]
+pub struct Backoff {
+	base: Duration,
+	next: Duration,
+}
+
+impl Backoff {
+	/// Creates new backoff starting at given delay.
+	pub fn new(base: Duration) -> Self {
+		Backoff {
+			base: base,
+			next: base,
+		}
+	}
+
+	/// Returns the delay before next attempt and doubles it for the one after.
+	pub fn next_delay(&mut self) -> Duration {
+		let delay = self.next;
+		self.next = delay * 2;
+		delay
+	}
+
+	/// Starts over from the base delay.
+	pub fn reset(&mut self) {
+		self.next = self.base;
+	}
+}
+
+/// Cap on the number of fetches running at once, shared by all the handlers.
+#[derive(Debug, Clone)]
+pub struct FetchLimit {
+	in_flight: Arc<AtomicUsize>,
+	max: usize,
+}
+
+impl FetchLimit {
+	/// Creates new limit allowing `max` concurrent fetches.
+	pub fn new(max: usize) -> Self {
+		FetchLimit {
+			in_flight: Arc::new(AtomicUsize::new(0)),
+			max: max,
+		}
+	}
+
+	/// Reserves a slot for a new fetch, `None` if the limit is reached already.
+	pub fn acquire(&self) -> Option<FetchSlot> {
+		let mut current = self.in_flight.load(Ordering::SeqCst);
+		loop {
+			if current >= self.max {
+				return None;
+			}
+			let previous = self.in_flight.compare_and_swap(current, current + 1, Ordering::SeqCst);
+			if previous == current {
+				return Some(FetchSlot { in_flight: self.in_flight.clone() });
+			}
+			current = previous;
+		}
+	}
+
+	/// Returns the number of fetches currently running.
+	pub fn in_flight(&self) -> usize {
+		self.in_flight.load(Ordering::SeqCst)
+	}
+}
+
+/// Slot of a running fetch, released when dropped.
+#[derive(Debug)]
+pub struct FetchSlot {
+	in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for FetchSlot {
+	fn drop(&mut self) {
+		self.in_flight.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+/// Cap on the bytes fetched by all the handlers together within a rolling window.
+/// The cap is checked only when a handler is created, so a download already running is never cut off:
+/// its bytes are counted as they arrive and hold back the fetches started after them.
+#[derive(Debug)]
+pub struct BandwidthLimit {
+	cap: u64,
+	window: Duration,
+	/// Bytes fetched with the time they were fetched, oldest first.
+	/// Bytes fetched within a second of the newest entry are added to it, so long downloads don't grow the list.
+	fetched: Mutex<VecDeque<(Instant, u64)>>,
+}
+
+impl BandwidthLimit {
+	/// Creates new limit allowing `cap` bytes to be fetched within every `window`.
+	pub fn new(cap: u64, window: Duration) -> Self {
+		BandwidthLimit {
+			cap: cap,
+			window: window,
+			fetched: Mutex::new(VecDeque::new()),
+		}
+	}
+
+	/// Counts bytes fetched just now.
+	pub fn record(&self, bytes: u64) {
+		self.record_at(Instant::now(), bytes)
+	}
+
+	/// Returns true if the bytes fetched within the last window reached the cap.
+	pub fn is_exceeded(&self) -> bool {
+		self.is_exceeded_at(Instant::now())
+	}
+
+	fn record_at(&self, now: Instant, bytes: u64) {
+		let mut fetched = self.fetched.lock();
+		if let Some(&mut (at, ref mut total)) = fetched.back_mut() {
+			if now < at + Duration::from_secs(1) {
+				*total += bytes;
+				return;
+			}
+		}
+		fetched.push_back((now, bytes));
+	}
+
+	fn is_exceeded_at(&self, now: Instant) -> bool {
+		let mut fetched = self.fetched.lock();
+		while fetched.front().map_or(false, |&(at, _)| at + self.window <= now) {
+			fetched.pop_front();
+		}
+		fetched.iter().fold(0, |total, &(_, bytes)| total + bytes) >= self.cap
+	}
+}
+
+/// How the content is handed to the client once it's fetched and installed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FetchMode {
+	/// Redirect the client to the installed content.
+	Redirect,
+	/// Respond with the installed content directly.
+	Inline,
+	/// Forward the content to the client as it arrives, without storing, validating or installing it.
+	/// Only suitable for trusted content sources served over plain http.
+	Stream,
+}
+
+/// Settings of content fetches, shared by all the handlers of a fetcher.
+#[derive(Debug, Clone)]
+pub struct FetchOptions {
+	/// How the content is handed to the client.
+	pub mode: FetchMode,
+	/// Delay before the first retry, doubled for every next one.
+	pub retry_delay: Duration,
+	/// Extra headers attached to the outgoing requests.
+	pub headers: Vec<(String, String)>,
+	/// Directory the content is downloaded to.
+	pub temp_dir: PathBuf,
+	/// Cap on the number of fetches running at once.
+	pub limit: FetchLimit,
+	/// Cap on the bytes fetched within a window.
+	pub bandwidth: Arc<BandwidthLimit>,
+	/// Overrides the address fetched content is redirected to,
+	/// for deployments where the externally visible address is different (e.g. behind a reverse proxy).
+	pub redirect_base: Option<String>,
+}
+
+/// Outcome of forwarding the streamed content to the client.
+#[derive(Debug, PartialEq)]
+enum Forward {
+	/// Client is not ready to accept more.
+	Blocked,
+	/// Nothing more has arrived yet.
+	Waiting,
+	/// Content is forwarded or the stream has failed.
+	Finished,
+}
+
+enum DoneResponse {
+	Redirect(String),
+	Inline(PageHandler<LocalSingleFile>),
+}
 
 enum FetchState<T: fmt::Debug> {
 	NotStarted(String),
 	Error(ContentHandler),
 	InProgress {
+		url: String,
 		deadline: Instant,
 		receiver: mpsc::Receiver<FetchResult>,
 	},
-	Done((String, T)),
+	Retrying {
+		url: String,
+		deadline: Instant,
+		retry_at: Instant,
+	},
+	FetchingMetadata {
+		deadline: Instant,
+		receiver: mpsc::Receiver<MetadataResult>,
+	},
+	Metadata(Metadata),
+	Streaming {
+		deadline: Instant,
+		receiver: mpsc::Receiver<StreamChunk>,
+		response_started: Arc<AtomicBool>,
+		metadata: Option<Metadata>,
+		pending: Vec<u8>,
+	},
+	Streamed,
+	Done((String, T), DoneResponse),
 }
 
 pub trait ContentValidator {
@@ -48,44 +251,125 @@ pub trait ContentValidator {
 
 	fn validate_and_install(&self, app: PathBuf) -> Result<(String, Self::Result), Self::Error>;
 	fn done(&self, Option<&Self::Result>);
+
+	/// Returns mime type and path of installed content if it can be served inline.
+	fn inline_content(&self, _result: &Self::Result) -> Option<(String, PathBuf)> {
+		None
+	}
 }
 
 pub struct ContentFetcherHandler<H: ContentValidator> {
 	abort: Arc<AtomicBool>,
-	control: Option<Control>,
+	control: Control,
 	status: FetchState<H::Result>,
 	client: Option<Client>,
 	using_dapps_domains: bool,
+	redirect_base: Option<String>,
+	mode: FetchMode,
+	backoff: Backoff,
+	retries: usize,
+	headers: Vec<(String, String)>,
+	temp_path: Option<PathBuf>,
 	installer: H,
+	bandwidth: Arc<BandwidthLimit>,
+	_slot: Option<FetchSlot>,
 }
 
 impl<H: ContentValidator> Drop for ContentFetcherHandler<H> {
 	fn drop(&mut self) {
 		let result = match self.status {
-			FetchState::Done((_, ref result)) => Some(result),
+			FetchState::Done((_, ref result), _) => Some(result),
 			_ => None,
 		};
 		self.installer.done(result);
+		// Streaming may be interrupted before the client is closed.
+		// Stop receiving first, the client may be blocked on sending more content otherwise.
+		if let FetchState::Streaming { .. } = self.status {
+			self.status = FetchState::Streamed;
+		}
+		if let Some(client) = self.client.take() {
+			client.close();
+		}
+		// Remove partially downloaded content kept for resuming
+		if let Some(ref path) = self.temp_path {
+			Self::remove_download(path);
+		}
 	}
 }
 
 impl<H: ContentValidator> ContentFetcherHandler<H> {
 
+	/// The handler refuses to fetch if the bandwidth is exceeded already, it's not checked again afterwards.
 	pub fn new(
 		url: String,
 		abort: Arc<AtomicBool>,
 		control: Control,
 		using_dapps_domains: bool,
-		handler: H) -> Self {
+		options: FetchOptions,
+		handler: H) -> Result<Self, FetchError> {
 
-		let client = Client::new();
-		ContentFetcherHandler {
+		try!(client::validate_headers(&options.headers));
+		let over_bandwidth = options.bandwidth.is_exceeded();
+		let slot = match over_bandwidth {
+			true => None,
+			false => options.limit.acquire(),
+		};
+		if slot.is_none() {
+			debug!(target: "dapps", "Rejecting fetch of {:?}, {} fetches in progress.", url, options.limit.in_flight());
+		}
+		// Rejected handlers respond with an error straight away, without opening any connection
+		let client = slot.as_ref().map(|_| Client::new());
+		Ok(ContentFetcherHandler {
 			abort: abort,
-			control: Some(control),
-			client: Some(client),
-			status: FetchState::NotStarted(url),
+			control: control,
+			client: client,
+			status: Self::initial_status(url, slot.is_some(), over_bandwidth),
 			using_dapps_domains: using_dapps_domains,
+			redirect_base: options.redirect_base,
+			mode: options.mode,
+			backoff: Backoff::new(options.retry_delay),
+			retries: 0,
+			headers: options.headers,
+			temp_path: Self::download_path(options.mode, &options.temp_dir),
 			installer: handler,
+			bandwidth: options.bandwidth,
+			_slot: slot,
+		})
+	}
+
+	fn initial_status(url: String, has_slot: bool, over_bandwidth: bool) -> FetchState<H::Result> {
+		if over_bandwidth {
+			return FetchState::Error(ContentHandler::error(
+				StatusCode::ServiceUnavailable,
+				"Bandwidth Limit Reached",
+				"Too much content was downloaded recently. Please try again later.",
+				None,
+			));
+		}
+		match has_slot {
+			true => FetchState::NotStarted(url),
+			false => FetchState::Error(ContentHandler::error_with_refresh(
+				StatusCode::ServiceUnavailable,
+				"Too Many Downloads",
+				"Too many downloads are in progress. Please wait...",
+				None,
+			)),
+		}
+	}
+
+	/// Location of the downloaded content, streamed content is never stored.
+	fn download_path(mode: FetchMode, temp_dir: &Path) -> Option<PathBuf> {
+		match mode {
+			FetchMode::Stream => None,
+			_ => Some(Client::temp_path(temp_dir)),
+		}
+	}
+
+	fn remove_download(path: &Path) {
+		if let Err(e) = fs::remove_file(path) {
+			if e.kind() != io::ErrorKind::NotFound {
+				warn!(target: "dapps", "Unable to remove downloaded content {:?}: {:?}", path, e);
+			}
 		}
 	}
 
@@ -95,9 +379,125 @@ impl<H: ContentValidator> ContentFetcherHandler<H> {
 			.close();
 	}
 
+	/// Time left until the retry is due, never past the fetch deadline.
+	/// Returns `None` when it's time to retry.
+	fn retry_wait(retry_at: Instant, deadline: Instant, now: Instant) -> Option<Duration> {
+		let wake_at = if retry_at < deadline { retry_at } else { deadline };
+		match wake_at > now {
+			true => Some(wake_at - now),
+			false => None,
+		}
+	}
+
+	/// Starts the next attempt if it's due, otherwise waits for the read timeout.
+	fn poll_retry(&mut self) -> Next {
+		let (status, next) = match self.status {
+			FetchState::Retrying { ref url, ref deadline, ref retry_at } => match Self::retry_wait(*retry_at, *deadline, Instant::now()) {
+				Some(delay) => (None, Next::read().timeout(delay)),
+				None => {
+					trace!(target: "dapps", "Retrying to fetch content from: {:?}", url);
+					let client = self.client.as_mut().expect("Client is closed only when going into write.");
+					let path = self.temp_path.as_ref().expect("Content is stored unless streamed; qed");
+					match Self::fetch_content(client, url, &self.headers, path, self.bandwidth.clone(), self.abort.clone(), self.control.clone()) {
+						Ok(receiver) => (Some(FetchState::InProgress {
+							url: url.clone(),
+							deadline: *deadline,
+							receiver: receiver,
+						}), Next::wait()),
+						Err(e) => (Some(Self::start_error(e)), Next::write()),
+					}
+				},
+			},
+			_ => (None, Next::write()),
+		};
+
+		if let Some(status) = status {
+			self.status = status;
+		}
+
+		next
+	}
+
+	fn done_response(mode: FetchMode, installer: &H, using_dapps_domains: bool, redirect_base: Option<&str>, id: &str, result: &H::Result) -> DoneResponse {
+		if mode == FetchMode::Inline {
+			let handler = installer.inline_content(result)
+				.and_then(|(mime, path)| LocalPageEndpoint::file_handler(path, mime));
+			if let Some(handler) = handler {
+				return DoneResponse::Inline(handler);
+			}
+			warn!(target: "dapps", "Content {} cannot be served inline. Redirecting instead.", id);
+		}
+		let address = match redirect_base {
+			Some(base) => format!("{}/{}/", base.trim_right_matches('/'), id),
+			None => redirection_address(using_dapps_domains, id),
+		};
+		DoneResponse::Redirect(address)
+	}
+
+	fn set_metadata_headers(headers: &mut header::Headers, metadata: &Metadata) {
+		if let Some(len) = metadata.content_length {
+			headers.set(header::ContentLength(len));
+		}
+		if let Some(mime) = metadata.content_type.as_ref().and_then(|mime| mime.parse().ok()) {
+			headers.set(header::ContentType(mime));
+		}
+	}
 
-	fn fetch_content(client: &mut Client, url: &str, abort: Arc<AtomicBool>, control: Control) -> Result<mpsc::Receiver<FetchResult>, String> {
-		client.request(url, abort, Box::new(move || {
+	fn start_error(e: String) -> FetchState<H::Result> {
+		FetchState::Error(ContentHandler::error(
+			StatusCode::BadGateway,
+			"Unable To Start Dapp Download",
+			"Could not initialize download of the dapp. It might be a problem with the remote server.",
+			Some(&e),
+		))
+	}
+
+	fn stream_content(client: &mut Client, url: &str, headers: &[(String, String)], abort: Arc<AtomicBool>, control: Control, response_started: Arc<AtomicBool>) -> Result<mpsc::Receiver<StreamChunk>, String> {
+		client.stream(url, headers, abort, Box::new(move || {
+			// The first chunk completes the request, the rest continue the response
+			let next = match response_started.load(Ordering::SeqCst) {
+				true => Next::write(),
+				false => Next::read(),
+			};
+			// Ignoring control errors
+			let _ = control.ready(next);
+		})).map_err(|e| format!("{:?}", e))
+	}
+
+	/// Writes the streamed content until the writer blocks or nothing more has arrived.
+	fn forward_stream<W: io::Write>(receiver: &mpsc::Receiver<StreamChunk>, pending: &mut Vec<u8>, writer: &mut W, bandwidth: &BandwidthLimit) -> Forward {
+		loop {
+			if pending.is_empty() {
+				match receiver.try_recv() {
+					Ok(StreamChunk::Data(data)) => {
+						bandwidth.record(data.len() as u64);
+						*pending = data;
+					},
+					Ok(StreamChunk::Metadata(_)) => continue,
+					Ok(StreamChunk::Done) => return Forward::Finished,
+					Ok(StreamChunk::Failed(e)) => {
+						warn!(target: "dapps", "Streaming content failed: {:?}", e);
+						return Forward::Finished;
+					},
+					Err(mpsc::TryRecvError::Empty) => return Forward::Waiting,
+					Err(mpsc::TryRecvError::Disconnected) => return Forward::Finished,
+				}
+			}
+
+			match writer.write(pending) {
+				Ok(0) => return Forward::Blocked,
+				Ok(written) => { pending.drain(..written); },
+				Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Forward::Blocked,
+				Err(e) => {
+					warn!(target: "dapps", "Unable to stream content to the client: {:?}", e);
+					return Forward::Finished;
+				},
+			}
+		}
+	}
+
+	fn fetch_content(client: &mut Client, url: &str, headers: &[(String, String)], path: &PathBuf, bandwidth: Arc<BandwidthLimit>, abort: Arc<AtomicBool>, control: Control) -> Result<mpsc::Receiver<FetchResult>, String> {
+		client.request(url, headers, path.clone(), true, bandwidth, abort, Box::new(move || {
 			trace!(target: "dapps", "Fetching finished.");
 			// Ignoring control errors
 			let _ = control.ready(Next::read());
@@ -109,30 +509,67 @@ impl<H: ContentValidator> server::Handler<HttpStream> for ContentFetcherHandler<
 	fn on_request(&mut self, request: server::Request<HttpStream>) -> Next {
 		let status = if let FetchState::NotStarted(ref url) = self.status {
 			Some(match *request.method() {
+				// Start forwarding content
+				Method::Get if self.mode == FetchMode::Stream => {
+					trace!(target: "dapps", "Streaming content from: {:?}", url);
+					let client = self.client.as_mut().expect("on_request is called before client is closed.");
+					let response_started = Arc::new(AtomicBool::new(false));
+					let stream = Self::stream_content(client, url, &self.headers, self.abort.clone(), self.control.clone(), response_started.clone());
+					match stream {
+						Ok(receiver) => FetchState::Streaming {
+							deadline: Instant::now() + Duration::from_secs(FETCH_TIMEOUT),
+							receiver: receiver,
+							response_started: response_started,
+							metadata: None,
+							pending: Vec::new(),
+						},
+						Err(e) => Self::start_error(e),
+					}
+				},
 				// Start fetching content
 				Method::Get => {
 					trace!(target: "dapps", "Fetching content from: {:?}", url);
-					let control = self.control.take().expect("on_request is called only once, thus control is always Some");
 					let client = self.client.as_mut().expect("on_request is called before client is closed.");
-					let fetch = Self::fetch_content(client, url, self.abort.clone(), control);
+					let path = self.temp_path.as_ref().expect("Content is stored unless streamed; qed");
+					let fetch = Self::fetch_content(client, url, &self.headers, path, self.bandwidth.clone(), self.abort.clone(), self.control.clone());
 					match fetch {
 						Ok(receiver) => FetchState::InProgress {
+							url: url.clone(),
 							deadline: Instant::now() + Duration::from_secs(FETCH_TIMEOUT),
 							receiver: receiver,
 						},
-						Err(e) => FetchState::Error(ContentHandler::error(
-							StatusCode::BadGateway,
-							"Unable To Start Dapp Download",
-							"Could not initialize download of the dapp. It might be a problem with the remote server.",
-							Some(&format!("{}", e)),
-						)),
+						Err(e) => Self::start_error(e),
+					}
+				},
+				// https client cannot read response headers, so there is no metadata to return
+				Method::Head if url.starts_with("https://") => FetchState::Error(ContentHandler::error(
+					StatusCode::MethodNotAllowed,
+					"Method Not Allowed",
+					"<code>HEAD</code> requests are not supported for content served over https.",
+					None,
+				)),
+				// Fetch only size and type of the content
+				Method::Head => {
+					trace!(target: "dapps", "Fetching content metadata from: {:?}", url);
+					let client = self.client.as_mut().expect("on_request is called before client is closed.");
+					let control = self.control.clone();
+					let fetch = client.head(url, &self.headers, self.abort.clone(), Box::new(move || {
+						// Ignoring control errors
+						let _ = control.ready(Next::read());
+					}));
+					match fetch {
+						Ok(receiver) => FetchState::FetchingMetadata {
+							deadline: Instant::now() + Duration::from_secs(FETCH_TIMEOUT),
+							receiver: receiver,
+						},
+						Err(e) => Self::start_error(format!("{:?}", e)),
 					}
 				},
 				// or return error
 				_ => FetchState::Error(ContentHandler::error(
 					StatusCode::MethodNotAllowed,
 					"Method Not Allowed",
-					"Only <code>GET</code> requests are allowed.",
+					"Only <code>GET</code> and <code>HEAD</code> requests are allowed.",
 					None,
 				)),
 			})
@@ -146,6 +583,10 @@ impl<H: ContentValidator> server::Handler<HttpStream> for ContentFetcherHandler<
 	}
 
 	fn on_request_readable(&mut self, decoder: &mut Decoder<HttpStream>) -> Next {
+		if let FetchState::Retrying { .. } = self.status {
+			return self.poll_retry();
+		}
+
 		let (status, next) = match self.status {
 			// Request may time out
 			FetchState::InProgress { ref deadline, .. } if *deadline < Instant::now() => {
@@ -159,7 +600,7 @@ impl<H: ContentValidator> server::Handler<HttpStream> for ContentFetcherHandler<
 				Self::close_client(&mut self.client);
 				(Some(FetchState::Error(timeout)), Next::write())
 			},
-			FetchState::InProgress { ref receiver, .. } => {
+			FetchState::InProgress { ref url, ref deadline, ref receiver } => {
 				// Check if there is an answer
 				let rec = receiver.try_recv();
 				match rec {
@@ -167,6 +608,7 @@ impl<H: ContentValidator> server::Handler<HttpStream> for ContentFetcherHandler<
 					Ok(Ok(path)) => {
 						trace!(target: "dapps", "Fetching content finished. Starting validation ({:?})", path);
 						Self::close_client(&mut self.client);
+						self.backoff.reset();
 						// Unpack and verify
 						let state = match self.installer.validate_and_install(path.clone()) {
 							Err(e) => {
@@ -178,12 +620,32 @@ impl<H: ContentValidator> server::Handler<HttpStream> for ContentFetcherHandler<
 									Some(&format!("{:?}", e))
 								))
 							},
-							Ok(result) => FetchState::Done(result)
+							Ok(result) => {
+								let response = Self::done_response(
+									self.mode,
+									&self.installer,
+									self.using_dapps_domains,
+									self.redirect_base.as_ref().map(|base| base.as_str()),
+									&result.0,
+									&result.1,
+								);
+								FetchState::Done(result, response)
+							},
 						};
 						// Remove temporary zip file
-						let _ = fs::remove_file(path);
+						Self::remove_download(&path);
 						(Some(state), Next::write())
 					},
+					Ok(Err(e)) if self.retries < FETCH_RETRIES && Instant::now() + self.backoff.next < *deadline => {
+						let delay = self.backoff.next_delay();
+						warn!(target: "dapps", "Unable to fetch content: {:?}. Retrying in {:?}.", e, delay);
+						self.retries += 1;
+						(Some(FetchState::Retrying {
+							url: url.clone(),
+							deadline: *deadline,
+							retry_at: Instant::now() + delay,
+						}), Next::read().timeout(delay))
+					},
 					Ok(Err(e)) => {
 						warn!(target: "dapps", "Unable to fetch content: {:?}", e);
 						let error = ContentHandler::error(
@@ -198,6 +660,76 @@ impl<H: ContentValidator> server::Handler<HttpStream> for ContentFetcherHandler<
 					_ => (None, Next::wait())
 				}
 			},
+			FetchState::FetchingMetadata { ref deadline, ref receiver } => {
+				match receiver.try_recv() {
+					Ok(Ok(metadata)) => {
+						trace!(target: "dapps", "Fetching content metadata finished: {:?}", metadata);
+						Self::close_client(&mut self.client);
+						(Some(FetchState::Metadata(metadata)), Next::write())
+					},
+					Ok(Err(e)) => {
+						warn!(target: "dapps", "Unable to fetch content metadata: {:?}", e);
+						Self::close_client(&mut self.client);
+						let error = ContentHandler::error(
+							StatusCode::BadGateway,
+							"Download Error",
+							"There was an error when fetching the content metadata.",
+							Some(&format!("{:?}", e)),
+						);
+						(Some(FetchState::Error(error)), Next::write())
+					},
+					_ if *deadline < Instant::now() => {
+						trace!(target: "dapps", "Fetching content metadata failed because of timeout.");
+						Self::close_client(&mut self.client);
+						let timeout = ContentHandler::error(
+							StatusCode::GatewayTimeout,
+							"Download Timeout",
+							&format!("Could not fetch content metadata within {} seconds.", FETCH_TIMEOUT),
+							None
+						);
+						(Some(FetchState::Error(timeout)), Next::write())
+					},
+					// wait some more
+					_ => (None, Next::wait()),
+				}
+			},
+			FetchState::Streaming { ref deadline, ref receiver, ref response_started, ref mut metadata, .. } => {
+				if metadata.is_some() {
+					(None, Next::write())
+				} else {
+					match receiver.try_recv() {
+						Ok(StreamChunk::Metadata(started)) => {
+							trace!(target: "dapps", "Streaming content started: {:?}", started);
+							*metadata = Some(started);
+							response_started.store(true, Ordering::SeqCst);
+							(None, Next::write())
+						},
+						Err(mpsc::TryRecvError::Empty) if *deadline >= Instant::now() => (None, Next::wait()),
+						Err(mpsc::TryRecvError::Empty) => {
+							trace!(target: "dapps", "Streaming content failed because of timeout.");
+							Self::close_client(&mut self.client);
+							let timeout = ContentHandler::error(
+								StatusCode::GatewayTimeout,
+								"Download Timeout",
+								&format!("Could not start fetching content within {} seconds.", FETCH_TIMEOUT),
+								None
+							);
+							(Some(FetchState::Error(timeout)), Next::write())
+						},
+						other => {
+							warn!(target: "dapps", "Unable to stream content: {:?}", other);
+							Self::close_client(&mut self.client);
+							let error = ContentHandler::error(
+								StatusCode::BadGateway,
+								"Download Error",
+								"There was an error when fetching the content.",
+								Some(&format!("{:?}", other)),
+							);
+							(Some(FetchState::Error(error)), Next::write())
+						},
+					}
+				}
+			},
 			FetchState::Error(ref mut handler) => (None, handler.on_request_readable(decoder)),
 			_ => (None, Next::write()),
 		};
@@ -211,10 +743,26 @@ impl<H: ContentValidator> server::Handler<HttpStream> for ContentFetcherHandler<
 
 	fn on_response(&mut self, res: &mut server::Response) -> Next {
 		match self.status {
-			FetchState::Done((ref id, _)) => {
-				trace!(target: "dapps", "Fetching content finished. Redirecting to {}", id);
+			FetchState::Done(_, DoneResponse::Redirect(ref address)) => {
+				trace!(target: "dapps", "Fetching content finished. Redirecting to {}", address);
 				res.set_status(StatusCode::Found);
-				res.headers_mut().set(header::Location(redirection_address(self.using_dapps_domains, id)));
+				res.headers_mut().set(header::Location(address.clone()));
+				Next::write()
+			},
+			FetchState::Done(_, DoneResponse::Inline(ref mut handler)) => {
+				trace!(target: "dapps", "Fetching content finished. Serving inline.");
+				handler.on_response(res)
+			},
+			FetchState::Metadata(ref metadata) => {
+				trace!(target: "dapps", "Responding with content metadata.");
+				res.set_status(StatusCode::Ok);
+				Self::set_metadata_headers(res.headers_mut(), metadata);
+				Next::end()
+			},
+			FetchState::Streaming { metadata: Some(ref metadata), .. } => {
+				trace!(target: "dapps", "Streaming content to the client.");
+				res.set_status(StatusCode::Ok);
+				Self::set_metadata_headers(res.headers_mut(), metadata);
 				Next::write()
 			},
 			FetchState::Error(ref mut handler) => handler.on_response(res),
@@ -223,10 +771,388 @@ impl<H: ContentValidator> server::Handler<HttpStream> for ContentFetcherHandler<
 	}
 
 	fn on_response_writable(&mut self, encoder: &mut Encoder<HttpStream>) -> Next {
-		match self.status {
-			FetchState::Error(ref mut handler) => handler.on_response_writable(encoder),
-			_ => Next::end(),
+		let (status, next) = match self.status {
+			FetchState::Done(_, DoneResponse::Inline(ref mut handler)) => (None, handler.on_response_writable(encoder)),
+			FetchState::Streaming { ref receiver, ref mut pending, .. } => match Self::forward_stream(receiver, pending, encoder, &self.bandwidth) {
+				Forward::Blocked => (None, Next::write()),
+				Forward::Waiting => (None, Next::wait()),
+				Forward::Finished => (Some(FetchState::Streamed), Next::end()),
+			},
+			FetchState::Error(ref mut handler) => (None, handler.on_response_writable(encoder)),
+			_ => (None, Next::end()),
+		};
+
+		if let Some(status) = status {
+			// Receiver is dropped before closing the client, which may be blocked on sending more content
+			self.status = status;
+			Self::close_client(&mut self.client);
+		}
+
+		next
+	}
+
+	fn on_error(&mut self, err: hyper::Error) -> Next {
+		// The read timeout set while retrying is what wakes us up for the next attempt.
+		let retry_due = match (&err, &self.status) {
+			(&hyper::Error::Timeout, &FetchState::Retrying { .. }) => true,
+			_ => false,
+		};
+		if retry_due {
+			return self.poll_retry();
 		}
+		debug!(target: "dapps", "Error while fetching content: {:?}", err);
+		Next::remove()
 	}
 }
 
+#[cfg(test)]
+mod tests {
+	use std::{env, fmt, fs};
+	use std::io::Write;
+	use std::path::PathBuf;
+	use std::sync::mpsc;
+	use std::time::{Duration, Instant};
+	use hyper::header;
+	use handlers::client::Client;
+	use handlers::client::fetch_head::Metadata;
+	use handlers::client::fetch_stream::StreamChunk;
+	use random_filename;
+	use hyper::status::StatusCode;
+	use page::{ServedFile, DappFile};
+	use super::{ContentFetcherHandler, ContentValidator, FetchMode, FetchLimit, BandwidthLimit, FetchState, DoneResponse, Backoff, Forward, FETCH_TIMEOUT};
+
+	#[derive(Debug)]
+	struct FakeError;
+
+	impl fmt::Display for FakeError {
+		fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+			write!(f, "FakeError")
+		}
+	}
+
+	struct FakeValidator;
+
+	impl ContentValidator for FakeValidator {
+		type Error = FakeError;
+		type Result = PathBuf;
+
+		fn validate_and_install(&self, _app: PathBuf) -> Result<(String, PathBuf), FakeError> {
+			Err(FakeError)
+		}
+
+		fn done(&self, _result: Option<&PathBuf>) {}
+
+		fn inline_content(&self, result: &PathBuf) -> Option<(String, PathBuf)> {
+			Some(("text/plain".into(), result.clone()))
+		}
+	}
+
+	type Handler = ContentFetcherHandler<FakeValidator>;
+
+	#[test]
+	fn should_serve_inline_content() {
+		// given
+		let mut path = env::temp_dir();
+		path.push(random_filename());
+		fs::File::create(&path).unwrap().write_all(b"content").unwrap();
+
+		// when
+		let response = Handler::done_response(FetchMode::Inline, &FakeValidator, false, None, "test", &path);
+
+		// then
+		let mut handler = match response {
+			DoneResponse::Inline(handler) => handler,
+			DoneResponse::Redirect(address) => panic!("Expected inline content, got redirect to {}", address),
+		};
+		match handler.file {
+			// served with 200 and streamed from the installed file
+			ServedFile::File(ref mut file) => {
+				assert_eq!(file.content_type(), "text/plain");
+				let mut body = Vec::new();
+				while !file.is_drained() {
+					let written = {
+						let chunk = file.next_chunk();
+						body.extend_from_slice(chunk);
+						chunk.len()
+					};
+					assert!(written > 0);
+					file.bytes_written(written);
+				}
+				assert_eq!(body, b"content".to_vec());
+			},
+			ServedFile::Error(ref handler) => panic!("Expected inline content, got error {:?}", handler.status()),
+		}
+		fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn should_redirect_if_content_is_missing() {
+		// given
+		let mut path = env::temp_dir();
+		path.push(random_filename());
+
+		// when
+		let response = Handler::done_response(FetchMode::Inline, &FakeValidator, false, None, "test", &path);
+
+		// then
+		match response {
+			DoneResponse::Redirect(address) => assert!(address.contains("test")),
+			DoneResponse::Inline(_) => panic!("Expected redirect, got inline content"),
+		}
+	}
+
+	#[test]
+	fn should_redirect_by_default() {
+		let response = Handler::done_response(FetchMode::Redirect, &FakeValidator, false, None, "test", &PathBuf::from("test"));
+		match response {
+			DoneResponse::Redirect(address) => assert!(address.contains("test")),
+			DoneResponse::Inline(_) => panic!("Expected redirect, got inline content"),
+		}
+	}
+
+	#[test]
+	fn should_redirect_to_overridden_base() {
+		// when
+		let base = Some("https://proxy.example.com/dapps/");
+		let response = Handler::done_response(FetchMode::Redirect, &FakeValidator, true, base, "test", &PathBuf::from("test"));
+
+		// then
+		match response {
+			DoneResponse::Redirect(address) => assert_eq!(address, "https://proxy.example.com/dapps/test/"),
+			DoneResponse::Inline(_) => panic!("Expected redirect, got inline content"),
+		}
+	}
+
+	#[test]
+	fn should_respond_to_head_with_metadata_headers() {
+		// given
+		let metadata = Metadata {
+			content_length: Some(1024),
+			content_type: Some("application/zip".into()),
+		};
+		let mut headers = header::Headers::new();
+
+		// when
+		Handler::set_metadata_headers(&mut headers, &metadata);
+
+		// then
+		assert_eq!(headers.get::<header::ContentLength>(), Some(&header::ContentLength(1024)));
+		assert_eq!(format!("{}", headers.get::<header::ContentType>().unwrap().0), "application/zip");
+		assert_eq!(headers.len(), 2);
+	}
+
+	#[test]
+	fn should_skip_missing_metadata_headers() {
+		// given
+		let metadata = Metadata {
+			content_length: None,
+			content_type: Some("not a mime".into()),
+		};
+		let mut headers = header::Headers::new();
+
+		// when
+		Handler::set_metadata_headers(&mut headers, &metadata);
+
+		// then
+		assert_eq!(headers.len(), 0);
+	}
+
+	#[test]
+	fn should_download_to_and_cleanup_custom_temp_dir() {
+		// given
+		let mut temp_dir = env::temp_dir();
+		temp_dir.push(random_filename());
+		fs::create_dir_all(&temp_dir).unwrap();
+		let path = Client::temp_path(&temp_dir);
+
+		// when
+		fs::File::create(&path).unwrap();
+		assert!(path.starts_with(&temp_dir));
+		assert!(path.exists());
+		Handler::remove_download(&path);
+
+		// then
+		assert!(!path.exists());
+		fs::remove_dir(&temp_dir).unwrap();
+	}
+
+	#[test]
+	fn should_grow_delay_between_failed_attempts() {
+		// given
+		let mut backoff = Backoff::new(Duration::from_millis(100));
+
+		// when
+		let first = backoff.next_delay();
+		let second = backoff.next_delay();
+		let third = backoff.next_delay();
+
+		// then
+		assert_eq!(first, Duration::from_millis(100));
+		assert_eq!(second, Duration::from_millis(200));
+		assert_eq!(third, Duration::from_millis(400));
+	}
+
+	#[test]
+	fn should_reset_delay_after_success() {
+		// given
+		let mut backoff = Backoff::new(Duration::from_millis(100));
+		backoff.next_delay();
+		backoff.next_delay();
+
+		// when
+		backoff.reset();
+
+		// then
+		assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+	}
+
+	#[test]
+	fn should_wait_until_retry_is_due() {
+		// given
+		let now = Instant::now();
+		let deadline = now + Duration::from_secs(FETCH_TIMEOUT);
+		let mut backoff = Backoff::new(Duration::from_millis(100));
+
+		// when
+		let first = Handler::retry_wait(now + backoff.next_delay(), deadline, now);
+		let second = Handler::retry_wait(now + backoff.next_delay(), deadline, now);
+		let due = Handler::retry_wait(now, deadline, now);
+
+		// then
+		assert_eq!(first, Some(Duration::from_millis(100)));
+		assert_eq!(second, Some(Duration::from_millis(200)));
+		assert_eq!(due, None);
+	}
+
+	#[test]
+	fn should_not_wait_for_retry_past_fetch_timeout() {
+		// given
+		let now = Instant::now();
+		let deadline = now + Duration::from_secs(FETCH_TIMEOUT);
+		let retry_at = deadline + Duration::from_secs(5);
+
+		// when
+		let wait = Handler::retry_wait(retry_at, deadline, now);
+
+		// then
+		assert_eq!(wait, Some(Duration::from_secs(FETCH_TIMEOUT)));
+		assert_eq!(Handler::retry_wait(retry_at, deadline, deadline), None);
+	}
+
+	#[test]
+	fn should_not_store_streamed_content() {
+		// given
+		let mut temp_dir = env::temp_dir();
+		temp_dir.push(random_filename());
+		fs::create_dir_all(&temp_dir).unwrap();
+
+		// when
+		let streamed = Handler::download_path(FetchMode::Stream, &temp_dir);
+		let fetched = Handler::download_path(FetchMode::Redirect, &temp_dir);
+
+		// then
+		assert_eq!(streamed, None);
+		assert!(fetched.unwrap().starts_with(&temp_dir));
+		assert_eq!(fs::read_dir(&temp_dir).unwrap().count(), 0);
+		fs::remove_dir(&temp_dir).unwrap();
+	}
+
+	#[test]
+	fn should_forward_streamed_body() {
+		// given
+		let (tx, rx) = mpsc::channel();
+		let mut pending = Vec::new();
+		let mut body = Vec::new();
+		tx.send(StreamChunk::Metadata(Metadata { content_length: Some(12), content_type: None })).unwrap();
+		tx.send(StreamChunk::Data(b"Hello ".to_vec())).unwrap();
+
+		// when
+		let bandwidth = BandwidthLimit::new(1024, Duration::from_secs(60));
+		let first = Handler::forward_stream(&rx, &mut pending, &mut body, &bandwidth);
+		tx.send(StreamChunk::Data(b"World!".to_vec())).unwrap();
+		tx.send(StreamChunk::Done).unwrap();
+		let second = Handler::forward_stream(&rx, &mut pending, &mut body, &bandwidth);
+
+		// then
+		assert_eq!(first, Forward::Waiting);
+		assert_eq!(second, Forward::Finished);
+		assert_eq!(body, b"Hello World!".to_vec());
+		assert_eq!(bandwidth.fetched.lock().iter().map(|&(_, bytes)| bytes).collect::<Vec<_>>(), vec![6, 6]);
+	}
+
+	#[test]
+	fn should_reject_fetches_over_the_limit() {
+		// given
+		let limit = FetchLimit::new(2);
+		let first = limit.acquire();
+		let second = limit.acquire();
+
+		// when
+		let third = limit.acquire();
+		let status = Handler::initial_status("http://parity.io".into(), third.is_some(), false);
+
+		// then
+		assert!(first.is_some() && second.is_some());
+		assert_eq!(limit.in_flight(), 2);
+		match status {
+			FetchState::Error(handler) => assert_eq!(handler.status(), StatusCode::ServiceUnavailable),
+			_ => panic!("Expected fetch over the limit to be rejected"),
+		}
+	}
+
+	#[test]
+	fn should_release_fetch_slot_on_drop() {
+		// given
+		let limit = FetchLimit::new(1);
+		let slot = limit.acquire();
+		assert!(limit.acquire().is_none());
+
+		// when
+		drop(slot);
+
+		// then
+		assert_eq!(limit.in_flight(), 0);
+		assert!(limit.acquire().is_some());
+	}
+
+	#[test]
+	fn should_reject_fetches_over_the_bandwidth_cap() {
+		// given
+		let bandwidth = BandwidthLimit::new(1000, Duration::from_secs(60));
+		let start = Instant::now();
+		bandwidth.record_at(start, 400);
+		bandwidth.record_at(start + Duration::from_secs(10), 400);
+		assert!(!bandwidth.is_exceeded_at(start + Duration::from_secs(20)));
+
+		// when
+		bandwidth.record_at(start + Duration::from_secs(20), 400);
+		let exceeded = bandwidth.is_exceeded_at(start + Duration::from_secs(30));
+		let status = Handler::initial_status("http://parity.io".into(), true, exceeded);
+
+		// then
+		assert!(exceeded);
+		match status {
+			FetchState::Error(handler) => assert_eq!(handler.status(), StatusCode::ServiceUnavailable),
+			_ => panic!("Expected fetch over the bandwidth cap to be rejected"),
+		}
+		// the first fetch leaves the window
+		assert!(!bandwidth.is_exceeded_at(start + Duration::from_secs(60)));
+	}
+
+	#[test]
+	fn should_count_download_progress_towards_the_bandwidth_cap() {
+		// given
+		let bandwidth = BandwidthLimit::new(1000, Duration::from_secs(60));
+		let start = Instant::now();
+
+		// when
+		for i in 0..10 {
+			bandwidth.record_at(start + Duration::from_millis(i * 50), 100);
+		}
+
+		// then
+		assert_eq!(bandwidth.fetched.lock().len(), 1);
+		assert!(bandwidth.is_exceeded_at(start + Duration::from_secs(1)));
+		assert!(!bandwidth.is_exceeded_at(start + Duration::from_secs(60)));
+	}
+}
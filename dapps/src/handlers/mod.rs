@@ -27,7 +27,7 @@ pub use self::auth::AuthRequiredHandler;
 pub use self::echo::EchoHandler;
 pub use self::content::ContentHandler;
 pub use self::redirect::Redirection;
-pub use self::fetch::{ContentFetcherHandler, ContentValidator};
+pub use self::fetch::{ContentFetcherHandler, ContentValidator, FetchMode, FetchOptions, FetchLimit, BandwidthLimit};
 
 use url::Url;
 use hyper::{server, header, net, uri};
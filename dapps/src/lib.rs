@@ -78,13 +78,17 @@ mod url;
 mod tests;
 
 pub use self::apps::urlhint::ContractClient;
+pub use self::handlers::FetchMode;
 
 use std::sync::{Arc, Mutex};
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::collections::HashMap;
 
 use jsonrpc_core::{IoHandler, IoDelegate};
 use router::auth::{Authorization, NoAuth, HttpBasicAuth};
+use apps::fetcher::ContentFetcher;
+use apps::urlhint::URLHintContract;
 use ethcore_rpc::Extendable;
 
 static DAPPS_DOMAIN : &'static str = ".parity";
@@ -105,6 +109,10 @@ pub struct ServerBuilder {
 	handler: Arc<IoHandler>,
 	registrar: Arc<ContractClient>,
 	sync_status: Arc<SyncStatus>,
+	redirect_base: Option<String>,
+	fetch_mode: FetchMode,
+	fetch_headers: Vec<(String, String)>,
+	fetch_temp_dir: Option<PathBuf>,
 }
 
 impl Extendable for ServerBuilder {
@@ -121,6 +129,10 @@ impl ServerBuilder {
 			handler: Arc::new(IoHandler::new()),
 			registrar: registrar,
 			sync_status: Arc::new(|| false),
+			redirect_base: None,
+			fetch_mode: FetchMode::Redirect,
+			fetch_headers: Vec::new(),
+			fetch_temp_dir: None,
 		}
 	}
 
@@ -129,6 +141,28 @@ impl ServerBuilder {
 		self.sync_status = status;
 	}
 
+	/// Change the base address fetched dapps are redirected to (e.g. when running behind a reverse proxy).
+	pub fn with_redirect_base(&mut self, base: String) {
+		self.redirect_base = Some(base);
+	}
+
+	/// Change how fetched content is handed to the client, redirecting to it by default.
+	/// `FetchMode::Stream` skips validation of the content, so use it only with trusted content sources.
+	pub fn with_fetch_mode(&mut self, mode: FetchMode) {
+		self.fetch_mode = mode;
+	}
+
+	/// Change the directory dapps are downloaded to, the system temp dir by default.
+	pub fn with_fetch_temp_dir(&mut self, dir: PathBuf) {
+		self.fetch_temp_dir = Some(dir);
+	}
+
+	/// Attach extra headers (e.g. `Authorization`) to the requests fetching dapps.
+	/// Invalid headers are reported when the server is started.
+	pub fn with_fetch_headers(&mut self, headers: Vec<(String, String)>) {
+		self.fetch_headers = headers;
+	}
+
 	/// Asynchronously start server with no authentication,
 	/// returns result with `Server` handle on success or an error.
 	pub fn start_unsecured_http(&self, addr: &SocketAddr, hosts: Option<Vec<String>>) -> Result<Server, ServerError> {
@@ -138,8 +172,7 @@ impl ServerBuilder {
 			NoAuth,
 			self.handler.clone(),
 			self.dapps_path.clone(),
-			self.registrar.clone(),
-			self.sync_status.clone(),
+			try!(self.content_fetcher()),
 		)
 	}
 
@@ -152,10 +185,21 @@ impl ServerBuilder {
 			HttpBasicAuth::single_user(username, password),
 			self.handler.clone(),
 			self.dapps_path.clone(),
-			self.registrar.clone(),
-			self.sync_status.clone(),
+			try!(self.content_fetcher()),
 		)
 	}
+
+	fn content_fetcher(&self) -> Result<ContentFetcher, ServerError> {
+		let fetcher = ContentFetcher::new(URLHintContract::new(self.registrar.clone()), self.sync_status.clone())
+			.with_redirect_base(self.redirect_base.clone())
+			.with_mode(self.fetch_mode);
+		let fetcher = match self.fetch_temp_dir {
+			Some(ref dir) => fetcher.with_temp_dir(dir.clone()),
+			None => fetcher,
+		};
+		fetcher.with_headers(self.fetch_headers.clone())
+			.map_err(|e| ServerError::InvalidFetchHeaders(format!("{:?}", e)))
+	}
 }
 
 /// Webapps HTTP server.
@@ -186,12 +230,11 @@ impl Server {
 		authorization: A,
 		handler: Arc<IoHandler>,
 		dapps_path: String,
-		registrar: Arc<ContractClient>,
-		sync_status: Arc<SyncStatus>,
+		content_fetcher: ContentFetcher,
 	) -> Result<Server, ServerError> {
 		let panic_handler = Arc::new(Mutex::new(None));
 		let authorization = Arc::new(authorization);
-		let content_fetcher = Arc::new(apps::fetcher::ContentFetcher::new(apps::urlhint::URLHintContract::new(registrar), sync_status));
+		let content_fetcher = Arc::new(content_fetcher);
 		let endpoints = Arc::new(apps::all_endpoints(dapps_path));
 		let special = Arc::new({
 			let mut special = HashMap::new();
@@ -251,6 +294,8 @@ pub enum ServerError {
 	IoError(std::io::Error),
 	/// Other `hyper` error
 	Other(hyper::error::Error),
+	/// Invalid extra headers of dapp fetches
+	InvalidFetchHeaders(String),
 }
 
 impl From<hyper::error::Error> for ServerError {
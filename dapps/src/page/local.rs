@@ -47,6 +47,18 @@ impl LocalPageEndpoint {
 	pub fn path(&self) -> PathBuf {
 		self.path.clone()
 	}
+
+	/// Creates a handler streaming the single file regardless of the requested path,
+	/// `None` if the file can't be opened.
+	pub fn file_handler(path: PathBuf, mime: String) -> Option<handler::PageHandler<LocalSingleFile>> {
+		LocalFile::from_path(&path, Some(mime.as_str())).map(|file| handler::PageHandler {
+			app: LocalSingleFile { path: path, mime: mime },
+			prefix: None,
+			path: EndpointPath::default(),
+			file: handler::ServedFile::File(file),
+			safe_to_embed: false,
+		})
+	}
 }
 
 impl Endpoint for LocalPageEndpoint {
@@ -75,7 +87,7 @@ impl Endpoint for LocalPageEndpoint {
 	}
 }
 
-struct LocalSingleFile {
+pub struct LocalSingleFile {
 	path: PathBuf,
 	mime: String,
 }
@@ -104,7 +116,7 @@ impl handler::Dapp for LocalDapp {
 	}
 }
 
-struct LocalFile {
+pub struct LocalFile {
 	content_type: String,
 	buffer: [u8; 4096],
 	file: fs::File,
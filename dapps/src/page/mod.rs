@@ -19,6 +19,7 @@ mod builtin;
 mod local;
 mod handler;
 
-pub use self::local::LocalPageEndpoint;
+pub use self::local::{LocalPageEndpoint, LocalSingleFile};
+pub use self::handler::{PageHandler, ServedFile, DappFile};
 pub use self::builtin::PageEndpoint;
 
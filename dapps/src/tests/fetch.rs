@@ -14,7 +14,26 @@
 // You should have received a copy of the GNU General Public License
 // along with Parity.  If not, see <http://www.gnu.org/licenses/>.
 
-use tests::helpers::{serve_with_registrar, request};
+use std::{env, fs};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+use tests::helpers::{serve_with_registrar, serve_with_builder, request};
+use random_filename;
+use FetchMode;
+
+fn read_request_head(stream: &mut TcpStream) -> String {
+	let mut head = Vec::new();
+	let mut byte = [0u8; 1];
+	while !head.ends_with(b"\r\n\r\n") {
+		match stream.read(&mut byte) {
+			Ok(1) => head.push(byte[0]),
+			_ => break,
+		}
+	}
+	String::from_utf8_lossy(&head).into_owned()
+}
 
 #[test]
 fn should_resolve_dapp() {
@@ -36,3 +55,209 @@ fn should_resolve_dapp() {
 	assert_eq!(registrar.calls.lock().len(), 2);
 }
 
+#[test]
+fn should_respond_to_head_with_content_metadata() {
+	// given
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}/image.png", listener.local_addr().unwrap());
+	// serves a single request, so fetching the content afterwards would fail
+	let content_server = thread::spawn(move || {
+		let (mut stream, _) = listener.accept().unwrap();
+		let head = read_request_head(&mut stream);
+		stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: 5\r\nConnection: close\r\n\r\n").unwrap();
+		head
+	});
+	let (server, registrar) = serve_with_registrar();
+	registrar.resolve_content_to(&url);
+
+	// when
+	let response = request(server,
+		"\
+			HEAD / HTTP/1.1\r\n\
+			Host: 1472a9e190620cdf6b31f383373e45efcfe869a820c91f9ccd7eb9fb45e4985d.parity\r\n\
+			Connection: close\r\n\
+			\r\n\
+		"
+	);
+
+	// then
+	let content_request = content_server.join().unwrap();
+	assert!(content_request.starts_with("HEAD /image.png HTTP/1.1"), content_request);
+	assert_eq!(response.status, "HTTP/1.1 200 OK".to_owned());
+	assert!(response.headers.contains(&"Content-Type: image/png".to_owned()), response.headers_raw);
+	assert!(response.headers.contains(&"Content-Length: 5".to_owned()), response.headers_raw);
+	assert!(!response.headers_raw.contains("Location"), response.headers_raw);
+	assert_eq!(response.body, "".to_owned());
+	assert_eq!(registrar.calls.lock().len(), 4);
+}
+
+#[test]
+fn should_not_allow_head_for_https_content() {
+	// given
+	let (server, registrar) = serve_with_registrar();
+	registrar.resolve_content_to("https://127.0.0.1:1/image.png");
+
+	// when
+	let response = request(server,
+		"\
+			HEAD / HTTP/1.1\r\n\
+			Host: 1472a9e190620cdf6b31f383373e45efcfe869a820c91f9ccd7eb9fb45e4985d.parity\r\n\
+			Connection: close\r\n\
+			\r\n\
+		"
+	);
+
+	// then
+	assert_eq!(response.status, "HTTP/1.1 405 Method Not Allowed".to_owned());
+	assert_eq!(registrar.calls.lock().len(), 4);
+}
+
+#[test]
+fn should_send_custom_headers_with_fetch_request() {
+	// given
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}/file.txt", listener.local_addr().unwrap());
+	let content_server = thread::spawn(move || {
+		let (mut stream, _) = listener.accept().unwrap();
+		let head = read_request_head(&mut stream);
+		stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello").unwrap();
+		head
+	});
+	let (server, registrar) = serve_with_builder(|builder| {
+		builder.with_fetch_headers(vec![("X-Registry-Token".into(), "secret".into())]);
+	});
+	registrar.resolve_content_to(&url);
+
+	// when
+	let response = request(server,
+		"\
+			GET / HTTP/1.1\r\n\
+			Host: 1472a9e190620cdf6b31f383373e45efcfe869a820c91f9ccd7eb9fb45e4985d.parity\r\n\
+			Connection: close\r\n\
+			\r\n\
+		"
+	);
+
+	// then
+	let content_request = content_server.join().unwrap();
+	assert!(content_request.starts_with("GET /file.txt HTTP/1.1"), content_request);
+	assert!(content_request.to_lowercase().contains("\r\nx-registry-token: secret\r\n"), content_request);
+	assert_eq!(response.status, "HTTP/1.1 302 Found".to_owned());
+	assert_eq!(registrar.calls.lock().len(), 4);
+}
+
+fn serve_content_once(listener: TcpListener) -> thread::JoinHandle<()> {
+	thread::spawn(move || {
+		let (mut stream, _) = listener.accept().unwrap();
+		read_request_head(&mut stream);
+		stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello").unwrap();
+	})
+}
+
+#[test]
+fn should_serve_fetched_content_inline() {
+	// given
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}/file.txt", listener.local_addr().unwrap());
+	let content_server = serve_content_once(listener);
+	let (server, registrar) = serve_with_builder(|builder| {
+		builder.with_fetch_mode(FetchMode::Inline);
+	});
+	registrar.resolve_content_to(&url);
+
+	// when
+	let response = request(server,
+		"\
+			GET / HTTP/1.1\r\n\
+			Host: 1472a9e190620cdf6b31f383373e45efcfe869a820c91f9ccd7eb9fb45e4985d.parity\r\n\
+			Connection: close\r\n\
+			\r\n\
+		"
+	);
+
+	// then
+	content_server.join().unwrap();
+	assert_eq!(response.status, "HTTP/1.1 200 OK".to_owned());
+	assert!(!response.headers_raw.contains("Location"), response.headers_raw);
+	assert!(response.body.contains("hello"), response.body);
+	assert_eq!(registrar.calls.lock().len(), 4);
+}
+
+#[test]
+fn should_stream_content_without_storing_it() {
+	// given
+	let mut temp_dir = env::temp_dir();
+	temp_dir.push(random_filename());
+	fs::create_dir_all(&temp_dir).unwrap();
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}/file.txt", listener.local_addr().unwrap());
+	let content_server = serve_content_once(listener);
+	let dir = temp_dir.clone();
+	let (server, registrar) = serve_with_builder(move |builder| {
+		builder.with_fetch_mode(FetchMode::Stream);
+		builder.with_fetch_temp_dir(dir);
+	});
+	registrar.resolve_content_to(&url);
+
+	// when
+	let response = request(server,
+		"\
+			GET / HTTP/1.1\r\n\
+			Host: 1472a9e190620cdf6b31f383373e45efcfe869a820c91f9ccd7eb9fb45e4985d.parity\r\n\
+			Connection: close\r\n\
+			\r\n\
+		"
+	);
+
+	// then
+	content_server.join().unwrap();
+	assert_eq!(response.status, "HTTP/1.1 200 OK".to_owned());
+	assert!(response.headers.contains(&"Content-Length: 5".to_owned()), response.headers_raw);
+	assert!(!response.headers_raw.contains("Location"), response.headers_raw);
+	assert_eq!(response.body, "hello\n".to_owned());
+	assert_eq!(fs::read_dir(&temp_dir).unwrap().count(), 0);
+	fs::remove_dir(&temp_dir).unwrap();
+}
+
+#[test]
+fn should_wait_before_retrying_failed_fetch() {
+	// given
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}/file.txt", listener.local_addr().unwrap());
+	// fails the first request and serves the content on the retry
+	let content_server = thread::spawn(move || {
+		let (mut stream, _) = listener.accept().unwrap();
+		read_request_head(&mut stream);
+		stream.write_all(b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap();
+		drop(stream);
+		let failed_at = Instant::now();
+
+		let (mut stream, _) = listener.accept().unwrap();
+		let retried_at = Instant::now();
+		read_request_head(&mut stream);
+		stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello").unwrap();
+		retried_at - failed_at
+	});
+	let (server, registrar) = serve_with_registrar();
+	registrar.resolve_content_to(&url);
+	let started = Instant::now();
+
+	// when
+	let response = request(server,
+		"\
+			GET / HTTP/1.1\r\n\
+			Host: 1472a9e190620cdf6b31f383373e45efcfe869a820c91f9ccd7eb9fb45e4985d.parity\r\n\
+			Connection: close\r\n\
+			\r\n\
+		"
+	);
+
+	// then
+	let retry_delay = content_server.join().unwrap();
+	// first retry waits for the base delay of the fetcher (500ms)
+	assert!(retry_delay >= Duration::from_millis(450), "Retried after {:?}", retry_delay);
+	// and the whole fetch stays within the fetch timeout (30s)
+	assert!(started.elapsed() < Duration::from_secs(30));
+	assert_eq!(response.status, "HTTP/1.1 302 Found".to_owned());
+	assert_eq!(registrar.calls.lock().len(), 4);
+}
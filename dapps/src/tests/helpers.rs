@@ -27,6 +27,7 @@ use devtools::http_client;
 
 const REGISTRAR: &'static str = "8e4e9b13d4b45cb0befc93c3061b1408f67316b2";
 const URLHINT: &'static str = "deadbeefcafe0000000000000000000000000000";
+const OWNER: &'static str = "deadcafebeefbeefcafedeaddeedfeedffffffff";
 
 pub struct FakeRegistrar {
 	pub calls: Arc<Mutex<Vec<(String, String)>>>,
@@ -45,6 +46,22 @@ impl FakeRegistrar {
 			),
 		}
 	}
+
+	/// Resolves content to given url, both when checking for it and when fetching it.
+	pub fn resolve_content_to(&self, url: &str) {
+		let mut hex_url = url.as_bytes().to_hex();
+		while hex_url.len() % 64 != 0 {
+			hex_url.push('0');
+		}
+		let entry = format!("{:064x}{:064x}000000000000000000000000{}{:064x}{}", 0x60, 0, OWNER, url.len(), hex_url);
+
+		let mut responses = self.responses.lock();
+		responses.clear();
+		for _ in 0..2 {
+			responses.push(Ok(format!("000000000000000000000000{}", URLHINT).from_hex().unwrap()));
+			responses.push(Ok(entry.from_hex().unwrap()));
+		}
+	}
 }
 
 impl ContractClient for FakeRegistrar {
@@ -58,11 +75,12 @@ impl ContractClient for FakeRegistrar {
 	}
 }
 
-pub fn init_server(hosts: Option<Vec<String>>) -> (Server, Arc<FakeRegistrar>) {
+pub fn init_server<F>(hosts: Option<Vec<String>>, configure: F) -> (Server, Arc<FakeRegistrar>) where F: FnOnce(&mut ServerBuilder) {
 	let registrar = Arc::new(FakeRegistrar::new());
 	let mut dapps_path = env::temp_dir();
 	dapps_path.push("non-existent-dir-to-prevent-fs-files-from-loading");
-	let builder = ServerBuilder::new(dapps_path.to_str().unwrap().into(), registrar.clone());
+	let mut builder = ServerBuilder::new(dapps_path.to_str().unwrap().into(), registrar.clone());
+	configure(&mut builder);
 	(
 		builder.start_unsecured_http(&"127.0.0.1:0".parse().unwrap(), hosts).unwrap(),
 		registrar,
@@ -78,15 +96,20 @@ pub fn serve_with_auth(user: &str, pass: &str) -> Server {
 }
 
 pub fn serve_hosts(hosts: Option<Vec<String>>) -> Server {
-	init_server(hosts).0
+	init_server(hosts, |_| {}).0
 }
 
 pub fn serve_with_registrar() -> (Server, Arc<FakeRegistrar>) {
-	init_server(None)
+	init_server(None, |_| {})
+}
+
+/// Serves with the builder changed by `configure` first.
+pub fn serve_with_builder<F>(configure: F) -> (Server, Arc<FakeRegistrar>) where F: FnOnce(&mut ServerBuilder) {
+	init_server(None, configure)
 }
 
 pub fn serve() -> Server {
-	init_server(None).0
+	init_server(None, |_| {}).0
 }
 
 pub fn request(server: Server, request: &str) -> http_client::Response {
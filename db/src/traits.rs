@@ -32,18 +32,63 @@ pub struct KeyValue {
 pub enum Error {
 	AlreadyOpen,
 	IsClosed,
-	RocksDb(String),
+	/// Database files are corrupted
+	Corruption(String),
+	/// Underlying io failure, e.g. disk full
+	IoError(String),
+	/// Database or requested file does not exist
+	NotFound(String),
+	/// Any other rocksdb failure
+	Other(String),
 	TransactionUnknown,
 	IteratorUnknown,
 	UncommitedTransactions,
+	UnknownColumn,
+	ChecksumMismatch,
+	WalDirNotWritable(String),
+	/// Value is larger than the configured `max_value_size`
+	ValueTooLarge,
+	/// Database is paused for maintenance, only reads are served
+	Paused,
 }
 
 impl From<String> for Error {
+	/// Best-effort classification of rocksdb status messages
 	fn from(s: String) -> Error {
-		Error::RocksDb(s)
+		if s.starts_with("Corruption:") {
+			Error::Corruption(s)
+		} else if s.starts_with("IO error:") {
+			Error::IoError(s)
+		} else if s.starts_with("NotFound:") || s.contains("does not exist") {
+			Error::NotFound(s)
+		} else {
+			Error::Other(s)
+		}
 	}
 }
 
+/// Tuning of the universal compaction, `None` fields keep the rocksdb defaults
+#[derive(Binary, Default)]
+pub struct UniversalCompactionOptions {
+	/// Percentage flexibility when comparing file sizes for merging
+	pub size_ratio: Option<u32>,
+	/// Minimal number of files merged in a single compaction run
+	pub min_merge_width: Option<u32>,
+	/// Maximal number of files merged in a single compaction run
+	pub max_merge_width: Option<u32>,
+	/// Number of level-0 files at which writes are stopped
+	pub stop_writes_trigger: Option<i32>,
+}
+
+/// Order in which cached writes are flushed to the disk
+#[derive(Binary, Debug, Clone, Copy, PartialEq)]
+pub enum EvictionPolicy {
+	/// Oldest written keys first
+	Fifo,
+	/// Least recently written or read keys first, keeping hot keys cached
+	Lru,
+}
+
 /// Database configuration
 #[derive(Binary)]
 pub struct DatabaseConfig {
@@ -51,6 +96,26 @@ pub struct DatabaseConfig {
 	pub prefix_size: Option<usize>,
 	/// write cache length
 	pub cache: usize,
+	/// Names of the column families to open, created if missing
+	pub columns: Vec<String>,
+	/// Directory for the write-ahead log, if not in the database directory
+	pub wal_dir: Option<String>,
+	/// Universal compaction tuning, rocksdb defaults if none
+	pub universal_compaction: Option<UniversalCompactionOptions>,
+	/// Largest value in bytes accepted by `put` and `write`, unbounded if none
+	pub max_value_size: Option<usize>,
+	/// Which cached writes are flushed first once the cache is full
+	pub eviction: EvictionPolicy,
+	/// Fully compact the database on `close`, leaving the smallest on-disk form behind.
+	/// Compacting a large database can make closing it take a long time.
+	pub compact_on_close: bool,
+	/// Write `put` and `delete` straight to the database instead of the write cache,
+	/// trading throughput for not losing them in a crash
+	pub write_through: bool,
+	/// Skip the write-ahead log, e.g. during an initial import which is started over if interrupted anyway.
+	/// Writes not flushed from the rocksdb memtables yet are lost in a crash, `force_flush` doesn't help,
+	/// only a clean `close` persists them. Reopen with the log enabled once the import is done.
+	pub disable_wal: bool,
 }
 
 impl Default for DatabaseConfig {
@@ -58,6 +123,14 @@ impl Default for DatabaseConfig {
 		DatabaseConfig {
 			prefix_size: None,
 			cache: DEFAULT_CACHE_LEN,
+			columns: Vec::new(),
+			wal_dir: None,
+			universal_compaction: None,
+			max_value_size: None,
+			eviction: EvictionPolicy::Fifo,
+			compact_on_close: false,
+			write_through: false,
+			disable_wal: false,
 		}
 	}
 }
@@ -67,6 +140,14 @@ impl DatabaseConfig {
 		DatabaseConfig {
 			prefix_size: Some(prefix),
 			cache: DEFAULT_CACHE_LEN,
+			columns: Vec::new(),
+			wal_dir: None,
+			universal_compaction: None,
+			max_value_size: None,
+			eviction: EvictionPolicy::Fifo,
+			compact_on_close: false,
+			write_through: false,
+			disable_wal: false,
 		}
 	}
 }
@@ -78,33 +159,159 @@ pub trait DatabaseService : Sized {
 	/// Opens database in the specified path with the default config
 	fn open_default(&self, path: String) -> Result<(), Error>;
 
+	/// Opens an existing database in the specified path with the default config.
+	/// Fails with `Error::NotFound` instead of creating a new database.
+	fn open_existing(&self, path: String) -> Result<(), Error>;
+
 	/// Closes database
 	fn close(&self) -> Result<(), Error>;
 
+	/// Check if the database is open
+	fn is_open(&self) -> Result<bool, Error>;
+
+	/// Does nothing, used to probe the service and measure the IPC round trip.
+	fn ping(&self) -> Result<(), Error>;
+
+	/// Reject all the mutating calls with `Error::Paused` until resumed, e.g. during a migration.
+	/// Reads are still served.
+	fn pause(&self) -> Result<(), Error>;
+
+	/// Accept mutating calls again after `pause`.
+	fn resume(&self) -> Result<(), Error>;
+
+	/// Flush all the cached writes and sync them to the disk.
+	fn force_flush(&self) -> Result<(), Error>;
+
+	/// Flush all the cached writes and create a consistent copy of the database at `target_path`,
+	/// e.g. for backing up a live database. Files are hard-linked where possible, so `target_path`
+	/// should be on the same filesystem and must not exist yet.
+	fn checkpoint(&self, target_path: String) -> Result<(), Error>;
+
+	/// Set the number of cached writes flushed to the disk in a single batch. Zero is treated as one.
+	fn set_flush_batch_size(&self, size: usize) -> Result<(), Error>;
+
+	/// Remove all the keys, including pending writes. Database stays open.
+	fn clear(&self) -> Result<(), Error>;
+
 	/// Insert a key-value pair in the transaction. Any existing value value will be overwritten.
 	fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error>;
 
+	/// Insert all the key-value pairs at once, e.g. for bulk import. Nothing is inserted
+	/// if any of the values is too large.
+	fn put_batch(&self, pairs: Vec<KeyValue>) -> Result<(), Error>;
+
+	/// Insert a key-value pair only if the key is missing, e.g. for content-addressed values.
+	/// Returns whether the value was written.
+	fn put_if_absent(&self, key: &[u8], value: &[u8]) -> Result<bool, Error>;
+
+	/// Delete all the keys starting with `prefix`, including pending writes. Returns the number of keys deleted.
+	fn delete_prefix(&self, prefix: Vec<u8>) -> Result<u64, Error>;
+
+	/// Move every key starting with `old_prefix` under `new_prefix` instead, keeping the rest of the key
+	/// and the value, e.g. when the key encoding changes. Keys are moved in batches, so readers may see
+	/// some of them moved before it returns. Returns the number of keys moved.
+	/// Fails with `Error::Other` if `new_prefix` starts with `old_prefix`.
+	fn rekey_prefix(&self, old_prefix: Vec<u8>, new_prefix: Vec<u8>) -> Result<u64, Error>;
+
 	/// Delete value by key.
 	fn delete(&self, key: &[u8]) -> Result<(), Error>;
 
+	/// Atomically add `delta` to the counter stored under `key`, returning the new value.
+	/// Counters are stored as 8-byte little-endian `i64`, an absent key counts as zero.
+	fn increment(&self, key: &[u8], delta: i64) -> Result<i64, Error>;
+
+	/// Atomically append `suffix` to the value stored under `key`, an absent key counts as empty.
+	/// Backed by a rocksdb merge, so concurrent appends are all kept, in the order they were applied.
+	/// Appending to a counter, or incrementing an appended value, treats it as a plain byte string.
+	fn append(&self, key: &[u8], suffix: &[u8]) -> Result<(), Error>;
+
+	/// Atomically write `new` only if the current value equals `expected` (`None` meaning absent).
+	/// Returns whether the value was written.
+	fn compare_and_set(&self, key: &[u8], expected: Option<Vec<u8>>, new: Vec<u8>) -> Result<bool, Error>;
+
 	/// Get value by key.
 	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
 
+	/// Get at most `len` bytes of the value starting at `offset`, so large values can be transferred in pieces.
+	/// Empty if `offset` is past the end of the value.
+	fn get_chunked(&self, key: Vec<u8>, offset: usize, len: usize) -> Result<Option<Vec<u8>>, Error>;
+
+	/// Insert a key-value pair into the column family. Column family writes are not cached.
+	fn put_cf(&self, column: String, key: &[u8], value: &[u8]) -> Result<(), Error>;
+
+	/// Delete value by key from the column family.
+	fn delete_cf(&self, column: String, key: &[u8]) -> Result<(), Error>;
+
+	/// Get value by key from the column family.
+	fn get_cf(&self, column: String, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+
+	/// Insert a key-value pair under the namespace, a lighter alternative to column families.
+	/// Namespaced keys are stored prefixed with the namespace length and the namespace itself,
+	/// so the same key in different namespaces never collides. Namespaces are at most 255 bytes long.
+	fn put_ns(&self, ns: &[u8], key: &[u8], value: &[u8]) -> Result<(), Error>;
+
+	/// Delete value by key from the namespace.
+	fn delete_ns(&self, ns: &[u8], key: &[u8]) -> Result<(), Error>;
+
+	/// Get value by key from the namespace.
+	fn get_ns(&self, ns: &[u8], key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+
 	/// Get value by partial key. Prefix size should match configured prefix size.
 	fn get_by_prefix(&self, prefix: &[u8]) -> Result<Option<Vec<u8>>, Error>;
 
-	/// Check if there is anything in the database.
+	/// Estimate the number of bytes stored on disk for keys in `[start, end)`.
+	/// This is an approximation based on sst file metadata and does not include cached writes.
+	fn approximate_size(&self, start: Vec<u8>, end: Vec<u8>) -> Result<u64, Error>;
+
+	/// Check if there is anything in the database, including pending writes.
+	/// Pending deletes count as applied, so deleting a key that was never stored leaves the database empty.
 	fn is_empty(&self) -> Result<bool, Error>;
 
-	/// Get handle to iterate through keys
+	/// Approximate number of keys in the database, including pending writes.
+	/// Based on rocksdb's own estimate, so it is not exact.
+	fn len(&self) -> Result<u64, Error>;
+
+	/// Get the value of a rocksdb property, e.g. `rocksdb.stats`. None if the property is unknown.
+	fn get_property(&self, name: String) -> Result<Option<String>, Error>;
+
+	/// Check whether rocksdb is currently stopping or delaying writes while compaction catches up,
+	/// so importers can throttle. Based on the `rocksdb.is-write-stopped` and
+	/// `rocksdb.actual-delayed-write-rate` properties, unknown properties count as not stalled.
+	fn is_stalled(&self) -> Result<bool, Error>;
+
+	/// Get handle to iterate through keys.
+	///
+	/// Keys are yielded in ascending byte-lexicographic order, a key before any longer key it's a prefix of,
+	/// for every iterator of this service. Writes still in the write cache are not visible.
 	fn iter(&self) -> Result<IteratorHandle, Error>;
 
-	/// Next key-value for the the given iterator
-	fn iter_next(&self, iterator: IteratorHandle) -> Option<KeyValue>;
+	/// Get handle to iterate through keys of the column family
+	fn iter_cf(&self, column: String) -> Result<IteratorHandle, Error>;
+
+	/// Get handle to iterate through keys in `[from, until)`, or from `from` to the end if `until` is none
+	fn iter_range(&self, from: Vec<u8>, until: Option<Vec<u8>>) -> Result<IteratorHandle, Error>;
+
+	/// Get handle to iterate through keys starting with `prefix`
+	fn iter_prefix(&self, prefix: Vec<u8>) -> Result<IteratorHandle, Error>;
+
+	/// Get handle to iterate through keys of the namespace, yielded without the namespace prefix
+	fn iter_ns(&self, ns: Vec<u8>) -> Result<IteratorHandle, Error>;
+
+	/// Next key-value for the the given iterator in ascending key order, `None` once it's exhausted
+	fn iter_next(&self, iterator: IteratorHandle) -> Result<Option<KeyValue>, Error>;
+
+	/// Get handle to iterate through keys only, see `iter_next_key`
+	fn iter_keys(&self) -> Result<IteratorHandle, Error>;
+
+	/// Next key for the given iterator without its value, `None` once it's exhausted
+	fn iter_next_key(&self, iterator: IteratorHandle) -> Result<Option<Vec<u8>>, Error>;
 
 	/// Dispose iteration that is no longer needed
 	fn dispose_iter(&self, handle: IteratorHandle) -> Result<(), Error>;
 
+	/// Handles of the iterators not disposed yet, in ascending order, to find leaked ones
+	fn active_iterators(&self) -> Result<Vec<IteratorHandle>, Error>;
+
 	/// Write client transaction
 	fn write(&self, transaction: DBTransaction) -> Result<(), Error>;
 }
@@ -113,14 +320,60 @@ pub trait DatabaseService : Sized {
 pub struct DBTransaction {
 	pub writes: RefCell<Vec<KeyValue>>,
 	pub removes: RefCell<Vec<Vec<u8>>>,
+	/// CRC32 of all the keys and values, verified before the transaction is applied
+	pub checksum: Option<u32>,
+}
+
+/// CRC32 (IEEE) of the data, continuing from `crc`
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+	for byte in data {
+		crc ^= *byte as u32;
+		for _ in 0..8 {
+			crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+		}
+	}
+	crc
+}
+
+/// CRC32 of the data preceded by its length, so adjacent fields can't be shifted into each other
+fn crc32_update_field(crc: u32, data: &[u8]) -> u32 {
+	let len = data.len() as u32;
+	let len = [len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8];
+	crc32_update(crc32_update(crc, &len), data)
 }
 
+/// Tags of the operations covered by the checksum
+const CHECKSUM_PUT: u8 = 1;
+const CHECKSUM_DELETE: u8 = 2;
+
 impl DBTransaction {
 	pub fn new() -> DBTransaction {
 		DBTransaction {
 			writes: RefCell::new(Vec::new()),
 			removes: RefCell::new(Vec::new()),
+			checksum: None,
+		}
+	}
+
+	/// CRC32 over the written keys and values followed by the removed keys, each operation
+	/// tagged with its kind and each key and value prefixed with its length
+	pub fn compute_checksum(&self) -> u32 {
+		let mut crc = !0u32;
+		for kv in self.writes.borrow().iter() {
+			crc = crc32_update(crc, &[CHECKSUM_PUT]);
+			crc = crc32_update_field(crc, &kv.key);
+			crc = crc32_update_field(crc, &kv.value);
 		}
+		for key in self.removes.borrow().iter() {
+			crc = crc32_update(crc, &[CHECKSUM_DELETE]);
+			crc = crc32_update_field(crc, key);
+		}
+		!crc
+	}
+
+	/// Attach the checksum of the current contents, so the service can verify it
+	pub fn seal(&mut self) {
+		self.checksum = Some(self.compute_checksum());
 	}
 
 	pub fn put(&self, key: &[u8], value: &[u8]) {
@@ -133,3 +386,46 @@ impl DBTransaction {
 		brw.push(key.to_vec());
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{Error, DBTransaction};
+
+	fn checksum<F>(fill: F) -> u32 where F: Fn(&DBTransaction) {
+		let transaction = DBTransaction::new();
+		fill(&transaction);
+		transaction.compute_checksum()
+	}
+
+	#[test]
+	fn checksum_tells_operations_apart() {
+		assert!(checksum(|t| t.put(b"ab", b"c")) != checksum(|t| t.put(b"a", b"bc")));
+		assert!(checksum(|t| t.put(b"k", b"")) != checksum(|t| t.delete(b"k")));
+		assert!(checksum(|t| { t.delete(b"a"); t.delete(b"b"); }) != checksum(|t| t.delete(b"ab")));
+		assert_eq!(checksum(|t| t.put(b"a", b"bc")), checksum(|t| t.put(b"a", b"bc")));
+	}
+
+	#[test]
+	fn classifies_rocksdb_errors() {
+		match Error::from("Corruption: block checksum mismatch".to_owned()) {
+			Error::Corruption(_) => {},
+			e => panic!("Unexpected error: {:?}", e),
+		}
+		match Error::from("IO error: No space left on device".to_owned()) {
+			Error::IoError(_) => {},
+			e => panic!("Unexpected error: {:?}", e),
+		}
+		match Error::from("NotFound: /tmp/db/000012.sst".to_owned()) {
+			Error::NotFound(_) => {},
+			e => panic!("Unexpected error: {:?}", e),
+		}
+		match Error::from("Invalid argument: /tmp/db/: does not exist (create_if_missing is false)".to_owned()) {
+			Error::NotFound(_) => {},
+			e => panic!("Unexpected error: {:?}", e),
+		}
+		match Error::from("Invalid argument: Column family not found".to_owned()) {
+			Error::Other(ref s) if s == "Invalid argument: Column family not found" => {},
+			e => panic!("Unexpected error: {:?}", e),
+		}
+	}
+}
@@ -89,6 +89,9 @@ impl Default for MinerOptions {
 	}
 }
 
+/// Number of recent blocks the gas price percentile is taken over.
+const GAS_PRICE_PERCENTILE_BLOCKS: usize = 100;
+
 /// Options for the dynamic gas price recalibrator.
 #[derive(Debug, PartialEq)]
 pub struct GasPriceCalibratorOptions {
@@ -182,6 +185,7 @@ pub struct Miner {
 	accounts: Option<Arc<AccountProvider>>,
 	work_poster: Option<WorkPoster>,
 	gas_pricer: Mutex<GasPricer>,
+	gas_price_percentile: RwLock<Option<u8>>,
 }
 
 impl Miner {
@@ -200,6 +204,7 @@ impl Miner {
 			engine: spec.engine.clone(),
 			work_poster: None,
 			gas_pricer: Mutex::new(GasPricer::new_fixed(20_000_000_000u64.into())),
+			gas_price_percentile: RwLock::new(None),
 		}
 	}
 
@@ -220,9 +225,27 @@ impl Miner {
 			engine: spec.engine.clone(),
 			work_poster: work_poster,
 			gas_pricer: Mutex::new(gas_pricer),
+			gas_price_percentile: RwLock::new(None),
 		})
 	}
 
+	/// Set the minimal gas price to the tracked percentile of the gas prices in recent blocks, if any.
+	fn update_gas_price_from_percentile(&self, chain: &MiningBlockChainClient) {
+		let percentile = match *self.gas_price_percentile.read() {
+			Some(percentile) => percentile,
+			None => return,
+		};
+		// distribution of 100 parts yields the price at every percentile
+		match chain.gas_price_statistics(GAS_PRICE_PERCENTILE_BLOCKS, 100) {
+			Ok(prices) => {
+				let price = prices[percentile as usize];
+				trace!(target: "miner", "Setting minimal gas price to {}, percentile {}", price, percentile);
+				self.transaction_queue.lock().set_minimal_gas_price(price);
+			},
+			Err(_) => trace!(target: "miner", "No recent transactions to take the gas price percentile of"),
+		}
+	}
+
 	fn forced_sealing(&self) -> bool {
 		self.options.force_sealing || !self.options.new_work_notify.is_empty()
 	}
@@ -248,7 +271,8 @@ impl Miner {
 	fn prepare_sealing(&self, chain: &MiningBlockChainClient) {
 		trace!(target: "miner", "prepare_sealing: entering");
 
-		{
+		// the gas price percentile is tracked as new blocks arrive instead
+		if self.gas_price_percentile.read().is_none() {
 			trace!(target: "miner", "recalibrating...");
 			let txq = self.transaction_queue.clone();
 			self.gas_pricer.lock().recalibrate(move |price| {
@@ -554,9 +578,18 @@ impl MinerService for Miner {
 	}
 
 	fn set_minimal_gas_price(&self, min_gas_price: U256) {
+		*self.gas_price_percentile.write() = None;
 		self.transaction_queue.lock().set_minimal_gas_price(min_gas_price);
 	}
 
+	fn set_gas_price_percentile(&self, percentile: u8) {
+		*self.gas_price_percentile.write() = Some(::std::cmp::min(percentile, 100));
+	}
+
+	fn gas_price_percentile(&self) -> Option<u8> {
+		*self.gas_price_percentile.read()
+	}
+
 	fn minimal_gas_price(&self) -> U256 {
 		*self.transaction_queue.lock().minimal_gas_price()
 	}
@@ -863,8 +896,9 @@ impl MinerService for Miner {
 		// 2. We ignore blocks that are `invalid` because it doesn't have any meaning in terms of the transactions that
 		//    are in those blocks
 
-		// First update gas limit in transaction queue
+		// First update gas limit and price in transaction queue
 		self.update_gas_limit(chain);
+		self.update_gas_price_from_percentile(chain);
 
 		// Then import all transactions...
 		{
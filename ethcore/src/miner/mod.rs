@@ -82,8 +82,17 @@ pub trait MinerService : Send + Sync {
 	fn minimal_gas_price(&self) -> U256;
 
 	/// Set minimal gas price of transaction to be accepted for mining.
+	/// Stops tracking the gas price percentile, if set.
 	fn set_minimal_gas_price(&self, min_gas_price: U256);
 
+	/// Track the given percentile (0 to 100) of the gas prices in recent blocks as the minimal gas price,
+	/// recomputed whenever new blocks are imported. Overrides the fixed minimal gas price
+	/// until `set_minimal_gas_price` is called.
+	fn set_gas_price_percentile(&self, percentile: u8);
+
+	/// Get the tracked gas price percentile, `None` if the minimal gas price is fixed.
+	fn gas_price_percentile(&self) -> Option<u8>;
+
 	/// Get the lower bound of the gas limit we wish to target when sealing a new block.
 	fn gas_floor_target(&self) -> U256;
 
@@ -22,9 +22,10 @@ use spec::Spec;
 use error::*;
 use client::{Client, ClientConfig, ChainNotify};
 use miner::Miner;
-use snapshot::ManifestData;
-use snapshot::service::{Service as SnapshotService, ServiceParams as SnapServiceParams};
+use snapshot::{ManifestData, ChunkSlot};
+use snapshot::service::{Service as SnapshotService, ServiceParams as SnapServiceParams, DEFAULT_MAX_CHUNK_FAILURES, DEFAULT_RESTORE_IDLE_TIMEOUT_SECS};
 use std::sync::atomic::AtomicBool;
+use std::time::Duration;
 
 #[cfg(feature="ipc")]
 use nanoipc;
@@ -42,10 +43,10 @@ pub enum ClientIoMessage {
 	NewTransactions(Vec<Bytes>),
 	/// Begin snapshot restoration
 	BeginRestoration(ManifestData),
-	/// Feed a state chunk to the snapshot service
-	FeedStateChunk(H256, Bytes),
-	/// Feed a block chunk to the snapshot service
-	FeedBlockChunk(H256, Bytes),
+	/// Feed a state chunk to the snapshot service, holding its place in the chunk queue
+	FeedStateChunk(H256, Bytes, ChunkSlot),
+	/// Feed a block chunk to the snapshot service, holding its place in the chunk queue
+	FeedBlockChunk(H256, Bytes, ChunkSlot),
 	/// Take a snapshot for the block with given number.
 	TakeSnapshot(u64),
 }
@@ -96,6 +97,9 @@ impl ClientService {
 			snapshot_root: snapshot_path.into(),
 			client_db: client_path.into(),
 			db_restore: client.clone(),
+			max_restore_threads: ::num_cpus::get(),
+			max_chunk_failures: DEFAULT_MAX_CHUNK_FAILURES,
+			restore_idle_timeout: Duration::from_secs(DEFAULT_RESTORE_IDLE_TIMEOUT_SECS),
 		};
 		let snapshot = Arc::new(try!(SnapshotService::new(snapshot_params)));
 
@@ -191,8 +195,8 @@ impl IoHandler<ClientIoMessage> for ClientIoHandler {
 					warn!("Failed to initialize snapshot restoration: {}", e);
 				}
 			}
-			ClientIoMessage::FeedStateChunk(ref hash, ref chunk) => self.snapshot.feed_state_chunk(*hash, chunk),
-			ClientIoMessage::FeedBlockChunk(ref hash, ref chunk) => self.snapshot.feed_block_chunk(*hash, chunk),
+			ClientIoMessage::FeedStateChunk(ref hash, ref chunk, _) => self.snapshot.feed_state_chunk(*hash, chunk),
+			ClientIoMessage::FeedBlockChunk(ref hash, ref chunk, _) => self.snapshot.feed_block_chunk(*hash, chunk),
 			ClientIoMessage::TakeSnapshot(num) => {
 				if let Err(e) = self.snapshot.take_snapshot(&*self.client, num) {
 					warn!("Failed to take snapshot at block #{}: {}", num, e);
@@ -45,6 +45,10 @@ pub enum Error {
 	Decoder(DecoderError),
 	/// Io error.
 	Io(::std::io::Error),
+	/// Manifest cannot be used for restoration.
+	InvalidManifest(&'static str),
+	/// Chunk with the given hash failed verification too many times.
+	TooManyChunkFailures(H256),
 }
 
 impl fmt::Display for Error {
@@ -57,6 +61,8 @@ impl fmt::Display for Error {
 				a pruned database. Please re-run with the --pruning archive flag."),
 			Error::MissingCode(ref missing) => write!(f, "Incomplete snapshot: {} contract codes not found.", missing.len()),
 			Error::UnrecognizedCodeState(state) => write!(f, "Unrecognized code encoding ({})", state),
+			Error::InvalidManifest(reason) => write!(f, "Invalid snapshot manifest: {}", reason),
+			Error::TooManyChunkFailures(ref hash) => write!(f, "Chunk {} failed verification too many times", hash),
 			Error::Io(ref err) => err.fmt(f),
 			Error::Decoder(ref err) => err.fmt(f),
 			Error::Trie(ref err) => err.fmt(f),
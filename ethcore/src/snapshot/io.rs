@@ -263,6 +263,8 @@ impl PackedReader {
 		let manifest = ManifestData {
 			state_hashes: state.iter().map(|c| c.0).collect(),
 			block_hashes: blocks.iter().map(|c| c.0).collect(),
+			state_chunk_sizes: state.iter().map(|c| c.1).collect(),
+			block_chunk_sizes: blocks.iter().map(|c| c.1).collect(),
 			state_root: try!(rlp.val_at(2)),
 			block_number: try!(rlp.val_at(3)),
 			block_hash: try!(rlp.val_at(4)),
@@ -376,6 +378,8 @@ mod tests {
 		let manifest = ManifestData {
 			state_hashes: state_hashes,
 			block_hashes: block_hashes,
+			state_chunk_sizes: STATE_CHUNKS.iter().map(|c| c.len() as u64).collect(),
+			block_chunk_sizes: BLOCK_CHUNKS.iter().map(|c| c.len() as u64).collect(),
 			state_root: b"notarealroot".sha3(),
 			block_number: 12345678987654321,
 			block_hash: b"notarealblock".sha3(),
@@ -414,6 +418,8 @@ mod tests {
 		let manifest = ManifestData {
 			state_hashes: state_hashes,
 			block_hashes: block_hashes,
+			state_chunk_sizes: STATE_CHUNKS.iter().map(|c| c.len() as u64).collect(),
+			block_chunk_sizes: BLOCK_CHUNKS.iter().map(|c| c.len() as u64).collect(),
 			state_root: b"notarealroot".sha3(),
 			block_number: 12345678987654321,
 			block_hash: b"notarealblock".sha3(),
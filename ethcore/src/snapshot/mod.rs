@@ -45,11 +45,11 @@ use rand::{Rng, OsRng};
 
 pub use self::error::Error;
 
-pub use self::service::{Service, DatabaseRestore};
+pub use self::service::{Service, DatabaseRestore, RestorationEvent, ChunkSlot};
 pub use self::traits::{SnapshotService, RemoteSnapshotService};
 pub use self::watcher::Watcher;
 pub use types::snapshot_manifest::ManifestData;
-pub use types::restoration_status::RestorationStatus;
+pub use types::restoration_status::{RestorationStatus, RejectedChunk};
 
 pub mod io;
 pub mod service;
@@ -123,12 +123,12 @@ pub fn take_snapshot<W: SnapshotWriter + Send>(
 	info!("Taking snapshot starting at block {}", number);
 
 	let writer = Mutex::new(writer);
-	let (state_hashes, block_hashes) = try!(scope(|scope| {
+	let ((state_hashes, state_chunk_sizes), (block_hashes, block_chunk_sizes)) = try!(scope(|scope| {
 		let block_guard = scope.spawn(|| chunk_blocks(chain, (number, block_at), &writer, p));
 		let state_res = chunk_state(state_db, state_root, &writer, p);
 
-		state_res.and_then(|state_hashes| {
-			block_guard.join().map(|block_hashes| (state_hashes, block_hashes))
+		state_res.and_then(|state_chunks| {
+			block_guard.join().map(|block_chunks| (state_chunks, block_chunks))
 		})
 	}));
 
@@ -137,6 +137,8 @@ pub fn take_snapshot<W: SnapshotWriter + Send>(
 	let manifest_data = ManifestData {
 		state_hashes: state_hashes,
 		block_hashes: block_hashes,
+		state_chunk_sizes: state_chunk_sizes,
+		block_chunk_sizes: block_chunk_sizes,
 		state_root: *state_root,
 		block_number: number,
 		block_hash: block_at,
@@ -156,6 +158,7 @@ struct BlockChunker<'a> {
 	rlps: VecDeque<Bytes>,
 	current_hash: H256,
 	hashes: Vec<H256>,
+	sizes: Vec<u64>,
 	snappy_buffer: Vec<u8>,
 	writer: &'a Mutex<SnapshotWriter + 'a>,
 	progress: &'a Progress,
@@ -242,17 +245,18 @@ impl<'a> BlockChunker<'a> {
 		self.progress.blocks.fetch_add(num_entries, Ordering::SeqCst);
 
 		self.hashes.push(hash);
+		self.sizes.push(size as u64);
 		Ok(())
 	}
 }
 
 /// Create and write out all block chunks to disk, returning a vector of all
-/// the hashes of block chunks created.
+/// the hashes of block chunks created, along with their sizes.
 ///
 /// The path parameter is the directory to store the block chunks in.
 /// This function assumes the directory exists already.
 /// Returns a list of chunk hashes, with the first having the blocks furthest from the genesis.
-pub fn chunk_blocks<'a>(chain: &'a BlockChain, start_block_info: (u64, H256), writer: &Mutex<SnapshotWriter + 'a>, progress: &'a Progress) -> Result<Vec<H256>, Error> {
+pub fn chunk_blocks<'a>(chain: &'a BlockChain, start_block_info: (u64, H256), writer: &Mutex<SnapshotWriter + 'a>, progress: &'a Progress) -> Result<(Vec<H256>, Vec<u64>), Error> {
 	let (start_number, start_hash) = start_block_info;
 
 	let first_hash = if start_number < SNAPSHOT_BLOCKS {
@@ -268,6 +272,7 @@ pub fn chunk_blocks<'a>(chain: &'a BlockChain, start_block_info: (u64, H256), wr
 		rlps: VecDeque::new(),
 		current_hash: start_hash,
 		hashes: Vec::new(),
+		sizes: Vec::new(),
 		snappy_buffer: vec![0; snappy::max_compressed_len(PREFERRED_CHUNK_SIZE)],
 		writer: writer,
 		progress: progress,
@@ -275,12 +280,13 @@ pub fn chunk_blocks<'a>(chain: &'a BlockChain, start_block_info: (u64, H256), wr
 
 	try!(chunker.chunk_all(first_hash));
 
-	Ok(chunker.hashes)
+	Ok((chunker.hashes, chunker.sizes))
 }
 
 /// State trie chunker.
 struct StateChunker<'a> {
 	hashes: Vec<H256>,
+	sizes: Vec<u64>,
 	rlps: Vec<Bytes>,
 	cur_size: usize,
 	snappy_buffer: Vec<u8>,
@@ -332,6 +338,7 @@ impl<'a> StateChunker<'a> {
 		self.progress.size.fetch_add(compressed_size, Ordering::SeqCst);
 
 		self.hashes.push(hash);
+		self.sizes.push(compressed_size as u64);
 		self.cur_size = 0;
 
 		Ok(())
@@ -341,13 +348,14 @@ impl<'a> StateChunker<'a> {
 /// Walk the given state database starting from the given root,
 /// creating chunks and writing them out.
 ///
-/// Returns a list of hashes of chunks created, or any error it may
+/// Returns a list of hashes of chunks created along with their sizes, or any error it may
 /// have encountered.
-pub fn chunk_state<'a>(db: &HashDB, root: &H256, writer: &Mutex<SnapshotWriter + 'a>, progress: &'a Progress) -> Result<Vec<H256>, Error> {
+pub fn chunk_state<'a>(db: &HashDB, root: &H256, writer: &Mutex<SnapshotWriter + 'a>, progress: &'a Progress) -> Result<(Vec<H256>, Vec<u64>), Error> {
 	let account_trie = try!(TrieDB::new(db, &root));
 
 	let mut chunker = StateChunker {
 		hashes: Vec::new(),
+		sizes: Vec::new(),
 		rlps: Vec::new(),
 		cur_size: 0,
 		snappy_buffer: vec![0; snappy::max_compressed_len(PREFERRED_CHUNK_SIZE)],
@@ -373,7 +381,7 @@ pub fn chunk_state<'a>(db: &HashDB, root: &H256, writer: &Mutex<SnapshotWriter +
 		try!(chunker.write_chunk());
 	}
 
-	Ok(chunker.hashes)
+	Ok((chunker.hashes, chunker.sizes))
 }
 
 /// Used to rebuild the state trie piece by piece.
@@ -16,14 +16,17 @@
 
 //! Snapshot network service implementation.
 
-use std::collections::HashSet;
+use std::cmp::max;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::ErrorKind;
 use std::fs;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::mpsc::{channel, Sender, Receiver};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-use super::{ManifestData, StateRebuilder, BlockRebuilder, RestorationStatus, SnapshotService};
+use super::{ManifestData, StateRebuilder, BlockRebuilder, RestorationStatus, RejectedChunk, SnapshotService, Error as SnapshotError};
 use super::io::{SnapshotReader, LooseReader, SnapshotWriter, LooseWriter};
 
 use blockchain::BlockChain;
@@ -35,11 +38,45 @@ use service::ClientIoMessage;
 
 use io::IoChannel;
 
-use util::{Bytes, H256, Mutex, RwLock, UtilError};
+use util::{Bytes, H256, Hashable, Mutex, Condvar, RwLock, UtilError};
 use util::journaldb::Algorithm;
 use util::kvdb::{Database, DatabaseConfig};
 use util::snappy;
 
+/// Maximum number of restoration chunks queued for processing at once.
+const MAX_PENDING_CHUNKS: usize = 64;
+
+/// Number of most recently rejected chunks remembered.
+const MAX_REJECTED_CHUNKS: usize = 256;
+
+/// Default number of times a chunk may fail verification before the restoration fails.
+pub const DEFAULT_MAX_CHUNK_FAILURES: usize = 5;
+
+/// Default number of seconds a restoration may go without accepting a chunk before it fails.
+pub const DEFAULT_RESTORE_IDLE_TIMEOUT_SECS: u64 = 300;
+
+/// Checks that the manifest describes a restoration which is able to complete.
+/// Identical chunks share the hash, but a single chunk can't be valid as both state and block data,
+/// so manifests listing a hash more than once are refused rather than deduplicated.
+fn validate_manifest(manifest: &ManifestData) -> Result<(), SnapshotError> {
+	if manifest.state_hashes.is_empty() {
+		return Err(SnapshotError::InvalidManifest("no state chunks"));
+	}
+
+	if manifest.block_hash.is_zero() {
+		return Err(SnapshotError::InvalidManifest("zero block hash"));
+	}
+
+	let mut seen = HashSet::new();
+	for hash in manifest.state_hashes.iter().chain(manifest.block_hashes.iter()) {
+		if !seen.insert(*hash) {
+			return Err(SnapshotError::InvalidManifest("duplicate chunk hash"));
+		}
+	}
+
+	Ok(())
+}
+
 /// Helper for removing directories in case of error.
 struct Guard(bool, PathBuf);
 
@@ -71,7 +108,6 @@ struct Restoration {
 	state: StateRebuilder,
 	blocks: BlockRebuilder,
 	writer: LooseWriter,
-	snappy_buffer: Bytes,
 	final_state_root: H256,
 	guard: Guard,
 }
@@ -108,34 +144,53 @@ impl Restoration {
 			state: StateRebuilder::new(raw_db, params.pruning),
 			blocks: blocks,
 			writer: params.writer,
-			snappy_buffer: Vec::new(),
 			final_state_root: root,
 			guard: params.guard,
 		})
 	}
 
-	// feeds a state chunk
-	fn feed_state(&mut self, hash: H256, chunk: &[u8]) -> Result<(), Error> {
-		if self.state_chunks_left.remove(&hash) {
-			let len = try!(snappy::decompress_into(chunk, &mut self.snappy_buffer));
-
-			try!(self.state.feed(&self.snappy_buffer[..len]));
-			try!(self.writer.write_state_chunk(hash, chunk));
+	// whether the chunk is still waiting to be fed.
+	fn expects(&self, hash: &H256, is_state: bool) -> bool {
+		match is_state {
+			true => self.state_chunks_left.contains(hash),
+			false => self.block_chunks_left.contains(hash),
 		}
+	}
 
-		Ok(())
+	// feeds a state chunk along with its decompressed contents, returns false if it wasn't expected.
+	fn feed_state(&mut self, hash: H256, chunk: &[u8], raw: &[u8]) -> Result<bool, Error> {
+		if !self.state_chunks_left.remove(&hash) { return Ok(false) }
+
+		try!(self.state.feed(raw));
+		try!(self.writer.write_state_chunk(hash, chunk));
+
+		Ok(true)
 	}
 
-	// feeds a block chunk
-	fn feed_blocks(&mut self, hash: H256, chunk: &[u8], engine: &Engine) -> Result<(), Error> {
-		if self.block_chunks_left.remove(&hash) {
-			let len = try!(snappy::decompress_into(chunk, &mut self.snappy_buffer));
+	// feeds a block chunk along with its decompressed contents, returns false if it wasn't expected.
+	fn feed_blocks(&mut self, hash: H256, chunk: &[u8], raw: &[u8], engine: &Engine) -> Result<bool, Error> {
+		if !self.block_chunks_left.remove(&hash) { return Ok(false) }
 
-			try!(self.blocks.feed(&self.snappy_buffer[..len], engine));
-			try!(self.writer.write_block_chunk(hash, chunk));
-		}
+		try!(self.blocks.feed(raw, engine));
+		try!(self.writer.write_block_chunk(hash, chunk));
 
-		Ok(())
+		Ok(true)
+	}
+
+	// why an unexpected chunk was not fed.
+	fn rejection_reason(&self, hash: &H256, is_state: bool) -> &'static str {
+		let (same, other) = match is_state {
+			true => (&self.manifest.state_hashes, &self.manifest.block_hashes),
+			false => (&self.manifest.block_hashes, &self.manifest.state_hashes),
+		};
+
+		if same.contains(hash) {
+			"already restored"
+		} else if other.contains(hash) {
+			"wrong chunk kind"
+		} else {
+			"not in manifest"
+		}
 	}
 
 	// finish up restoration.
@@ -192,6 +247,92 @@ pub struct ServiceParams {
 	pub client_db: PathBuf,
 	/// A handle for database restoration.
 	pub db_restore: Arc<DatabaseRestore>,
+	/// Maximal number of chunks restored at the same time, usually the number of CPUs.
+	/// With 1 the chunks are restored one after another.
+	pub max_restore_threads: usize,
+	/// Number of times a chunk may fail verification before the restoration fails.
+	/// Any successfully restored chunk resets the counts.
+	pub max_chunk_failures: usize,
+	/// Time a restoration may go without accepting any chunk, e.g. because of a stalled peer.
+	/// Checked on every tick, a restoration idle for longer fails.
+	pub restore_idle_timeout: Duration,
+}
+
+// a slot in the bounded set of chunks being restored, freed on drop.
+struct RestoreSlot<'a>(&'a Service);
+
+impl<'a> Drop for RestoreSlot<'a> {
+	fn drop(&mut self) {
+		let mut restoring = self.0.restoring.lock();
+		*restoring -= 1;
+		self.0.restore_slot_freed.notify_one();
+	}
+}
+
+// number of chunks queued for feeding in the current restoration attempt.
+struct PendingChunks {
+	// bumped on abort, so slots taken before it don't free the new attempt's ones.
+	generation: usize,
+	count: usize,
+}
+
+// the bounded queue of chunks sent over the io channel but not fed yet.
+struct ChunkQueue {
+	pending: Mutex<PendingChunks>,
+	slot_freed: Condvar,
+}
+
+impl ChunkQueue {
+	fn new() -> Self {
+		ChunkQueue {
+			pending: Mutex::new(PendingChunks { generation: 0, count: 0 }),
+			slot_freed: Condvar::new(),
+		}
+	}
+
+	// forget all the queued chunks, waking up anyone waiting for room.
+	fn reset(&self) {
+		let mut pending = self.pending.lock();
+		pending.generation += 1;
+		pending.count = 0;
+		self.slot_freed.notify_all();
+	}
+}
+
+struct ChunkSlotInner {
+	queue: Arc<ChunkQueue>,
+	generation: usize,
+}
+
+impl Drop for ChunkSlotInner {
+	fn drop(&mut self) {
+		let mut pending = self.queue.pending.lock();
+		if pending.generation == self.generation {
+			pending.count -= 1;
+			self.queue.slot_freed.notify_one();
+		}
+	}
+}
+
+/// A place in the queue of chunks waiting to be fed, taken by `restore_state_chunk` and
+/// `restore_block_chunk`. It travels with the chunk in its `ClientIoMessage` and is freed
+/// once the last copy of the message is dropped, whether the chunk was fed or not.
+#[derive(Clone)]
+pub struct ChunkSlot(Arc<ChunkSlotInner>);
+
+/// Restoration progress, as sent to the subscribers of `Service::subscribe`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RestorationEvent {
+	/// A restoration was initialized.
+	Started,
+	/// A chunk with the given hash was fed to the restoration.
+	ChunkAccepted(H256),
+	/// A chunk with the given hash was rejected for the given reason.
+	ChunkRejected(H256, String),
+	/// All the chunks were fed and the restored databases replaced the old ones.
+	Completed,
+	/// The restoration was given up.
+	Failed,
 }
 
 /// `SnapshotService` implementation.
@@ -209,9 +350,23 @@ pub struct Service {
 	genesis_block: Bytes,
 	state_chunks: AtomicUsize,
 	block_chunks: AtomicUsize,
+	bytes_done: AtomicUsize,
 	db_restore: Arc<DatabaseRestore>,
 	progress: super::Progress,
 	taking_snapshot: AtomicBool,
+	chunk_queue: Arc<ChunkQueue>,
+	rejected_chunks: Mutex<VecDeque<RejectedChunk>>,
+	max_restore_threads: usize,
+	restoring: Mutex<usize>,
+	restore_slot_freed: Condvar,
+	completion_listeners: Mutex<Vec<Sender<()>>>,
+	event_listeners: Mutex<Vec<Sender<RestorationEvent>>>,
+	max_chunk_failures: usize,
+	chunk_failures: Mutex<HashMap<H256, usize>>,
+	restore_idle_timeout: Duration,
+	// when the restoration was initialized or last accepted a chunk.
+	last_restore_progress: Mutex<Option<Instant>>,
+	clock: Box<Fn() -> Instant + Send + Sync>,
 }
 
 impl Service {
@@ -230,9 +385,22 @@ impl Service {
 			genesis_block: params.genesis_block,
 			state_chunks: AtomicUsize::new(0),
 			block_chunks: AtomicUsize::new(0),
+			bytes_done: AtomicUsize::new(0),
 			db_restore: params.db_restore,
 			progress: Default::default(),
 			taking_snapshot: AtomicBool::new(false),
+			chunk_queue: Arc::new(ChunkQueue::new()),
+			rejected_chunks: Mutex::new(VecDeque::new()),
+			max_restore_threads: max(params.max_restore_threads, 1),
+			restoring: Mutex::new(0),
+			restore_slot_freed: Condvar::new(),
+			completion_listeners: Mutex::new(Vec::new()),
+			event_listeners: Mutex::new(Vec::new()),
+			max_chunk_failures: max(params.max_chunk_failures, 1),
+			chunk_failures: Mutex::new(HashMap::new()),
+			restore_idle_timeout: params.restore_idle_timeout,
+			last_restore_progress: Mutex::new(None),
+			clock: Box::new(Instant::now),
 		};
 
 		// create the root snapshot dir if it doesn't exist.
@@ -262,6 +430,12 @@ impl Service {
 		Ok(service)
 	}
 
+	// replace the clock the restoration timeout is measured with.
+	#[cfg(test)]
+	fn set_clock<F>(&mut self, clock: F) where F: Fn() -> Instant + Send + Sync + 'static {
+		self.clock = Box::new(clock);
+	}
+
 	// get the current snapshot dir.
 	fn snapshot_dir(&self) -> PathBuf {
 		let mut dir = self.snapshot_root.clone();
@@ -309,12 +483,33 @@ impl Service {
 	/// Tick the snapshot service. This will log any active snapshot
 	/// being taken.
 	pub fn tick(&self) {
+		self.check_restore_timeout();
+
 		if self.progress.done() || !self.taking_snapshot.load(Ordering::SeqCst) { return }
 
 		let p = &self.progress;
 		info!("Snapshot: {} accounts {} blocks {} bytes", p.accounts(), p.blocks(), p.size());
 	}
 
+	// fail the restoration if it hasn't accepted any chunk for too long.
+	fn check_restore_timeout(&self) {
+		let mut restoration = self.restoration.lock();
+		if restoration.is_none() { return }
+
+		let idle_since = match *self.last_restore_progress.lock() {
+			Some(instant) => instant,
+			None => return,
+		};
+		if (self.clock)() < idle_since + self.restore_idle_timeout { return }
+
+		warn!("Snapshot restoration timed out: no chunk accepted within {:?}", self.restore_idle_timeout);
+		*restoration = None;
+		*self.last_restore_progress.lock() = None;
+		*self.status.lock() = RestorationStatus::Failed;
+		let _ = fs::remove_dir_all(self.restoration_dir());
+		self.notify(RestorationEvent::Failed);
+	}
+
 	/// Take a snapshot at the block with the given number.
 	/// calling this while a restoration is in progress or vice versa
 	/// will lead to a race condition where the first one to finish will
@@ -364,6 +559,13 @@ impl Service {
 
 		// tear down existing restoration.
 		*res = None;
+		self.chunk_failures.lock().clear();
+
+		if let Err(e) = validate_manifest(&manifest) {
+			*self.status.lock() = RestorationStatus::Failed;
+			self.notify(RestorationEvent::Failed);
+			return Err(e.into());
+		}
 
 		// delete and restore the restoration dir.
 		if let Err(e) = fs::remove_dir_all(&rest_dir) {
@@ -389,12 +591,19 @@ impl Service {
 		};
 
 		*res = Some(try!(Restoration::new(params)));
+		*self.last_restore_progress.lock() = Some((self.clock)());
+
+		*self.status.lock() = self.ongoing_status();
+		self.notify(RestorationEvent::Started);
+		Ok(())
+	}
 
-		*self.status.lock() = RestorationStatus::Ongoing {
+	fn ongoing_status(&self) -> RestorationStatus {
+		RestorationStatus::Ongoing {
 			state_chunks_done: self.state_chunks.load(Ordering::Relaxed) as u32,
 			block_chunks_done: self.block_chunks.load(Ordering::Relaxed) as u32,
-		};
-		Ok(())
+			bytes_done: self.bytes_done.load(Ordering::Relaxed) as u64,
+		}
 	}
 
 	// finalize the restoration. this accepts an already-locked
@@ -405,6 +614,7 @@ impl Service {
 
 		self.state_chunks.store(0, Ordering::SeqCst);
 		self.block_chunks.store(0, Ordering::SeqCst);
+		self.bytes_done.store(0, Ordering::SeqCst);
 
 		// destroy the restoration before replacing databases and snapshot.
 		try!(rest.take().map(Restoration::finalize).unwrap_or(Ok(())));
@@ -434,12 +644,49 @@ impl Service {
 
 		*self.status.lock() = RestorationStatus::Inactive;
 
+		for listener in self.completion_listeners.lock().drain(..) {
+			let _ = listener.send(());
+		}
+		self.notify(RestorationEvent::Completed);
+
 		Ok(())
 	}
 
+	/// Get notified once a restoration completes successfully.
+	pub fn on_complete(&self) -> Receiver<()> {
+		let (tx, rx) = channel();
+		self.completion_listeners.lock().push(tx);
+		rx
+	}
+
+	/// Get every restoration event from now on, in the order they happen.
+	/// Each call returns a separate receiver getting all the events.
+	pub fn subscribe(&self) -> Receiver<RestorationEvent> {
+		let (tx, rx) = channel();
+		self.event_listeners.lock().push(tx);
+		rx
+	}
+
+	// send the event to all the subscribers, forgetting the ones which are gone.
+	fn notify(&self, event: RestorationEvent) {
+		self.event_listeners.lock().retain(|listener| listener.send(event.clone()).is_ok());
+	}
+
 	/// Feed a chunk of either kind. no-op if no restoration or status is wrong.
 	fn feed_chunk(&self, hash: H256, chunk: &[u8], is_state: bool) -> Result<(), Error> {
-		// TODO: be able to process block chunks and state chunks at same time?
+		// decompress outside of the restoration lock, at most `max_restore_threads` chunks at once.
+		let _slot = self.reserve_restore_slot();
+
+		let expected = self.restoration.lock().as_ref().map_or(false, |r| r.expects(&hash, is_state));
+		if expected && chunk.sha3() != hash {
+			return self.chunk_failed(hash);
+		}
+
+		let raw = match expected {
+			true => Some(try!(snappy::decompress(chunk))),
+			false => None,
+		};
+
 		let mut restoration = self.restoration.lock();
 
 		match self.status() {
@@ -451,30 +698,88 @@ impl Service {
 						None => return Ok(()),
 					};
 
-					match is_state {
-						true => rest.feed_state(hash, chunk),
-						false => rest.feed_blocks(hash, chunk, &*self.engine),
-					}.map(|_| rest.is_done())
+					match raw {
+						// the restoration was replaced after the chunk was checked, so it was never verified against this one.
+						None if rest.expects(&hash, is_state) => Ok(Err("restoration restarted")),
+						None => Ok(Err(rest.rejection_reason(&hash, is_state))),
+						Some(ref raw) => {
+							let fed = match is_state {
+								true => rest.feed_state(hash, chunk, raw),
+								false => rest.feed_blocks(hash, chunk, raw, &*self.engine),
+							};
+
+							fed.map(|fed| match fed {
+								true => Ok(rest.is_done()),
+								false => Err(rest.rejection_reason(&hash, is_state)),
+							})
+						}
+					}
 				};
 
 				match res {
-					Ok(is_done) => {
+					Ok(Ok(is_done)) => {
 						match is_state {
 							true => self.state_chunks.fetch_add(1, Ordering::SeqCst),
 							false => self.block_chunks.fetch_add(1, Ordering::SeqCst),
 						};
+						self.bytes_done.fetch_add(chunk.len(), Ordering::SeqCst);
+						*self.status.lock() = self.ongoing_status();
+						self.chunk_failures.lock().clear();
+						*self.last_restore_progress.lock() = Some((self.clock)());
+						self.notify(RestorationEvent::ChunkAccepted(hash));
 
 						match is_done {
 							true => self.finalize_restoration(&mut *restoration),
 							false => Ok(())
 						}
 					}
-					other => other.map(drop),
+					Ok(Err(reason)) => {
+						self.reject_chunk(hash, reason);
+						Ok(())
+					}
+					Err(e) => Err(e),
 				}
 			}
 		}
 	}
 
+	// wait until fewer than `max_restore_threads` chunks are being restored.
+	fn reserve_restore_slot(&self) -> RestoreSlot {
+		let mut restoring = self.restoring.lock();
+		while *restoring >= self.max_restore_threads {
+			self.restore_slot_freed.wait(&mut restoring);
+		}
+		*restoring += 1;
+		RestoreSlot(self)
+	}
+
+	// count a failed verification of the chunk, giving up on the restoration after too many.
+	fn chunk_failed(&self, hash: H256) -> Result<(), Error> {
+		self.reject_chunk(hash, "hash mismatch");
+
+		let mut failures = self.chunk_failures.lock();
+		let count = failures.entry(hash).or_insert(0);
+		*count += 1;
+		match *count >= self.max_chunk_failures {
+			true => Err(SnapshotError::TooManyChunkFailures(hash).into()),
+			false => Ok(()),
+		}
+	}
+
+	// remember a rejected chunk, forgetting the oldest one if there are too many.
+	fn reject_chunk(&self, hash: H256, reason: &str) {
+		trace!(target: "snapshot", "rejected chunk {:?}: {}", hash, reason);
+		let mut rejected = self.rejected_chunks.lock();
+		if rejected.len() >= MAX_REJECTED_CHUNKS {
+			rejected.pop_front();
+		}
+		rejected.push_back(RejectedChunk {
+			hash: hash,
+			reason: reason.into(),
+		});
+		self.notify(RestorationEvent::ChunkRejected(hash, reason.into()));
+	}
+
 	/// Feed a state chunk to be processed synchronously.
 	pub fn feed_state_chunk(&self, hash: H256, chunk: &[u8]) {
 		match self.feed_chunk(hash, chunk, true) {
@@ -484,6 +789,7 @@ impl Service {
 				*self.restoration.lock() = None;
 				*self.status.lock() = RestorationStatus::Failed;
 				let _ = fs::remove_dir_all(self.restoration_dir());
+				self.notify(RestorationEvent::Failed);
 			}
 		}
 	}
@@ -497,9 +803,24 @@ impl Service {
 				*self.restoration.lock() = None;
 				*self.status.lock() = RestorationStatus::Failed;
 				let _ = fs::remove_dir_all(self.restoration_dir());
+				self.notify(RestorationEvent::Failed);
 			}
 		}
 	}
+
+	// wait until there is room for another chunk in the queue.
+	// must not be called from the io thread processing the chunks.
+	fn reserve_chunk_slot(&self) -> ChunkSlot {
+		let mut pending = self.chunk_queue.pending.lock();
+		while pending.count >= MAX_PENDING_CHUNKS {
+			self.chunk_queue.slot_freed.wait(&mut pending);
+		}
+		pending.count += 1;
+		ChunkSlot(Arc::new(ChunkSlotInner {
+			queue: self.chunk_queue.clone(),
+			generation: pending.generation,
+		}))
+	}
 }
 
 impl SnapshotService for Service {
@@ -522,7 +843,9 @@ impl SnapshotService for Service {
 
 	fn abort_restore(&self) {
 		*self.restoration.lock() = None;
+		self.chunk_failures.lock().clear();
 		*self.status.lock() = RestorationStatus::Inactive;
+		self.chunk_queue.reset();
 		if let Err(e) = fs::remove_dir_all(&self.restoration_dir()) {
 			match e.kind() {
 				ErrorKind::NotFound => {},
@@ -532,14 +855,20 @@ impl SnapshotService for Service {
 	}
 
 	fn restore_state_chunk(&self, hash: H256, chunk: Bytes) {
-		self.io_channel.send(ClientIoMessage::FeedStateChunk(hash, chunk))
+		let slot = self.reserve_chunk_slot();
+		self.io_channel.send(ClientIoMessage::FeedStateChunk(hash, chunk, slot))
 			.expect("snapshot service and io service are kept alive by client service; qed");
 	}
 
 	fn restore_block_chunk(&self, hash: H256, chunk: Bytes) {
-		self.io_channel.send(ClientIoMessage::FeedBlockChunk(hash, chunk))
+		let slot = self.reserve_chunk_slot();
+		self.io_channel.send(ClientIoMessage::FeedBlockChunk(hash, chunk, slot))
 			.expect("snapshot service and io service are kept alive by client service; qed");
 	}
+
+	fn rejected_chunks(&self) -> Vec<RejectedChunk> {
+		self.rejected_chunks.lock().iter().cloned().collect()
+	}
 }
 
 impl Drop for Service {
@@ -552,9 +881,13 @@ impl Drop for Service {
 mod tests {
 	use std::sync::Arc;
 	use service::ClientIoMessage;
-	use io::{IoService};
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::thread;
+	use std::time::Duration;
+	use io::{IoService, IoHandler, IoContext};
 	use devtools::RandomTempPath;
 	use tests::helpers::get_test_spec;
+	use util::H256;
 	use util::journaldb::Algorithm;
 	use error::Error;
 	use snapshot::{ManifestData, RestorationStatus, SnapshotService};
@@ -567,27 +900,34 @@ mod tests {
 		}
 	}
 
-	#[test]
-	fn sends_async_messages() {
-		let service = IoService::<ClientIoMessage>::start().unwrap();
+	fn test_params(io: &IoService<ClientIoMessage>, dir: &RandomTempPath) -> ServiceParams {
 		let spec = get_test_spec();
 
-		let dir = RandomTempPath::new();
-		let mut dir = dir.as_path().to_owned();
-		let mut client_db = dir.clone();
-		dir.push("snapshot");
+		let mut snapshot_root = dir.as_path().to_owned();
+		let mut client_db = snapshot_root.clone();
+		snapshot_root.push("snapshot");
 		client_db.push("client");
 
-		let snapshot_params = ServiceParams {
+		ServiceParams {
 			engine: spec.engine.clone(),
 			genesis_block: spec.genesis_block(),
 			db_config: Default::default(),
 			pruning: Algorithm::Archive,
-			channel: service.channel(),
-			snapshot_root: dir,
+			channel: io.channel(),
+			snapshot_root: snapshot_root,
 			client_db: client_db,
 			db_restore: Arc::new(NoopDBRestore),
-		};
+			max_restore_threads: ::num_cpus::get(),
+			max_chunk_failures: super::DEFAULT_MAX_CHUNK_FAILURES,
+			restore_idle_timeout: Duration::from_secs(super::DEFAULT_RESTORE_IDLE_TIMEOUT_SECS),
+		}
+	}
+
+	#[test]
+	fn sends_async_messages() {
+		let service = IoService::<ClientIoMessage>::start().unwrap();
+		let dir = RandomTempPath::new();
+		let snapshot_params = test_params(&service, &dir);
 
 		let service = Service::new(snapshot_params).unwrap();
 
@@ -598,6 +938,8 @@ mod tests {
 		let manifest = ManifestData {
 			state_hashes: vec![],
 			block_hashes: vec![],
+			state_chunk_sizes: Vec::new(),
+			block_chunk_sizes: Vec::new(),
 			state_root: Default::default(),
 			block_number: 0,
 			block_hash: Default::default(),
@@ -608,4 +950,341 @@ mod tests {
 		service.restore_state_chunk(Default::default(), vec![]);
 		service.restore_block_chunk(Default::default(), vec![]);
 	}
-}
\ No newline at end of file
+
+	fn manifest_with_chunks(state_hashes: Vec<H256>, block_hashes: Vec<H256>) -> ManifestData {
+		ManifestData {
+			state_hashes: state_hashes,
+			block_hashes: block_hashes,
+			state_chunk_sizes: Vec::new(),
+			block_chunk_sizes: Vec::new(),
+			state_root: H256::from(1),
+			block_number: 1,
+			block_hash: H256::from(2),
+		}
+	}
+
+	#[test]
+	fn rejects_manifest_without_state_chunks() {
+		let manifest = manifest_with_chunks(vec![], vec![H256::from(3)]);
+		assert!(super::validate_manifest(&manifest).is_err());
+	}
+
+	#[test]
+	fn rejects_manifest_with_zero_block_hash() {
+		let mut manifest = manifest_with_chunks(vec![H256::from(3)], vec![H256::from(4)]);
+		manifest.block_hash = H256::zero();
+		assert!(super::validate_manifest(&manifest).is_err());
+	}
+
+	#[test]
+	fn rejects_manifest_with_duplicate_state_hashes() {
+		let manifest = manifest_with_chunks(vec![H256::from(3), H256::from(3)], vec![H256::from(4)]);
+		assert!(super::validate_manifest(&manifest).is_err());
+	}
+
+	#[test]
+	fn rejects_manifest_with_hashes_duplicated_across_lists() {
+		let manifest = manifest_with_chunks(vec![H256::from(3)], vec![H256::from(4), H256::from(3)]);
+		assert!(super::validate_manifest(&manifest).is_err());
+	}
+
+	#[test]
+	fn accepts_valid_manifest() {
+		let manifest = manifest_with_chunks(vec![H256::from(3), H256::from(4)], vec![H256::from(5)]);
+		assert!(super::validate_manifest(&manifest).is_ok());
+	}
+
+	#[test]
+	fn invalid_manifest_fails_restoration() {
+		let service = IoService::<ClientIoMessage>::start().unwrap();
+		let dir = RandomTempPath::new();
+		let snapshot_params = test_params(&service, &dir);
+
+		let service = Service::new(snapshot_params).unwrap();
+
+		let invalid = manifest_with_chunks(vec![], vec![]);
+		assert!(service.init_restore(invalid).is_err());
+		assert_eq!(service.status(), RestorationStatus::Failed);
+
+		let valid = manifest_with_chunks(vec![H256::from(3)], vec![H256::from(4)]);
+		assert!(service.init_restore(valid).is_ok());
+		assert_eq!(service.status(), RestorationStatus::Ongoing { state_chunks_done: 0, block_chunks_done: 0, bytes_done: 0 });
+	}
+
+	#[test]
+	fn records_rejected_chunks() {
+		let service = IoService::<ClientIoMessage>::start().unwrap();
+		let dir = RandomTempPath::new();
+		let snapshot_params = test_params(&service, &dir);
+
+		let service = Service::new(snapshot_params).unwrap();
+		service.init_restore(manifest_with_chunks(vec![H256::from(3)], vec![H256::from(4)])).unwrap();
+
+		service.feed_state_chunk(H256::from(99), &[]);
+		service.feed_state_chunk(H256::from(4), &[]);
+
+		let rejected = service.rejected_chunks();
+		assert_eq!(rejected.len(), 2);
+		assert_eq!(rejected[0].hash, H256::from(99));
+		assert_eq!(rejected[0].reason, "not in manifest");
+		assert_eq!(rejected[1].hash, H256::from(4));
+		assert_eq!(rejected[1].reason, "wrong chunk kind");
+		assert_eq!(service.status(), RestorationStatus::Ongoing { state_chunks_done: 0, block_chunks_done: 0, bytes_done: 0 });
+	}
+
+	struct ChunkFeeder {
+		service: Arc<Service>,
+		fed: AtomicUsize,
+	}
+
+	impl IoHandler<ClientIoMessage> for ChunkFeeder {
+		fn message(&self, _io: &IoContext<ClientIoMessage>, message: &ClientIoMessage) {
+			match *message {
+				ClientIoMessage::FeedStateChunk(ref hash, ref chunk, _) => self.service.feed_state_chunk(*hash, chunk),
+				ClientIoMessage::FeedBlockChunk(ref hash, ref chunk, _) => self.service.feed_block_chunk(*hash, chunk),
+				_ => return,
+			}
+			self.fed.fetch_add(1, Ordering::SeqCst);
+		}
+	}
+
+	#[test]
+	fn chunk_flood_is_not_dropped() {
+		use rlp::{RlpStream, Stream, EMPTY_LIST_RLP};
+		use util::{Hashable, U256};
+
+		let io_service = IoService::<ClientIoMessage>::start().unwrap();
+		let dir = RandomTempPath::new();
+		let snapshot_params = test_params(&io_service, &dir);
+
+		let service = Arc::new(Service::new(snapshot_params).unwrap());
+		let feeder = Arc::new(ChunkFeeder { service: service.clone(), fed: AtomicUsize::new(0) });
+		io_service.register_handler(feeder.clone()).unwrap();
+
+		// the state chunk is never sent, so the restoration stays open with the fed block chunks.
+		let chunks = super::MAX_PENDING_CHUNKS * 4;
+		let state_chunk = ::util::snappy::compress(&EMPTY_LIST_RLP);
+		let block_chunks: Vec<_> = (1..chunks + 1).map(|i| {
+			let mut stream = RlpStream::new_list(3);
+			stream.append(&(i as u64)).append(&H256::from(i as u64)).append(&U256::zero());
+			::util::snappy::compress(&stream.out())
+		}).collect();
+
+		let manifest = manifest_with_chunks(vec![state_chunk.sha3()], block_chunks.iter().map(|c| c.sha3()).collect());
+		service.init_restore(manifest).unwrap();
+
+		for chunk in block_chunks {
+			service.restore_block_chunk(chunk.sha3(), chunk);
+			assert!(service.chunk_queue.pending.lock().count <= super::MAX_PENDING_CHUNKS);
+		}
+
+		for _ in 0..100 {
+			if feeder.fed.load(Ordering::SeqCst) == chunks { break }
+			thread::sleep(Duration::from_millis(50));
+		}
+
+		assert_eq!(feeder.fed.load(Ordering::SeqCst), chunks);
+		assert_eq!(service.chunk_queue.pending.lock().count, 0);
+		assert!(service.rejected_chunks().is_empty());
+		{
+			let restoration = service.restoration.lock();
+			let restoration = restoration.as_ref().unwrap();
+			assert!(restoration.block_chunks_left.is_empty());
+			assert!(restoration.state_chunks_left.contains(&state_chunk.sha3()));
+		}
+		match service.status() {
+			RestorationStatus::Ongoing { block_chunks_done, .. } => assert_eq!(block_chunks_done as usize, chunks),
+			status => panic!("unexpected status {:?}", status),
+		}
+	}
+
+	#[test]
+	fn abort_frees_queued_chunk_slots() {
+		let io_service = IoService::<ClientIoMessage>::start().unwrap();
+		let dir = RandomTempPath::new();
+		let snapshot_params = test_params(&io_service, &dir);
+
+		let service = Service::new(snapshot_params).unwrap();
+
+		// a dropped slot is freed even though its chunk was never fed.
+		drop(service.reserve_chunk_slot());
+		assert_eq!(service.chunk_queue.pending.lock().count, 0);
+
+		let stale = service.reserve_chunk_slot();
+		let copy = stale.clone();
+		assert_eq!(service.chunk_queue.pending.lock().count, 1);
+
+		service.abort_restore();
+		assert_eq!(service.chunk_queue.pending.lock().count, 0);
+
+		// slots taken before the abort don't free the ones taken after it.
+		let current = service.reserve_chunk_slot();
+		drop(stale);
+		drop(copy);
+		assert_eq!(service.chunk_queue.pending.lock().count, 1);
+		drop(current);
+		assert_eq!(service.chunk_queue.pending.lock().count, 0);
+	}
+
+	#[test]
+	fn restores_all_chunks_with_single_thread() {
+		use rlp::{RlpStream, Stream, EMPTY_LIST_RLP};
+		use util::{Hashable, U256};
+		use util::sha3::SHA3_NULL_RLP;
+
+		let io_service = IoService::<ClientIoMessage>::start().unwrap();
+		let dir = RandomTempPath::new();
+		let snapshot_params = ServiceParams {
+			max_restore_threads: 1,
+			..test_params(&io_service, &dir)
+		};
+
+		let service = Arc::new(Service::new(snapshot_params).unwrap());
+		let feeder = Arc::new(ChunkFeeder { service: service.clone(), fed: AtomicUsize::new(0) });
+		io_service.register_handler(feeder.clone()).unwrap();
+
+		// chunks must match their hashes, block chunks without any blocks can be told apart.
+		let chunks = 16;
+		let state_chunk = ::util::snappy::compress(&EMPTY_LIST_RLP);
+		let block_chunks: Vec<_> = (1..chunks).map(|i| {
+			let mut stream = RlpStream::new_list(3);
+			stream.append(&(i as u64)).append(&H256::from(i as u64)).append(&U256::zero());
+			::util::snappy::compress(&stream.out())
+		}).collect();
+
+		let mut manifest = manifest_with_chunks(vec![state_chunk.sha3()], block_chunks.iter().map(|c| c.sha3()).collect());
+		manifest.state_root = SHA3_NULL_RLP;
+		service.init_restore(manifest.clone()).unwrap();
+		let completed = service.on_complete();
+
+		service.restore_state_chunk(state_chunk.sha3(), state_chunk);
+		for chunk in block_chunks {
+			service.restore_block_chunk(chunk.sha3(), chunk);
+		}
+
+		for _ in 0..100 {
+			if feeder.fed.load(Ordering::SeqCst) == chunks { break }
+			thread::sleep(Duration::from_millis(50));
+		}
+
+		assert_eq!(feeder.fed.load(Ordering::SeqCst), chunks);
+		assert!(service.rejected_chunks().is_empty());
+		assert_eq!(service.status(), RestorationStatus::Inactive);
+		assert_eq!(service.manifest(), Some(manifest));
+		assert!(completed.try_recv().is_ok());
+	}
+
+	#[test]
+	fn sends_restoration_events_to_every_subscriber() {
+		use rlp::{RlpStream, Stream, EMPTY_LIST_RLP};
+		use util::{Hashable, U256};
+		use util::sha3::SHA3_NULL_RLP;
+
+		let service = IoService::<ClientIoMessage>::start().unwrap();
+		let dir = RandomTempPath::new();
+		let snapshot_params = test_params(&service, &dir);
+
+		let service = Service::new(snapshot_params).unwrap();
+		let first = service.subscribe();
+		let second = service.subscribe();
+
+		let state_chunk = ::util::snappy::compress(&EMPTY_LIST_RLP);
+		let block_chunks: Vec<_> = (1..3).map(|i| {
+			let mut stream = RlpStream::new_list(3);
+			stream.append(&(i as u64)).append(&H256::from(i as u64)).append(&U256::zero());
+			::util::snappy::compress(&stream.out())
+		}).collect();
+
+		let mut manifest = manifest_with_chunks(vec![state_chunk.sha3()], block_chunks.iter().map(|c| c.sha3()).collect());
+		manifest.state_root = SHA3_NULL_RLP;
+		service.init_restore(manifest).unwrap();
+
+		service.feed_state_chunk(H256::from(99), &[]);
+		service.feed_state_chunk(state_chunk.sha3(), &state_chunk);
+		for chunk in &block_chunks {
+			service.feed_block_chunk(chunk.sha3(), chunk);
+		}
+		assert_eq!(service.status(), RestorationStatus::Inactive);
+
+		let expected = vec![
+			RestorationEvent::Started,
+			RestorationEvent::ChunkRejected(H256::from(99), "not in manifest".into()),
+			RestorationEvent::ChunkAccepted(state_chunk.sha3()),
+			RestorationEvent::ChunkAccepted(block_chunks[0].sha3()),
+			RestorationEvent::ChunkAccepted(block_chunks[1].sha3()),
+			RestorationEvent::Completed,
+		];
+		let received = |rx: &::std::sync::mpsc::Receiver<RestorationEvent>| {
+			let mut events = Vec::new();
+			while let Ok(event) = rx.try_recv() {
+				events.push(event);
+			}
+			events
+		};
+		assert_eq!(received(&first), expected);
+		assert_eq!(received(&second), expected);
+	}
+
+	#[test]
+	fn fails_after_repeated_chunk_failures() {
+		use rlp::EMPTY_LIST_RLP;
+		use util::Hashable;
+
+		let service = IoService::<ClientIoMessage>::start().unwrap();
+		let dir = RandomTempPath::new();
+		let snapshot_params = ServiceParams {
+			max_chunk_failures: 3,
+			..test_params(&service, &dir)
+		};
+
+		let service = Service::new(snapshot_params).unwrap();
+		let hash = ::util::snappy::compress(&EMPTY_LIST_RLP).sha3();
+		service.init_restore(manifest_with_chunks(vec![hash], vec![H256::from(4)])).unwrap();
+
+		let corrupted = vec![1, 2, 3];
+		service.feed_state_chunk(hash, &corrupted);
+		service.feed_state_chunk(hash, &corrupted);
+		assert_eq!(service.status(), RestorationStatus::Ongoing { state_chunks_done: 0, block_chunks_done: 0, bytes_done: 0 });
+
+		service.feed_state_chunk(hash, &corrupted);
+		assert_eq!(service.status(), RestorationStatus::Failed);
+
+		let rejected = service.rejected_chunks();
+		assert_eq!(rejected.len(), 3);
+		assert!(rejected.iter().all(|r| r.hash == hash && r.reason == "hash mismatch"));
+	}
+
+	#[test]
+	fn fails_restoration_idle_for_too_long() {
+		use std::time::Instant;
+		use rlp::EMPTY_LIST_RLP;
+		use util::{Hashable, Mutex};
+
+		let service = IoService::<ClientIoMessage>::start().unwrap();
+		let dir = RandomTempPath::new();
+		let snapshot_params = ServiceParams {
+			restore_idle_timeout: Duration::from_secs(60),
+			..test_params(&service, &dir)
+		};
+
+		let now = Arc::new(Mutex::new(Instant::now()));
+		let mut service = Service::new(snapshot_params).unwrap();
+		let clock = now.clone();
+		service.set_clock(move || *clock.lock());
+
+		let state_chunk = ::util::snappy::compress(&EMPTY_LIST_RLP);
+		service.init_restore(manifest_with_chunks(vec![state_chunk.sha3()], vec![H256::from(4)])).unwrap();
+
+		// an accepted chunk resets the timer
+		*now.lock() += Duration::from_secs(40);
+		service.tick();
+		service.feed_state_chunk(state_chunk.sha3(), &state_chunk);
+		*now.lock() += Duration::from_secs(40);
+		service.tick();
+		assert_eq!(service.status(), RestorationStatus::Ongoing { state_chunks_done: 1, block_chunks_done: 0, bytes_done: state_chunk.len() as u64 });
+
+		*now.lock() += Duration::from_secs(21);
+		service.tick();
+		assert_eq!(service.status(), RestorationStatus::Failed);
+	}
+}
@@ -14,7 +14,8 @@
 // You should have received a copy of the GNU General Public License
 // along with Parity.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::{ManifestData, RestorationStatus};
+use std::collections::HashSet;
+use super::{ManifestData, RestorationStatus, RejectedChunk};
 use util::{Bytes, H256};
 use ipc::IpcConfig;
 
@@ -49,6 +50,20 @@ pub trait SnapshotService : Sync + Send {
 	/// Feed a raw block chunk to the service to be processed asynchronously.
 	/// no-op if currently restoring.
 	fn restore_block_chunk(&self, hash: H256, chunk: Bytes);
+
+	/// Most recently rejected restoration chunks, oldest first.
+	fn rejected_chunks(&self) -> Vec<RejectedChunk>;
+
+	/// Get the chunks of the `new` manifest which are not part of the `old` one.
+	/// Shared chunks are identical, so only the returned ones need to be fetched and restored.
+	fn delta_from(&self, old: &ManifestData, new: &ManifestData) -> Vec<H256> {
+		let known: HashSet<&H256> = old.state_hashes.iter().chain(old.block_hashes.iter()).collect();
+		new.state_hashes.iter()
+			.chain(new.block_hashes.iter())
+			.filter(|hash| !known.contains(hash))
+			.cloned()
+			.collect()
+	}
 }
 
 impl IpcConfig for SnapshotService { }
@@ -57,10 +57,12 @@ fn chunk_and_restore(amount: u64) {
 
 	// snapshot it.
 	let writer = Mutex::new(PackedWriter::new(&snapshot_path).unwrap());
-	let block_hashes = chunk_blocks(&bc, (amount, best_hash), &writer, &Progress::default()).unwrap();
+	let (block_hashes, block_chunk_sizes) = chunk_blocks(&bc, (amount, best_hash), &writer, &Progress::default()).unwrap();
 	writer.into_inner().finish(::snapshot::ManifestData {
 		state_hashes: Vec::new(),
 		block_hashes: block_hashes,
+		state_chunk_sizes: Vec::new(),
+		block_chunk_sizes: block_chunk_sizes,
 		state_root: Default::default(),
 		block_number: amount,
 		block_hash: best_hash,
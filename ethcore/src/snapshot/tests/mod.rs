@@ -28,6 +28,8 @@ fn manifest_rlp() {
 	let manifest = ManifestData {
 		block_hashes: Vec::new(),
 		state_hashes: Vec::new(),
+		state_chunk_sizes: Vec::new(),
+		block_chunk_sizes: Vec::new(),
 		block_number: 1234567,
 		state_root: Default::default(),
 		block_hash: Default::default(),
@@ -48,11 +48,13 @@ fn snap_and_restore() {
 	let state_root = producer.state_root();
 	let writer = Mutex::new(PackedWriter::new(&snap_file).unwrap());
 
-	let state_hashes = chunk_state(&old_db, &state_root, &writer, &Progress::default()).unwrap();
+	let (state_hashes, state_chunk_sizes) = chunk_state(&old_db, &state_root, &writer, &Progress::default()).unwrap();
 
 	writer.into_inner().finish(::snapshot::ManifestData {
 		state_hashes: state_hashes,
 		block_hashes: Vec::new(),
+		state_chunk_sizes: state_chunk_sizes,
+		block_chunk_sizes: Vec::new(),
 		state_root: state_root,
 		block_number: 0,
 		block_hash: H256::default(),
@@ -16,6 +16,8 @@
 
 //! Restoration status type definition
 
+use util::hash::H256;
+
 /// Statuses for restorations.
 #[derive(PartialEq, Eq, Clone, Copy, Debug, Binary)]
 pub enum RestorationStatus {
@@ -27,8 +29,19 @@ pub enum RestorationStatus {
 		state_chunks_done: u32,
 		/// Number of block chunks completed.
 		block_chunks_done: u32,
+		/// Total size of chunks completed, in bytes.
+		bytes_done: u64,
 	},
 	/// Failed restoration.
 	Failed,
 }
 
+/// Chunk refused during restoration.
+#[derive(PartialEq, Eq, Clone, Debug, Binary)]
+pub struct RejectedChunk {
+	/// Hash of the chunk.
+	pub hash: H256,
+	/// Why it was refused.
+	pub reason: String,
+}
+
@@ -27,6 +27,10 @@ pub struct ManifestData {
 	pub state_hashes: Vec<H256>,
 	/// List of block chunk hashes.
 	pub block_hashes: Vec<H256>,
+	/// Sizes in bytes of the state chunks, in the order of their hashes. Empty if unknown.
+	pub state_chunk_sizes: Vec<u64>,
+	/// Sizes in bytes of the block chunks, in the order of their hashes. Empty if unknown.
+	pub block_chunk_sizes: Vec<u64>,
 	/// The final, expected state root.
 	pub state_root: H256,
 	/// Block number this snapshot was taken at.
@@ -38,17 +42,26 @@ pub struct ManifestData {
 impl ManifestData {
 	/// Encode the manifest data to rlp.
 	pub fn into_rlp(self) -> Bytes {
-		let mut stream = RlpStream::new_list(5);
+		self.to_bytes()
+	}
+
+	/// Canonical encoding of the manifest, e.g. for storing it. Same as `into_rlp`.
+	/// Chunk sizes go last, so older decoders can skip them.
+	pub fn to_bytes(&self) -> Bytes {
+		let mut stream = RlpStream::new_list(7);
 		stream.append(&self.state_hashes);
 		stream.append(&self.block_hashes);
 		stream.append(&self.state_root);
 		stream.append(&self.block_number);
 		stream.append(&self.block_hash);
+		stream.append(&self.state_chunk_sizes);
+		stream.append(&self.block_chunk_sizes);
 
 		stream.out()
 	}
 
 	/// Try to restore manifest data from raw bytes, interpreted as RLP.
+	/// Manifests encoded without the chunk sizes decode with the sizes unknown.
 	pub fn from_rlp(raw: &[u8]) -> Result<Self, DecoderError> {
 		let decoder = UntrustedRlp::new(raw);
 
@@ -57,14 +70,94 @@ impl ManifestData {
 		let state_root: H256 = try!(decoder.val_at(2));
 		let block_number: u64 = try!(decoder.val_at(3));
 		let block_hash: H256 = try!(decoder.val_at(4));
+		let (state_chunk_sizes, block_chunk_sizes) = match decoder.item_count() > 5 {
+			true => (try!(decoder.val_at(5)), try!(decoder.val_at(6))),
+			false => (Vec::new(), Vec::new()),
+		};
 
 		Ok(ManifestData {
 			state_hashes: state_hashes,
 			block_hashes: block_hashes,
+			state_chunk_sizes: state_chunk_sizes,
+			block_chunk_sizes: block_chunk_sizes,
 			state_root: state_root,
 			block_number: block_number,
 			block_hash: block_hash,
 		})
 	}
+
+	/// Decode the manifest from its canonical encoding. Same as `from_rlp`.
+	pub fn from_bytes(data: &[u8]) -> Result<Self, DecoderError> {
+		Self::from_rlp(data)
+	}
+
+	/// Total size in bytes of all the chunks, e.g. to estimate the download.
+	/// `None` if the sizes are unknown, or don't match the hashes.
+	pub fn total_size(&self) -> Option<u64> {
+		if self.state_chunk_sizes.len() != self.state_hashes.len() || self.block_chunk_sizes.len() != self.block_hashes.len() {
+			return None;
+		}
+		Some(self.state_chunk_sizes.iter().chain(&self.block_chunk_sizes).fold(0, |total, size| total + size))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::ManifestData;
+	use rlp::{RlpStream, Stream};
+	use util::hash::H256;
+
+	fn manifest() -> ManifestData {
+		ManifestData {
+			state_hashes: vec![H256::from(1), H256::from(2)],
+			block_hashes: vec![H256::from(3)],
+			state_chunk_sizes: vec![100, 200],
+			block_chunk_sizes: vec![300],
+			state_root: H256::from(4),
+			block_number: 1234567,
+			block_hash: H256::from(5),
+		}
+	}
+
+	#[test]
+	fn bytes_roundtrip() {
+		let manifest = manifest();
+		let bytes = manifest.to_bytes();
+		assert_eq!(ManifestData::from_bytes(&bytes).unwrap(), manifest);
+		assert_eq!(manifest.clone().into_rlp(), bytes);
+	}
+
+	#[test]
+	fn sums_chunk_sizes() {
+		let mut manifest = manifest();
+		assert_eq!(manifest.total_size(), Some(600));
+
+		manifest.block_chunk_sizes.clear();
+		assert_eq!(manifest.total_size(), None);
+	}
+
+	#[test]
+	fn decodes_manifest_without_chunk_sizes() {
+		let manifest = manifest();
+		let mut stream = RlpStream::new_list(5);
+		stream.append(&manifest.state_hashes);
+		stream.append(&manifest.block_hashes);
+		stream.append(&manifest.state_root);
+		stream.append(&manifest.block_number);
+		stream.append(&manifest.block_hash);
+
+		let decoded = ManifestData::from_bytes(&stream.out()).unwrap();
+		assert_eq!(decoded.state_hashes, manifest.state_hashes);
+		assert!(decoded.state_chunk_sizes.is_empty());
+		assert!(decoded.block_chunk_sizes.is_empty());
+		assert_eq!(decoded.total_size(), None);
+	}
+
+	#[test]
+	fn truncated_bytes_fail_to_decode() {
+		let bytes = manifest().to_bytes();
+		assert!(ManifestData::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+		assert!(ManifestData::from_bytes(&[]).is_err());
+	}
 }
 
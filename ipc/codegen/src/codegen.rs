@@ -587,6 +587,18 @@ fn push_with_socket_client_implementation(
 			}
 		}).unwrap();
 	push(Annotatable::Item(implement));
+
+	let implement_handshaker = quote_item!(cx,
+		impl $generics ::ipc::Handshaker<S> for $client_ident $where_clause {
+			fn handshake(&self) -> Result<(), ::ipc::Error> {
+				$client_short_ident::handshake(self)
+			}
+
+			fn with_socket<F, T>(&self, f: F) -> T where F: FnOnce(&mut S) -> T {
+				f(&mut *self.socket.write().unwrap())
+			}
+		}).unwrap();
+	push(Annotatable::Item(implement_handshaker));
 }
 
 /// pushes full client side code for the original class exposed via ipc
@@ -613,13 +625,13 @@ fn push_client_implementation(
 				api_version: $endpoint::api_version(),
 			};
 
-			::ipc::invoke(
-				0,
-				&Some(::ipc::binary::serialize(&::ipc::BinHandshake::from(payload)).unwrap()),
-				&mut *self.socket.write().unwrap());
+			let mut message = vec![0u8; 2];
+			message.extend_from_slice(&::ipc::binary::serialize(&::ipc::BinHandshake::from(payload)).unwrap());
+			try!(::std::io::Write::write(&mut *self.socket.write().unwrap(), &message)
+				.map_err(::ipc::Error::from_handshake_io));
 
 			let mut result = vec![0u8; 1];
-			if try!(self.socket.write().unwrap().read(&mut result).map_err(|_| ::ipc::Error::HandshakeFailed)) == 1 {
+			if try!(self.socket.write().unwrap().read(&mut result).map_err(::ipc::Error::from_handshake_io)) == 1 {
 				match result[0] {
 					1 => Ok(()),
 					_ => Err(::ipc::Error::RemoteServiceUnsupported),
@@ -20,22 +20,42 @@ extern crate ethcore_ipc as ipc;
 extern crate nanomsg;
 #[macro_use] extern crate log;
 
-pub use ipc::{WithSocket, IpcInterface, IpcConfig};
+pub use ipc::{WithSocket, IpcInterface, IpcConfig, Handshaker};
 pub use nanomsg::Socket as NanoSocket;
 
 use std::sync::*;
+use std::collections::VecDeque;
 use nanomsg::{Socket, Protocol, Error, Endpoint, PollRequest, PollFd, PollInOut};
 use std::ops::Deref;
+use std::time::Duration;
 
 const POLL_TIMEOUT: isize = 200;
 const CLIENT_CONNECTION_TIMEOUT: isize = 120000;
+const NO_TIMEOUT: isize = -1;
 
 /// Generic worker to handle service (binded) sockets
 pub struct Worker<S: ?Sized> where S: IpcInterface {
 	service: Arc<S>,
 	sockets: Vec<(Socket, Endpoint)>,
 	polls: Vec<PollFd>,
-	buf: Vec<u8>,
+	queues: Vec<SocketQueue>,
+}
+
+/// Messages received over a socket but not dispatched yet
+struct SocketQueue {
+	/// Whether all available messages can be read ahead.
+	/// Request-reply sockets must answer a request before reading the next one.
+	read_ahead: bool,
+	messages: VecDeque<Vec<u8>>,
+}
+
+impl SocketQueue {
+	fn new(read_ahead: bool) -> SocketQueue {
+		SocketQueue {
+			read_ahead: read_ahead,
+			messages: VecDeque::new(),
+		}
+	}
 }
 
 /// struct for guarding `_endpoint` (so that it wont drop)
@@ -81,6 +101,39 @@ pub fn init_duplex_client<S>(socket_addr: &str) -> Result<GuardedSocket<S>, Sock
 	})
 }
 
+/// Spawns client <`S`> over specified address
+/// and performs the handshake, failing with `Error::Timeout`
+/// if the service does not respond within `handshake_timeout`
+pub fn init_duplex_client_with_timeout<S>(socket_addr: &str, handshake_timeout: Duration) -> Result<GuardedSocket<S>, SocketError>
+	where S: WithSocket<Socket> + Handshaker<Socket>
+{
+	let client = try!(init_duplex_client::<S>(socket_addr));
+	let timeout_ms = (handshake_timeout.as_secs() * 1000 + handshake_timeout.subsec_nanos() as u64 / 1_000_000) as isize;
+
+	try!(client.with_socket(|socket| {
+		socket.set_send_timeout(timeout_ms).and_then(|_| socket.set_receive_timeout(timeout_ms))
+	}).map_err(|e| {
+		warn!(target: "ipc", "Failed to set handshake timeout: {:?}", e);
+		SocketError::DuplexLink
+	}));
+
+	let handshake = client.handshake();
+
+	try!(client.with_socket(|socket| {
+		socket.set_send_timeout(NO_TIMEOUT).and_then(|_| socket.set_receive_timeout(CLIENT_CONNECTION_TIMEOUT))
+	}).map_err(|e| {
+		warn!(target: "ipc", "Failed to restore socket timeouts: {:?}", e);
+		SocketError::DuplexLink
+	}));
+
+	try!(handshake.map_err(|e| {
+		warn!(target: "ipc", "Handshake with '{}' failed: {:?}", socket_addr, e);
+		SocketError::Handshake(e)
+	}));
+
+	Ok(client)
+}
+
 /// Spawns client <`S`> over specified address
 /// creates socket and connects endpoint to it
 /// for request-reply connections to the service
@@ -111,6 +164,8 @@ pub enum SocketError {
 	DuplexLink,
 	/// Error establising duplex (paired) socket and/or endpoint
 	RequestLink,
+	/// Handshake with the service failed or timed out
+	Handshake(ipc::Error),
 }
 
 impl<S: ?Sized> Worker<S> where S: IpcInterface {
@@ -120,52 +175,116 @@ impl<S: ?Sized> Worker<S> where S: IpcInterface {
 			service: service.clone(),
 			sockets: Vec::new(),
 			polls: Vec::new(),
-			buf: Vec::new(),
+			queues: Vec::new(),
 		}
 	}
 
 	/// Polls all sockets, reads and dispatches method invocations
 	pub fn poll(&mut self) {
-		use std::io::Write;
+		self.poll_timeout(Duration::from_millis(POLL_TIMEOUT as u64));
+	}
+
+	/// Number of messages received but not dispatched yet.
+	/// Growing between polls means the worker is falling behind its clients.
+	pub fn pending(&self) -> usize {
+		self.queues.iter().map(|queue| queue.messages.len()).sum()
+	}
+
+	/// Waits up to `timeout` for incoming messages, reads all the available ones
+	/// and dispatches at most one message per socket. Does not wait if there are
+	/// pending messages already.
+	pub fn poll_timeout(&mut self, timeout: Duration) {
+		let timeout_ms = match self.pending() {
+			0 => (timeout.as_secs() * 1000 + timeout.subsec_nanos() as u64 / 1_000_000) as isize,
+			_ => 0,
+		};
+
+		self.receive(timeout_ms);
+		self.dispatch();
+	}
+
+	/// Stops accepting new requests: dispatches the messages already received,
+	/// shuts down all endpoints and hands back the service.
+	pub fn stop(mut self) -> Arc<S> {
+		self.receive(0);
+		while self.pending() > 0 {
+			self.dispatch();
+		}
+
+		for &mut (_, ref mut endpoint) in self.sockets.iter_mut() {
+			if let Err(e) = endpoint.shutdown() {
+				warn!(target: "ipc", "Failed to shut down endpoint: {:?}", e);
+			}
+		}
 
+		trace!(target: "ipc", "Stopped worker");
+		self.service
+	}
+
+	/// Waits up to `timeout_ms` for incoming messages and queues the available ones
+	fn receive(&mut self, timeout_ms: isize) {
 		let mut request = PollRequest::new(&mut self.polls[..]);
- 		let _result_guard = Socket::poll(&mut request, POLL_TIMEOUT);
+ 		let _result_guard = Socket::poll(&mut request, timeout_ms);
 
 		for (fd_index, fd) in request.get_fds().iter().enumerate() {
 			if fd.can_read() {
 				let (ref mut socket, _) = self.sockets[fd_index];
-				unsafe { self.buf.set_len(0); }
-				match socket.nb_read_to_end(&mut self.buf) {
-					Ok(method_sign_len) => {
-						if method_sign_len >= 2 {
-
-							// method_num
-							let method_num = self.buf[0] as u16 * 256 + self.buf[1] as u16;
-							// payload
-							let payload = &self.buf[2..];
-
-							// dispatching for ipc interface
-							let result = self.service.dispatch_buf(method_num, payload);
-
-							if let Err(e) = socket.write(&result) {
-								warn!(target: "ipc", "Failed to write response: {:?}", e);
-							}
-						}
-						else {
-							warn!(target: "ipc", "Failed to read method signature from socket: unexpected message length({})", method_sign_len);
-						}
-					},
-					Err(Error::TryAgain) => {
-					},
-					Err(x) => {
-						warn!(target: "ipc", "Error polling connections {:?}", x);
-						panic!();
-					}
+				let queue = &mut self.queues[fd_index];
+				if queue.read_ahead || queue.messages.is_empty() {
+					Self::read_available(socket, queue);
 				}
 			}
 		}
 	}
 
+	/// Dispatches at most one queued message per socket
+	fn dispatch(&mut self) {
+		use std::io::Write;
+
+		for (socket_index, queue) in self.queues.iter_mut().enumerate() {
+			let message = match queue.messages.pop_front() {
+				Some(message) => message,
+				None => continue,
+			};
+
+			if message.len() >= 2 {
+				// method_num
+				let method_num = message[0] as u16 * 256 + message[1] as u16;
+				// payload
+				let payload = &message[2..];
+
+				// dispatching for ipc interface
+				let result = self.service.dispatch_buf(method_num, payload);
+
+				let (ref mut socket, _) = self.sockets[socket_index];
+				if let Err(e) = socket.write(&result) {
+					warn!(target: "ipc", "Failed to write response: {:?}", e);
+				}
+			}
+			else {
+				warn!(target: "ipc", "Failed to read method signature from socket: unexpected message length({})", message.len());
+			}
+		}
+	}
+
+	/// Moves the messages available on the socket to the queue
+	fn read_available(socket: &mut Socket, queue: &mut SocketQueue) {
+		loop {
+			let mut buf = Vec::new();
+			match socket.nb_read_to_end(&mut buf) {
+				Ok(_) => queue.messages.push_back(buf),
+				Err(Error::TryAgain) => return,
+				Err(x) => {
+					warn!(target: "ipc", "Error polling connections {:?}", x);
+					panic!();
+				}
+			}
+			if !queue.read_ahead {
+				return;
+			}
+		}
+	}
+
 	/// Stores nanomsg poll request for reuse
 	fn rebuild_poll_request(&mut self) {
 		self.polls = self.sockets.iter()
@@ -187,6 +306,7 @@ impl<S: ?Sized> Worker<S> where S: IpcInterface {
 		}));
 
 		self.sockets.push((socket, endpoint));
+		self.queues.push(SocketQueue::new(true));
 
 		self.rebuild_poll_request();
 
@@ -210,6 +330,7 @@ impl<S: ?Sized> Worker<S> where S: IpcInterface {
 		}));
 
 		self.sockets.push((socket, endpoint));
+		self.queues.push(SocketQueue::new(false));
 
 		self.rebuild_poll_request();
 
@@ -225,6 +346,8 @@ mod service_tests {
 	use ipc::*;
 	use std::io::{Read, Write};
 	use std::sync::{Arc, RwLock};
+	use std::thread;
+	use std::time::Duration;
 	use nanomsg::{Socket, Protocol, Endpoint};
 
 	struct TestInvoke {
@@ -318,4 +441,28 @@ mod service_tests {
 		assert_eq!(0, worker.service.methods_stack.read().unwrap()[0].method_num);
 		assert_eq!(vec![0u8; 1024*1024-2], worker.service.methods_stack.read().unwrap()[0].params);
 	}
+
+	#[test]
+	fn worker_reports_pending() {
+		let url = "ipc:///tmp/parity-test50.ipc";
+
+		let mut worker = Worker::<DummyService>::new(&Arc::new(DummyService::new()));
+		worker.add_duplex(url).unwrap();
+
+		let (mut socket, _endpoint) = dummy_write(url, &vec![0, 0, 1]);
+		socket.write(&vec![0, 1, 2]).unwrap();
+		socket.write(&vec![0, 2, 3]).unwrap();
+		thread::sleep(Duration::from_millis(100));
+		assert_eq!(0, worker.pending());
+
+		worker.poll_timeout(Duration::from_millis(200));
+		assert_eq!(2, worker.pending());
+		assert_eq!(1, worker.service.methods_stack.read().unwrap().len());
+
+		worker.poll_timeout(Duration::from_millis(200));
+		worker.poll_timeout(Duration::from_millis(200));
+		assert_eq!(0, worker.pending());
+		assert_eq!(3, worker.service.methods_stack.read().unwrap().len());
+		assert_eq!(2, worker.service.methods_stack.read().unwrap()[2].method_num);
+	}
 }
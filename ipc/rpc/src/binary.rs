@@ -796,6 +796,7 @@ binary_fixed_size!(u64);
 binary_fixed_size!(u32);
 binary_fixed_size!(usize);
 binary_fixed_size!(i32);
+binary_fixed_size!(i64);
 binary_fixed_size!(bool);
 binary_fixed_size!(U256);
 binary_fixed_size!(U512);
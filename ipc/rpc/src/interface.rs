@@ -56,6 +56,17 @@ pub enum Error {
 	ClientUnsupported,
 	RemoteServiceUnsupported,
 	HandshakeFailed,
+	Timeout,
+}
+
+impl Error {
+	/// Maps socket io error which happened during the handshake
+	pub fn from_handshake_io(e: ::std::io::Error) -> Error {
+		match e.kind() {
+			::std::io::ErrorKind::TimedOut | ::std::io::ErrorKind::WouldBlock => Error::Timeout,
+			_ => Error::HandshakeFailed,
+		}
+	}
 }
 
 /// Allows implementor to be attached to generic worker and dispatch rpc requests
@@ -100,6 +111,15 @@ pub trait WithSocket<S: IpcSocket> {
 	fn init(socket: S) -> Self;
 }
 
+/// Client which can negotiate versions with the remote service
+pub trait Handshaker<S: IpcSocket> {
+	/// Performs handshake with the service
+	fn handshake(&self) -> Result<(), Error>;
+
+	/// Runs `f` with exclusive access to the client socket
+	fn with_socket<F, T>(&self, f: F) -> T where F: FnOnce(&mut S) -> T;
+}
+
 
 impl IpcSocket for ::devtools::TestSocket {}
 
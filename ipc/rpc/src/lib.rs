@@ -23,5 +23,5 @@ extern crate ethcore_util as util;
 
 pub mod interface;
 pub mod binary;
-pub use interface::{IpcInterface, IpcSocket, invoke, IpcConfig, Handshake, Error, WithSocket};
+pub use interface::{IpcInterface, IpcSocket, invoke, IpcConfig, Handshake, Error, WithSocket, Handshaker};
 pub use binary::{BinaryConvertable, BinaryConvertError, BinHandshake};
@@ -23,6 +23,7 @@ mod tests {
 	use std::sync::Arc;
 	use std::io::Write;
 	use std::sync::atomic::{Ordering, AtomicBool};
+	use std::time::{Duration, Instant};
 
 	fn dummy_write(addr: &str, buf: &[u8]) -> (::nanomsg::Socket, ::nanomsg::Endpoint) {
 		let mut socket = ::nanomsg::Socket::new(::nanomsg::Protocol::Pair).unwrap();
@@ -74,4 +75,19 @@ mod tests {
 		worker_should_exit.store(true, Ordering::Relaxed);
 		assert!(hs.is_ok());
 	}
+
+	#[test]
+	fn handshake_times_out_without_worker() {
+		let url = "ipc:///tmp/parity-test-nano-25.ipc";
+		let started = Instant::now();
+
+		let client = nanoipc::init_duplex_client_with_timeout::<ServiceClient<_>>(url, Duration::from_millis(300));
+
+		match client {
+			Err(nanoipc::SocketError::Handshake(::ipc::Error::Timeout)) => {},
+			Err(e) => panic!("Expected handshake timeout, got {:?}", e),
+			Ok(_) => panic!("Handshake should not succeed without worker"),
+		}
+		assert!(started.elapsed() < Duration::from_secs(5));
+	}
 }
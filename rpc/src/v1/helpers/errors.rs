@@ -41,6 +41,7 @@ mod codes {
 	pub const REQUEST_REJECTED_LIMIT: i64 = -32041;
 	pub const REQUEST_NOT_FOUND: i64 = -32042;
 	pub const COMPILATION_ERROR: i64 = -32050;
+	pub const NETWORK_ERROR: i64 = -32060;
 }
 
 pub fn unimplemented() -> Error {
@@ -91,6 +92,14 @@ pub fn compilation<T: fmt::Debug>(error: T) -> Error {
 	}
 }
 
+pub fn network<T: fmt::Debug>(error: T) -> Error {
+	Error {
+		code: ErrorCode::ServerError(codes::NETWORK_ERROR),
+		message: "Network operation failed.".into(),
+		data: Some(Value::String(format!("{:?}", error))),
+	}
+}
+
 pub fn internal<T: fmt::Debug>(error: &str, data: T) -> Error {
 	Error {
 		code: ErrorCode::InternalError,
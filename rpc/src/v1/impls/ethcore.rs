@@ -148,6 +148,18 @@ impl<C, M, S: ?Sized> Ethcore for EthcoreClient<C, M, S> where M: MinerService +
 		Ok(to_value(&self.settings.network_port))
 	}
 
+	fn list_reserved_peers(&self, params: Params) -> Result<Value, Error> {
+		try!(self.active());
+		try!(expect_no_params(params));
+		Ok(to_value(&take_weak!(self.net).reserved_peers()))
+	}
+
+	fn listen_address(&self, params: Params) -> Result<Value, Error> {
+		try!(self.active());
+		try!(expect_no_params(params));
+		Ok(to_value(&take_weak!(self.net).listen_address()))
+	}
+
 	fn node_name(&self, params: Params) -> Result<Value, Error> {
 		try!(self.active());
 		try!(expect_no_params(params));
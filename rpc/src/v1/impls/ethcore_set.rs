@@ -66,6 +66,17 @@ impl<C, M> EthcoreSet for EthcoreSetClient<C, M> where
 		})
 	}
 
+	fn set_gas_price_percentile(&self, params: Params) -> Result<Value, Error> {
+		try!(self.active());
+		from_params::<(u8,)>(params).and_then(|(percentile,)| {
+			if percentile > 100 {
+				return Err(errors::invalid_params("Percentile", "must be between 0 and 100"));
+			}
+			take_weak!(self.miner).set_gas_price_percentile(percentile);
+			Ok(to_value(&true))
+		})
+	}
+
 	fn set_gas_floor_target(&self, params: Params) -> Result<Value, Error> {
 		try!(self.active());
 		from_params::<(U256,)>(params).and_then(|(target,)| {
@@ -116,8 +127,16 @@ impl<C, M> EthcoreSet for EthcoreSetClient<C, M> where
 
 	fn add_reserved_peer(&self, params: Params) -> Result<Value, Error> {
 		try!(self.active());
-		from_params::<(String,)>(params).and_then(|(peer,)| {
-			match take_weak!(self.net).add_reserved_peer(peer) {
+		let with_check = match params {
+			Params::Array(ref vec) => vec.len() > 1,
+			_ => false,
+		};
+		let params = match with_check {
+			true => from_params::<(String, bool)>(params),
+			false => from_params::<(String,)>(params).map(|(peer,)| (peer, false)),
+		};
+		params.and_then(|(peer, check_network)| {
+			match take_weak!(self.net).add_reserved_peer(peer, check_network) {
 				Ok(()) => Ok(to_value(&true)),
 				Err(e) => Err(errors::invalid_params("Peer address", e)),
 			}
@@ -134,6 +153,12 @@ impl<C, M> EthcoreSet for EthcoreSetClient<C, M> where
 		})
 	}
 
+	fn clear_reserved_peers(&self, params: Params) -> Result<Value, Error> {
+		try!(self.active());
+		try!(expect_no_params(params));
+		Ok(to_value(&take_weak!(self.net).clear_reserved_peers()))
+	}
+
 	fn drop_non_reserved_peers(&self, params: Params) -> Result<Value, Error> {
 		try!(self.active());
 		try!(expect_no_params(params));
@@ -148,6 +173,27 @@ impl<C, M> EthcoreSet for EthcoreSetClient<C, M> where
 		Ok(to_value(&true))
 	}
 
+	fn set_reserved_only(&self, params: Params) -> Result<Value, Error> {
+		try!(self.active());
+		from_params::<(bool,)>(params).and_then(|(reserved_only,)| {
+			match reserved_only {
+				true => take_weak!(self.net).deny_unreserved_peers(),
+				false => take_weak!(self.net).accept_unreserved_peers(),
+			}
+			Ok(to_value(&true))
+		})
+	}
+
+	fn set_listen_port(&self, params: Params) -> Result<Value, Error> {
+		try!(self.active());
+		from_params::<(u16,)>(params).and_then(|(port,)| {
+			match take_weak!(self.net).set_listen_port(port) {
+				Ok(()) => Ok(to_value(&true)),
+				Err(e) => Err(errors::network(e)),
+			}
+		})
+	}
+
 	fn start_network(&self, params: Params) -> Result<Value, Error> {
 		try!(expect_no_params(params));
 		take_weak!(self.net).start_network();
@@ -39,6 +39,7 @@ pub struct TestMinerService {
 	pub last_nonces: RwLock<HashMap<Address, U256>>,
 
 	min_gas_price: RwLock<U256>,
+	gas_price_percentile: RwLock<Option<u8>>,
 	gas_range_target: RwLock<(U256, U256)>,
 	author: RwLock<Address>,
 	extra_data: RwLock<Bytes>,
@@ -55,6 +56,7 @@ impl Default for TestMinerService {
 			pending_receipts: Mutex::new(BTreeMap::new()),
 			last_nonces: RwLock::new(HashMap::new()),
 			min_gas_price: RwLock::new(U256::from(20_000_000)),
+			gas_price_percentile: RwLock::new(None),
 			gas_range_target: RwLock::new((U256::from(12345), U256::from(54321))),
 			author: RwLock::new(Address::zero()),
 			extra_data: RwLock::new(vec![1, 2, 3, 4]),
@@ -94,9 +96,18 @@ impl MinerService for TestMinerService {
 	}
 
 	fn set_minimal_gas_price(&self, min_gas_price: U256) {
+		*self.gas_price_percentile.write() = None;
 		*self.min_gas_price.write() = min_gas_price;
 	}
 
+	fn set_gas_price_percentile(&self, percentile: u8) {
+		*self.gas_price_percentile.write() = Some(percentile);
+	}
+
+	fn gas_price_percentile(&self) -> Option<u8> {
+		*self.gas_price_percentile.read()
+	}
+
 	fn set_transactions_limit(&self, limit: usize) {
 		*self.limit.write() = limit;
 	}
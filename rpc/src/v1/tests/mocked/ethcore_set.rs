@@ -23,7 +23,7 @@ use ethcore::client::TestBlockChainClient;
 use v1::tests::helpers::TestMinerService;
 use util::{U256, Address};
 use rustc_serialize::hex::FromHex;
-use super::manage_network::TestManageNetwork;
+use super::manage_network::{TestManageNetwork, BUSY_PORT};
 use ethsync::ManageNetwork;
 
 fn miner_service() -> Arc<TestMinerService> {
@@ -35,7 +35,7 @@ fn client_service() -> Arc<TestBlockChainClient> {
 }
 
 fn network_service() -> Arc<TestManageNetwork> {
-	Arc::new(TestManageNetwork)
+	Arc::new(TestManageNetwork::default())
 }
 
 fn ethcore_set_client(client: &Arc<TestBlockChainClient>, miner: &Arc<TestMinerService>, net: &Arc<TestManageNetwork>) -> EthcoreSetClient<TestBlockChainClient, TestMinerService> {
@@ -57,6 +57,30 @@ fn rpc_ethcore_set_min_gas_price() {
 	assert_eq!(miner.minimal_gas_price(), U256::from_str("cd1722f3947def4cf144679da39c4c32bdc35681").unwrap());
 }
 
+#[test]
+fn rpc_ethcore_set_gas_price_percentile() {
+	let miner = miner_service();
+	let client = client_service();
+	let network = network_service();
+	let io = IoHandler::new();
+	io.add_delegate(ethcore_set_client(&client, &miner, &network).to_delegate());
+
+	let request = r#"{"jsonrpc": "2.0", "method": "ethcore_setGasPricePercentile", "params":[50], "id": 1}"#;
+	let response = r#"{"jsonrpc":"2.0","result":true,"id":1}"#;
+	assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
+	assert_eq!(miner.gas_price_percentile(), Some(50));
+
+	let request = r#"{"jsonrpc": "2.0", "method": "ethcore_setGasPricePercentile", "params":[101], "id": 1}"#;
+	assert!(io.handle_request_sync(request).unwrap().contains("error"));
+	assert_eq!(miner.gas_price_percentile(), Some(50));
+
+	// a fixed price stops tracking the percentile
+	let request = r#"{"jsonrpc": "2.0", "method": "ethcore_setMinGasPrice", "params":["0x1"], "id": 1}"#;
+	assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
+	assert_eq!(miner.gas_price_percentile(), None);
+	assert_eq!(miner.minimal_gas_price(), U256::from(1));
+}
+
 #[test]
 fn rpc_ethcore_set_gas_floor_target() {
 	let miner = miner_service();
@@ -116,3 +140,108 @@ fn rpc_ethcore_set_transactions_limit() {
 	assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
 	assert_eq!(miner.transactions_limit(), 10_240_240);
 }
+
+#[test]
+fn rpc_ethcore_clear_reserved_peers() {
+	let miner = miner_service();
+	let client = client_service();
+	let network = network_service();
+	let io = IoHandler::new();
+	io.add_delegate(ethcore_set_client(&client, &miner, &network).to_delegate());
+
+	for port in 7770..7773 {
+		let request = format!(r#"{{"jsonrpc": "2.0", "method": "ethcore_addReservedPeer", "params":["enode://a979fb575495b8d6db44f750317d0f4622bf4c2aa3365d6af7c284339968eef29b69ad0dce72a4d8db5ebb4968de0e3bec910127f134779fbcb0cb6d3331163c@22.99.55.44:{}"], "id": 1}}"#, port);
+		io.handle_request_sync(&request).unwrap();
+	}
+	assert_eq!(network.reserved_peers.lock().len(), 3);
+
+	let request = r#"{"jsonrpc": "2.0", "method": "ethcore_clearReservedPeers", "params":[], "id": 1}"#;
+	let response = r#"{"jsonrpc":"2.0","result":true,"id":1}"#;
+
+	assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
+	assert!(network.reserved_peers.lock().is_empty());
+}
+
+#[test]
+fn rpc_ethcore_clear_reserved_peers_on_stopped_network() {
+	use std::sync::atomic::Ordering;
+
+	let miner = miner_service();
+	let client = client_service();
+	let network = network_service();
+	network.stopped.store(true, Ordering::SeqCst);
+	let io = IoHandler::new();
+	io.add_delegate(ethcore_set_client(&client, &miner, &network).to_delegate());
+
+	let request = r#"{"jsonrpc": "2.0", "method": "ethcore_clearReservedPeers", "params":[], "id": 1}"#;
+	let response = r#"{"jsonrpc":"2.0","result":false,"id":1}"#;
+
+	assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
+}
+
+#[test]
+fn rpc_ethcore_set_reserved_only() {
+	use std::sync::atomic::Ordering;
+
+	let miner = miner_service();
+	let client = client_service();
+	let network = network_service();
+	let io = IoHandler::new();
+	io.add_delegate(ethcore_set_client(&client, &miner, &network).to_delegate());
+
+	let request = r#"{"jsonrpc": "2.0", "method": "ethcore_setReservedOnly", "params":[true], "id": 1}"#;
+	let response = r#"{"jsonrpc":"2.0","result":true,"id":1}"#;
+	assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
+	assert!(network.reserved_only.load(Ordering::SeqCst));
+
+	let request = r#"{"jsonrpc": "2.0", "method": "ethcore_setReservedOnly", "params":[false], "id": 1}"#;
+	assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
+	assert!(!network.reserved_only.load(Ordering::SeqCst));
+}
+
+#[test]
+fn rpc_ethcore_set_listen_port() {
+	let miner = miner_service();
+	let client = client_service();
+	let network = network_service();
+	let io = IoHandler::new();
+	io.add_delegate(ethcore_set_client(&client, &miner, &network).to_delegate());
+
+	let request = r#"{"jsonrpc": "2.0", "method": "ethcore_setListenPort", "params":[30310], "id": 1}"#;
+	let response = r#"{"jsonrpc":"2.0","result":true,"id":1}"#;
+	assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
+	assert_eq!(network.listen_address(), Some("127.0.0.1:30310".to_owned()));
+
+	// the port in use is kept
+	assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
+	assert_eq!(network.listen_address(), Some("127.0.0.1:30310".to_owned()));
+
+	let request = format!(r#"{{"jsonrpc": "2.0", "method": "ethcore_setListenPort", "params":[{}], "id": 1}}"#, BUSY_PORT);
+	let response = r#"{"jsonrpc":"2.0","error":{"code":-32060,"message":"Network operation failed.","data":"\"Address already in use\""},"id":1}"#;
+	assert_eq!(io.handle_request_sync(&request), Some(response.to_owned()));
+	assert_eq!(network.listen_address(), Some("127.0.0.1:30310".to_owned()));
+}
+
+#[test]
+fn rpc_ethcore_add_reserved_peer_checks_network() {
+	let miner = miner_service();
+	let client = client_service();
+	let network = network_service();
+	let io = IoHandler::new();
+	io.add_delegate(ethcore_set_client(&client, &miner, &network).to_delegate());
+
+	let enode = "enode://a979fb575495b8d6db44f750317d0f4622bf4c2aa3365d6af7c284339968eef29b69ad0dce72a4d8db5ebb4968de0e3bec910127f134779fbcb0cb6d3331163c@22.99.55.44:7770";
+	let request = format!(r#"{{"jsonrpc": "2.0", "method": "ethcore_addReservedPeer", "params":["{}?networkid=2", true], "id": 1}}"#, enode);
+	let response = r#"{"jsonrpc":"2.0","error":{"code":-32602,"message":"Couldn't parse parameters: Peer address","data":"\"Peer is on network 2, expected 1\""},"id":1}"#;
+	assert_eq!(io.handle_request_sync(&request), Some(response.to_owned()));
+	assert!(network.reserved_peers.lock().is_empty());
+
+	let request = format!(r#"{{"jsonrpc": "2.0", "method": "ethcore_addReservedPeer", "params":["{}", true], "id": 1}}"#, enode);
+	let response = r#"{"jsonrpc":"2.0","error":{"code":-32602,"message":"Couldn't parse parameters: Peer address","data":"\"Peer does not advertise its network id\""},"id":1}"#;
+	assert_eq!(io.handle_request_sync(&request), Some(response.to_owned()));
+
+	let request = format!(r#"{{"jsonrpc": "2.0", "method": "ethcore_addReservedPeer", "params":["{}?networkid=1", true], "id": 1}}"#, enode);
+	let response = r#"{"jsonrpc":"2.0","result":true,"id":1}"#;
+	assert_eq!(io.handle_request_sync(&request), Some(response.to_owned()));
+	assert!(network.reserved_peers.lock().contains(enode));
+}
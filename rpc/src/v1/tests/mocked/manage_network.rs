@@ -14,17 +14,63 @@
 // You should have received a copy of the GNU General Public License
 // along with Parity.  If not, see <http://www.gnu.org/licenses/>.
 
-use ethsync::{ManageNetwork, NetworkConfiguration};
+use ethsync::{ManageNetwork, NetworkConfiguration, check_peer_network};
 
-pub struct TestManageNetwork;
+use std::sync::atomic::{AtomicBool, Ordering};
+use util::{Mutex, HashSet};
+
+pub const BUSY_PORT: u16 = 30303;
+
+#[derive(Default)]
+pub struct TestManageNetwork {
+	pub reserved_peers: Mutex<HashSet<String>>,
+	pub reserved_only: AtomicBool,
+	pub listen_port: Mutex<Option<u16>>,
+	pub stopped: AtomicBool,
+}
 
 // TODO: rob, gavin (originally introduced this functions) - proper tests and test state
 impl ManageNetwork for TestManageNetwork {
-	fn accept_unreserved_peers(&self) { }
-	fn deny_unreserved_peers(&self) { }
-	fn remove_reserved_peer(&self, _peer: String) -> Result<(), String> { Ok(()) }
-	fn add_reserved_peer(&self, _peer: String) -> Result<(), String> { Ok(()) }
+	fn accept_unreserved_peers(&self) { self.reserved_only.store(false, Ordering::SeqCst); }
+	fn deny_unreserved_peers(&self) { self.reserved_only.store(true, Ordering::SeqCst); }
+	fn remove_reserved_peer(&self, peer: String) -> Result<(), String> {
+		self.reserved_peers.lock().remove(&peer);
+		Ok(())
+	}
+	fn add_reserved_peer(&self, peer: String, check_network: bool) -> Result<(), String> {
+		let peer = match check_network {
+			true => try!(check_peer_network(&peer, 1.into())).to_owned(),
+			false => peer,
+		};
+		self.reserved_peers.lock().insert(peer);
+		Ok(())
+	}
+	fn clear_reserved_peers(&self) -> bool {
+		if self.stopped.load(Ordering::SeqCst) {
+			return false;
+		}
+		self.reserved_peers.lock().clear();
+		true
+	}
+	fn reserved_peers(&self) -> Vec<String> {
+		let mut peers: Vec<_> = self.reserved_peers.lock().iter().cloned().collect();
+		peers.sort();
+		peers
+	}
 	fn start_network(&self) {}
 	fn stop_network(&self) {}
 	fn network_config(&self) -> NetworkConfiguration { NetworkConfiguration::new_local() }
+	fn listen_address(&self) -> Option<String> {
+		self.listen_port.lock().map(|port| format!("127.0.0.1:{}", port))
+	}
+	fn set_listen_port(&self, port: u16) -> Result<(), String> {
+		if *self.listen_port.lock() == Some(port) {
+			return Ok(());
+		}
+		if port == BUSY_PORT {
+			return Err("Address already in use".into());
+		}
+		*self.listen_port.lock() = Some(port);
+		Ok(())
+	}
 }
@@ -51,6 +51,12 @@ pub trait Ethcore: Sized + Send + Sync + 'static {
 	/// Returns network port
 	fn net_port(&self, _: Params) -> Result<Value, Error>;
 
+	/// Returns the reserved peers as enode urls
+	fn list_reserved_peers(&self, _: Params) -> Result<Value, Error>;
+
+	/// Returns the address the node accepts peer connections on, null if the network is stopped
+	fn listen_address(&self, _: Params) -> Result<Value, Error>;
+
 	/// Returns rpc settings
 	fn rpc_settings(&self, _: Params) -> Result<Value, Error>;
 
@@ -90,6 +96,8 @@ pub trait Ethcore: Sized + Send + Sync + 'static {
 		delegate.add_method("ethcore_netChain", Ethcore::net_chain);
 		delegate.add_method("ethcore_netPeers", Ethcore::net_peers);
 		delegate.add_method("ethcore_netPort", Ethcore::net_port);
+		delegate.add_method("ethcore_listReservedPeers", Ethcore::list_reserved_peers);
+		delegate.add_method("ethcore_listenAddress", Ethcore::listen_address);
 		delegate.add_method("ethcore_rpcSettings", Ethcore::rpc_settings);
 		delegate.add_method("ethcore_nodeName", Ethcore::node_name);
 		delegate.add_method("ethcore_defaultExtraData", Ethcore::default_extra_data);
@@ -25,6 +25,10 @@ pub trait EthcoreSet: Sized + Send + Sync + 'static {
 	/// Sets new minimal gas price for mined blocks.
 	fn set_min_gas_price(&self, _: Params) -> Result<Value, Error>;
 
+	/// Makes the minimal gas price track a percentile of the gas prices in recent blocks,
+	/// until a fixed one is set with `set_min_gas_price`.
+	fn set_gas_price_percentile(&self, _: Params) -> Result<Value, Error>;
+
 	/// Sets new gas floor target for mined blocks.
 	fn set_gas_floor_target(&self, _: Params) -> Result<Value, Error>;
 
@@ -43,18 +47,27 @@ pub trait EthcoreSet: Sized + Send + Sync + 'static {
 	/// Sets the maximum amount of gas a single transaction may consume.
 	fn set_tx_gas_limit(&self, _: Params) -> Result<Value, Error>;
 
-	/// Add a reserved peer.
+	/// Add a reserved peer. Takes an optional flag to check the network id advertised in its address.
 	fn add_reserved_peer(&self, _: Params) -> Result<Value, Error>;
 
 	/// Remove a reserved peer.
 	fn remove_reserved_peer(&self, _: Params) -> Result<Value, Error>;
 
+	/// Remove all the reserved peers. Returns false if the network is not running.
+	fn clear_reserved_peers(&self, _: Params) -> Result<Value, Error>;
+
 	/// Drop all non-reserved peers.
 	fn drop_non_reserved_peers(&self, _: Params) -> Result<Value, Error>;
 
 	/// Accept non-reserved peers (default behavior)
 	fn accept_non_reserved_peers(&self, _: Params) -> Result<Value, Error>;
 
+	/// Drop and deny non-reserved peers if true, accept them again if false.
+	fn set_reserved_only(&self, _: Params) -> Result<Value, Error>;
+
+	/// Listen for peers on another port, restarting the network if it's running.
+	fn set_listen_port(&self, _: Params) -> Result<Value, Error>;
+
 	/// Start the network.
 	fn start_network(&self, _: Params) -> Result<Value, Error>;
 
@@ -65,6 +78,7 @@ pub trait EthcoreSet: Sized + Send + Sync + 'static {
 	fn to_delegate(self) -> IoDelegate<Self> {
 		let mut delegate = IoDelegate::new(Arc::new(self));
 		delegate.add_method("ethcore_setMinGasPrice", EthcoreSet::set_min_gas_price);
+		delegate.add_method("ethcore_setGasPricePercentile", EthcoreSet::set_gas_price_percentile);
 		delegate.add_method("ethcore_setGasFloorTarget", EthcoreSet::set_gas_floor_target);
 		delegate.add_method("ethcore_setGasCeilTarget", EthcoreSet::set_gas_ceil_target);
 		delegate.add_method("ethcore_setExtraData", EthcoreSet::set_extra_data);
@@ -73,8 +87,11 @@ pub trait EthcoreSet: Sized + Send + Sync + 'static {
 		delegate.add_method("ethcore_setTransactionsLimit", EthcoreSet::set_transactions_limit);
 		delegate.add_method("ethcore_addReservedPeer", EthcoreSet::add_reserved_peer);
 		delegate.add_method("ethcore_removeReservedPeer", EthcoreSet::remove_reserved_peer);
+		delegate.add_method("ethcore_clearReservedPeers", EthcoreSet::clear_reserved_peers);
 		delegate.add_method("ethcore_dropNonReservedPeers", EthcoreSet::drop_non_reserved_peers);
 		delegate.add_method("ethcore_acceptNonReservedPeers", EthcoreSet::accept_non_reserved_peers);
+		delegate.add_method("ethcore_setReservedOnly", EthcoreSet::set_reserved_only);
+		delegate.add_method("ethcore_setListenPort", EthcoreSet::set_listen_port);
 
 		delegate
 	}
@@ -17,7 +17,7 @@
 use std::sync::Arc;
 use network::{NetworkProtocolHandler, NetworkService, NetworkContext, PeerId,
 	NetworkConfiguration as BasicNetworkConfiguration, NonReservedPeerMode, NetworkError};
-use util::{U256, H256};
+use util::{U256, H256, Uint};
 use io::{TimerToken};
 use ethcore::client::{BlockChainClient, ChainNotify};
 use ethcore::snapshot::SnapshotService;
@@ -68,6 +68,8 @@ pub struct EthSync {
 	network: NetworkService,
 	/// Protocol handler
 	handler: Arc<SyncProtocolHandler>,
+	/// Network ID
+	network_id: U256,
 }
 
 impl EthSync {
@@ -78,10 +80,19 @@ impl EthSync {
 		let sync = Arc::new(EthSync{
 			network: service,
 			handler: Arc::new(SyncProtocolHandler { sync: RwLock::new(chain_sync), chain: chain, snapshot_service: snapshot_service }),
+			network_id: config.network_id,
 		});
 
 		Ok(sync)
 	}
+
+	// start the network, failing if the host can't be started, e.g. because the port is taken.
+	fn try_start(&self) -> Result<(), NetworkError> {
+		try!(self.network.start());
+		self.network.register_protocol(self.handler.clone(), ETH_PROTOCOL, &[62u8, 63u8, 64u8])
+			.unwrap_or_else(|e| warn!("Error registering ethereum protocol: {:?}", e));
+		Ok(())
+	}
 }
 
 #[derive(Ipc)]
@@ -148,9 +159,7 @@ impl ChainNotify for EthSync {
 	}
 
 	fn start(&self) {
-		self.network.start().unwrap_or_else(|e| warn!("Error starting network: {:?}", e));
-		self.network.register_protocol(self.handler.clone(), ETH_PROTOCOL, &[62u8, 63u8, 64u8])
-			.unwrap_or_else(|e| warn!("Error registering ethereum protocol: {:?}", e));
+		self.try_start().unwrap_or_else(|e| warn!("Error starting network: {:?}", e));
 	}
 
 	fn stop(&self) {
@@ -169,14 +178,23 @@ pub trait ManageNetwork : Send + Sync {
 	fn deny_unreserved_peers(&self);
 	/// Remove reservation for the peer
 	fn remove_reserved_peer(&self, peer: String) -> Result<(), String>;
-	/// Add reserved peer
-	fn add_reserved_peer(&self, peer: String) -> Result<(), String>;
+	/// Add reserved peer. With `check_network` the peer has to advertise the same network id
+	/// in its address (`enode://<id>@<ip>:<port>?networkid=<network id>`).
+	fn add_reserved_peer(&self, peer: String, check_network: bool) -> Result<(), String>;
+	/// Remove all the reserved peers, false if the network is not running
+	fn clear_reserved_peers(&self) -> bool;
+	/// List the reserved peers as enode urls
+	fn reserved_peers(&self) -> Vec<String>;
 	/// Start network
 	fn start_network(&self);
 	/// Stop network
 	fn stop_network(&self);
 	/// Query the current configuration of the network
 	fn network_config(&self) -> NetworkConfiguration;
+	/// Address accepting peer connections, none if the network is stopped
+	fn listen_address(&self) -> Option<String>;
+	/// Listen on another port, restarting the network if it's running. Fails if the port is in use.
+	fn set_listen_port(&self, port: u16) -> Result<(), String>;
 }
 
 
@@ -195,8 +213,20 @@ impl ManageNetwork for EthSync {
 		self.network.remove_reserved_peer(&peer).map_err(|e| format!("{:?}", e))
 	}
 
-	fn add_reserved_peer(&self, peer: String) -> Result<(), String> {
-		self.network.add_reserved_peer(&peer).map_err(|e| format!("{:?}", e))
+	fn add_reserved_peer(&self, peer: String, check_network: bool) -> Result<(), String> {
+		let address = match check_network {
+			true => try!(check_peer_network(&peer, self.network_id)),
+			false => peer_address(&peer),
+		};
+		self.network.add_reserved_peer(address).map_err(|e| format!("{:?}", e))
+	}
+
+	fn clear_reserved_peers(&self) -> bool {
+		self.network.clear_reserved_peers()
+	}
+
+	fn reserved_peers(&self) -> Vec<String> {
+		self.network.reserved_peers()
 	}
 
 	fn start_network(&self) {
@@ -212,7 +242,60 @@ impl ManageNetwork for EthSync {
 	}
 
 	fn network_config(&self) -> NetworkConfiguration {
-		NetworkConfiguration::from(self.network.config().clone())
+		NetworkConfiguration::from(self.network.config())
+	}
+
+	fn listen_address(&self) -> Option<String> {
+		self.network.listen_address().map(|address| format!("{}", address))
+	}
+
+	fn set_listen_port(&self, port: u16) -> Result<(), String> {
+		let listening = self.network.listen_address();
+		if listening.map_or(false, |address| address.port() == port) {
+			return Ok(());
+		}
+
+		let previous = self.network.set_listen_port(port);
+		// a stopped network binds the port once it's started
+		if listening.is_none() {
+			return Ok(());
+		}
+
+		// reserved peers and the peer mode set at runtime are kept in the network configuration,
+		// so the restarted network picks them up
+		self.stop_network();
+		if let Err(e) = self.try_start() {
+			warn!("Unable to listen on port {}: {}", port, e);
+			self.network.set_listen_port(previous);
+			if let Err(restart) = self.try_start() {
+				warn!("Unable to restart network on port {}: {}", previous, restart);
+				return Err(format!("{}; network is stopped, restarting it on port {} failed: {}", e, previous, restart));
+			}
+			return Err(format!("{}", e));
+		}
+		Ok(())
+	}
+}
+
+/// Node address without the network id advertised by the peer.
+fn peer_address(peer: &str) -> &str {
+	peer.splitn(2, '?').next().unwrap_or(peer)
+}
+
+/// Checks the network id advertised in the peer address against the local one.
+/// Returns the node address without the network id.
+pub fn check_peer_network(peer: &str, network_id: U256) -> Result<&str, String> {
+	let advertised = peer.splitn(2, '?').nth(1)
+		.and_then(|query| query.split('&').find(|param| param.starts_with("networkid=")))
+		.map(|param| &param["networkid=".len()..]);
+
+	match advertised {
+		None => Err("Peer does not advertise its network id".into()),
+		Some(id) => match U256::from_dec_str(id) {
+			Ok(ref id) if *id == network_id => Ok(peer_address(peer)),
+			Ok(id) => Err(format!("Peer is on network {}, expected {}", id, network_id)),
+			Err(_) => Err(format!("Invalid network id: {}", id)),
+		},
 	}
 }
 
@@ -141,6 +141,8 @@ mod test {
 		let manifest = ManifestData {
 			state_hashes: state_chunks.iter().map(|data| data.sha3()).collect(),
 			block_hashes: block_chunks.iter().map(|data| data.sha3()).collect(),
+			state_chunk_sizes: Vec::new(),
+			block_chunk_sizes: Vec::new(),
 			state_root: H256::new(),
 			block_number: 42,
 			block_hash: H256::new(),
@@ -14,8 +14,9 @@
 // You should have received a copy of the GNU General Public License
 // along with Parity.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::sync::mpsc::{channel, Sender, Receiver};
 use util::*;
-use ethcore::snapshot::{SnapshotService, ManifestData, RestorationStatus};
+use ethcore::snapshot::{SnapshotService, ManifestData, RestorationStatus, RejectedChunk};
 use ethcore::header::BlockNumber;
 use ethcore::client::{EachBlockWith};
 use super::helpers::*;
@@ -25,8 +26,13 @@ pub struct TestSnapshotService {
 	chunks: HashMap<H256, Bytes>,
 
 	restoration_manifest: Mutex<Option<ManifestData>>,
-	state_restoration_chunks: Mutex<HashMap<H256, Bytes>>,
-	block_restoration_chunks: Mutex<HashMap<H256, Bytes>>,
+	state_restoration_chunks: Mutex<HashSet<H256>>,
+	block_restoration_chunks: Mutex<HashSet<H256>>,
+	restored_chunks: Mutex<HashMap<H256, Bytes>>,
+	restored_bytes: Mutex<u64>,
+	rejected_chunks: Mutex<Vec<RejectedChunk>>,
+	completion_listeners: Mutex<Vec<Sender<()>>>,
+	restoration_failed: Mutex<bool>,
 }
 
 impl TestSnapshotService {
@@ -35,8 +41,13 @@ impl TestSnapshotService {
 			manifest: None,
 			chunks: HashMap::new(),
 			restoration_manifest: Mutex::new(None),
-			state_restoration_chunks: Mutex::new(HashMap::new()),
-			block_restoration_chunks: Mutex::new(HashMap::new()),
+			state_restoration_chunks: Mutex::new(HashSet::new()),
+			block_restoration_chunks: Mutex::new(HashSet::new()),
+			restored_chunks: Mutex::new(HashMap::new()),
+			restored_bytes: Mutex::new(0),
+			rejected_chunks: Mutex::new(Vec::new()),
+			completion_listeners: Mutex::new(Vec::new()),
+			restoration_failed: Mutex::new(false),
 		}
 	}
 
@@ -48,7 +59,9 @@ impl TestSnapshotService {
 		let manifest = ManifestData {
 			state_hashes: state_chunks.iter().map(|data| data.sha3()).collect(),
 			block_hashes: block_chunks.iter().map(|data| data.sha3()).collect(),
-			state_root: H256::new(),
+			state_chunk_sizes: Vec::new(),
+			block_chunk_sizes: Vec::new(),
+			state_root: ordered_trie_root(state_chunks.clone()),
 			block_number: block_number,
 			block_hash: block_hash,
 		};
@@ -58,8 +71,83 @@ impl TestSnapshotService {
 			manifest: Some(manifest),
 			chunks: chunks,
 			restoration_manifest: Mutex::new(None),
-			state_restoration_chunks: Mutex::new(HashMap::new()),
-			block_restoration_chunks: Mutex::new(HashMap::new()),
+			state_restoration_chunks: Mutex::new(HashSet::new()),
+			block_restoration_chunks: Mutex::new(HashSet::new()),
+			restored_chunks: Mutex::new(HashMap::new()),
+			restored_bytes: Mutex::new(0),
+			rejected_chunks: Mutex::new(Vec::new()),
+			completion_listeners: Mutex::new(Vec::new()),
+			restoration_failed: Mutex::new(false),
+		}
+	}
+
+	/// Get notified once all the chunks of the current restoration are in.
+	pub fn on_complete(&self) -> Receiver<()> {
+		let (tx, rx) = channel();
+		self.completion_listeners.lock().push(tx);
+		rx
+	}
+
+	/// Check that the restored state chunks rebuild the state root of the manifest.
+	/// The test service has no real state, so the root is the ordered trie root of the state chunks.
+	/// A mismatch fails the restoration.
+	pub fn verify_state_root(&self) -> Result<bool, UtilError> {
+		let manifest = self.restoration_manifest.lock();
+		let manifest = try!(manifest.as_ref().ok_or(UtilError::SimpleString("No restoration in progress".into())));
+		let restored = self.restored_chunks.lock();
+		let mut state = Vec::with_capacity(manifest.state_hashes.len());
+		for hash in &manifest.state_hashes {
+			match restored.get(hash) {
+				Some(chunk) => state.push(chunk.clone()),
+				None => return Err(UtilError::SimpleString(format!("State chunk {} is not restored yet", hash))),
+			}
+		}
+
+		let matches = ordered_trie_root(state) == manifest.state_root;
+		if !matches {
+			*self.restoration_failed.lock() = true;
+		}
+		Ok(matches)
+	}
+
+	fn restore_chunk(&self, hash: H256, chunk: Bytes, is_state: bool) {
+		let listed = match *self.restoration_manifest.lock() {
+			Some(ref manifest) if is_state => manifest.state_hashes.contains(&hash),
+			Some(ref manifest) => manifest.block_hashes.contains(&hash),
+			None => false,
+		};
+
+		if !listed {
+			self.rejected_chunks.lock().push(RejectedChunk { hash: hash, reason: "not in manifest".into() });
+			return;
+		}
+
+		let was_complete = self.all_chunks_restored();
+		match is_state {
+			true => self.state_restoration_chunks.lock().insert(hash),
+			false => self.block_restoration_chunks.lock().insert(hash),
+		};
+
+		{
+			let mut restored = self.restored_chunks.lock();
+			if !restored.contains_key(&hash) {
+				*self.restored_bytes.lock() += chunk.len() as u64;
+				restored.insert(hash, chunk);
+			}
+		}
+
+		if !was_complete && self.all_chunks_restored() {
+			for listener in self.completion_listeners.lock().drain(..) {
+				let _ = listener.send(());
+			}
+		}
+	}
+
+	fn all_chunks_restored(&self) -> bool {
+		match *self.restoration_manifest.lock() {
+			Some(ref manifest) => self.state_restoration_chunks.lock().len() == manifest.state_hashes.len() &&
+				self.block_restoration_chunks.lock().len() == manifest.block_hashes.len(),
+			None => false,
 		}
 	}
 }
@@ -74,39 +162,51 @@ impl SnapshotService for TestSnapshotService {
 	}
 
 	fn status(&self) -> RestorationStatus {
+		if *self.restoration_failed.lock() {
+			return RestorationStatus::Failed;
+		}
 		match &*self.restoration_manifest.lock() {
 			&Some(ref manifest) if self.state_restoration_chunks.lock().len() == manifest.state_hashes.len() &&
 				self.block_restoration_chunks.lock().len() == manifest.block_hashes.len() => RestorationStatus::Inactive,
 			&Some(_) => RestorationStatus::Ongoing {
 				state_chunks_done: self.state_restoration_chunks.lock().len() as u32,
 				block_chunks_done: self.block_restoration_chunks.lock().len() as u32,
+				bytes_done: *self.restored_bytes.lock(),
 			},
 			&None => RestorationStatus::Inactive,
 		}
 	}
 
 	fn begin_restore(&self, manifest: ManifestData) {
+		self.abort_restore();
+		// like the real service, refuse manifests listing a chunk more than once
+		let mut seen = HashSet::new();
+		if !manifest.state_hashes.iter().chain(manifest.block_hashes.iter()).all(|hash| seen.insert(*hash)) {
+			*self.restoration_failed.lock() = true;
+			return;
+		}
 		*self.restoration_manifest.lock() = Some(manifest);
-		self.state_restoration_chunks.lock().clear();
-		self.block_restoration_chunks.lock().clear();
 	}
 
 	fn abort_restore(&self) {
 		*self.restoration_manifest.lock() = None;
 		self.state_restoration_chunks.lock().clear();
 		self.block_restoration_chunks.lock().clear();
+		self.restored_chunks.lock().clear();
+		*self.restored_bytes.lock() = 0;
+		*self.restoration_failed.lock() = false;
 	}
 
 	fn restore_state_chunk(&self, hash: H256, chunk: Bytes) {
-		if self.restoration_manifest.lock().as_ref().map_or(false, |ref m| m.state_hashes.iter().any(|h| h == &hash)) {
-			self.state_restoration_chunks.lock().insert(hash, chunk);
-		}
+		self.restore_chunk(hash, chunk, true);
 	}
 
 	fn restore_block_chunk(&self, hash: H256, chunk: Bytes) {
-		if self.restoration_manifest.lock().as_ref().map_or(false, |ref m| m.block_hashes.iter().any(|h| h == &hash)) {
-			self.block_restoration_chunks.lock().insert(hash, chunk);
-		}
+		self.restore_chunk(hash, chunk, false);
+	}
+
+	fn rejected_chunks(&self) -> Vec<RejectedChunk> {
+		self.rejected_chunks.lock().clone()
 	}
 }
 
@@ -121,3 +221,133 @@ fn snapshot_sync() {
 	assert_eq!(net.peer(1).snapshot_service.block_restoration_chunks.lock().len(), net.peer(0).snapshot_service.manifest.as_ref().unwrap().block_hashes.len());
 }
 
+#[test]
+fn restoration_tracks_bytes_done() {
+	let service = TestSnapshotService::new();
+	let state_chunks: Vec<Bytes> = vec![vec![1u8; 256], vec![2u8; 2048], vec![3u8; 256]];
+	let block_chunks: Vec<Bytes> = vec![vec![4u8; 2048]];
+	service.begin_restore(ManifestData {
+		state_hashes: state_chunks.iter().map(|data| data.sha3()).collect(),
+		block_hashes: block_chunks.iter().map(|data| data.sha3()).collect(),
+		state_chunk_sizes: Vec::new(),
+		block_chunk_sizes: Vec::new(),
+		state_root: H256::new(),
+		block_number: 1,
+		block_hash: H256::new(),
+	});
+
+	service.restore_state_chunk(state_chunks[0].sha3(), state_chunks[0].clone());
+	service.restore_state_chunk(state_chunks[1].sha3(), state_chunks[1].clone());
+	service.restore_block_chunk(block_chunks[0].sha3(), block_chunks[0].clone());
+
+	assert_eq!(service.status(), RestorationStatus::Ongoing {
+		state_chunks_done: 2,
+		block_chunks_done: 1,
+		bytes_done: 256 + 2048 + 2048,
+	});
+}
+
+#[test]
+fn manifest_delta_reports_only_new_chunks() {
+	let service = TestSnapshotService::new();
+	let hashes: Vec<H256> = (1..8).map(H256::from).collect();
+	let old = ManifestData {
+		state_hashes: hashes[0..4].to_vec(),
+		block_hashes: hashes[4..6].to_vec(),
+		state_chunk_sizes: Vec::new(),
+		block_chunk_sizes: Vec::new(),
+		state_root: H256::new(),
+		block_number: 1,
+		block_hash: H256::new(),
+	};
+	let new = ManifestData {
+		state_hashes: vec![hashes[0], hashes[1], hashes[2], hashes[6]],
+		block_hashes: vec![hashes[4], hashes[3]],
+		state_chunk_sizes: Vec::new(),
+		block_chunk_sizes: Vec::new(),
+		state_root: H256::new(),
+		block_number: 2,
+		block_hash: H256::new(),
+	};
+
+	assert_eq!(service.delta_from(&old, &new), vec![hashes[6]]);
+	assert_eq!(service.delta_from(&new, &old), vec![hashes[5]]);
+	assert!(service.delta_from(&old, &old).is_empty());
+}
+
+#[test]
+fn manifest_listing_a_chunk_twice_is_refused() {
+	let service = TestSnapshotService::new();
+	let shared: Bytes = vec![1u8; 256];
+	let state: Bytes = vec![2u8; 128];
+	service.begin_restore(ManifestData {
+		state_hashes: vec![shared.sha3(), state.sha3()],
+		block_hashes: vec![shared.sha3()],
+		state_chunk_sizes: Vec::new(),
+		block_chunk_sizes: Vec::new(),
+		state_root: H256::new(),
+		block_number: 1,
+		block_hash: H256::new(),
+	});
+	assert_eq!(service.status(), RestorationStatus::Failed);
+
+	service.restore_state_chunk(shared.sha3(), shared.clone());
+	assert!(service.restored_chunks.lock().is_empty());
+	assert_eq!(service.rejected_chunks().len(), 1);
+}
+
+#[test]
+fn restoration_completion_is_notified_once() {
+	let service = TestSnapshotService::new();
+	let state_chunks: Vec<Bytes> = vec![vec![1u8; 256], vec![2u8; 256]];
+	let block_chunks: Vec<Bytes> = vec![vec![3u8; 256]];
+	service.begin_restore(ManifestData {
+		state_hashes: state_chunks.iter().map(|data| data.sha3()).collect(),
+		block_hashes: block_chunks.iter().map(|data| data.sha3()).collect(),
+		state_chunk_sizes: Vec::new(),
+		block_chunk_sizes: Vec::new(),
+		state_root: H256::new(),
+		block_number: 1,
+		block_hash: H256::new(),
+	});
+	let completed = service.on_complete();
+
+	service.restore_state_chunk(state_chunks[0].sha3(), state_chunks[0].clone());
+	service.restore_block_chunk(block_chunks[0].sha3(), block_chunks[0].clone());
+	assert!(completed.try_recv().is_err());
+
+	service.restore_state_chunk(state_chunks[1].sha3(), state_chunks[1].clone());
+	service.restore_state_chunk(state_chunks[1].sha3(), state_chunks[1].clone());
+	assert!(completed.try_recv().is_ok());
+	assert!(completed.try_recv().is_err());
+}
+
+#[test]
+fn state_root_mismatch_fails_restoration() {
+	let service = TestSnapshotService::new();
+	let state_chunks: Vec<Bytes> = vec![vec![1u8; 256], vec![2u8; 256]];
+	let mut manifest = ManifestData {
+		state_hashes: state_chunks.iter().map(|data| data.sha3()).collect(),
+		block_hashes: Vec::new(),
+		state_chunk_sizes: Vec::new(),
+		block_chunk_sizes: Vec::new(),
+		state_root: ordered_trie_root(state_chunks.clone()),
+		block_number: 1,
+		block_hash: H256::new(),
+	};
+	service.begin_restore(manifest.clone());
+	service.restore_state_chunk(state_chunks[0].sha3(), state_chunks[0].clone());
+	assert!(service.verify_state_root().is_err());
+	service.restore_state_chunk(state_chunks[1].sha3(), state_chunks[1].clone());
+	assert_eq!(service.verify_state_root().unwrap(), true);
+	assert_eq!(service.status(), RestorationStatus::Inactive);
+
+	// every chunk is valid on its own, but they don't make up the declared state
+	manifest.state_root = ordered_trie_root(vec![state_chunks[1].clone(), state_chunks[0].clone()]);
+	service.begin_restore(manifest);
+	for chunk in &state_chunks {
+		service.restore_state_chunk(chunk.sha3(), chunk.clone());
+	}
+	assert_eq!(service.verify_state_root().unwrap(), false);
+	assert_eq!(service.status(), RestorationStatus::Failed);
+}
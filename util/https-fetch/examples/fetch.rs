@@ -9,7 +9,7 @@ fn main() {
 	let client = Client::new().unwrap();
 	let aborted = Arc::new(AtomicBool::new(false));
 
-	client.fetch(Url::new("github.com", 443, "/").unwrap(), Box::new(io::stdout()), aborted, |result| {
+	client.fetch(Url::new("github.com", 443, "/").unwrap(), Vec::new(), Box::new(io::stdout()), aborted, |result| {
 		assert!(result.is_ok());
 	}).unwrap();
 }
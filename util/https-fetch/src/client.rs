@@ -58,7 +58,7 @@ impl From<TlsClientError> for FetchError {
 pub type FetchResult = Result<(), FetchError>;
 
 pub enum ClientMessage {
-	Fetch(Url, Box<io::Write + Send>, Arc<AtomicBool>, Box<FnMut(FetchResult) + Send>),
+	Fetch(Url, Vec<(String, String)>, Box<io::Write + Send>, Arc<AtomicBool>, Box<FnMut(FetchResult) + Send>),
 	Shutdown,
 }
 
@@ -95,9 +95,9 @@ impl Client {
 		})
 	}
 
-	pub fn fetch_to_file<F: FnOnce(FetchResult) + Send + 'static>(&self, url: Url, path: PathBuf, abort: Arc<AtomicBool>, callback: F) -> Result<(), FetchError> {
+	pub fn fetch_to_file<F: FnOnce(FetchResult) + Send + 'static>(&self, url: Url, headers: Vec<(String, String)>, path: PathBuf, abort: Arc<AtomicBool>, callback: F) -> Result<(), FetchError> {
 		let file = try!(fs::File::create(&path));
-		self.fetch(url, Box::new(file), abort, move |result| {
+		self.fetch(url, headers, Box::new(file), abort, move |result| {
 			if let Err(_) = result {
 				// remove temporary file
 				let _ = fs::remove_file(&path);
@@ -106,9 +106,9 @@ impl Client {
 		})
 	}
 
-	pub fn fetch<F: FnOnce(FetchResult) + Send + 'static>(&self, url: Url, writer: Box<io::Write + Send>, abort: Arc<AtomicBool>, callback: F) -> Result<(), FetchError> {
+	pub fn fetch<F: FnOnce(FetchResult) + Send + 'static>(&self, url: Url, headers: Vec<(String, String)>, writer: Box<io::Write + Send>, abort: Arc<AtomicBool>, callback: F) -> Result<(), FetchError> {
 		let cell = RefCell::new(Some(callback));
-		try!(self.channel.send(ClientMessage::Fetch(url, writer, abort, Box::new(move |res| {
+		try!(self.channel.send(ClientMessage::Fetch(url, headers, writer, abort, Box::new(move |res| {
 			cell.borrow_mut().take().expect("Called only once.")(res);
 		}))));
 		Ok(())
@@ -125,6 +125,20 @@ impl Client {
 	}
 }
 
+/// Builds a raw `GET` request for given url, including extra headers.
+fn http_request(url: &Url, headers: &[(String, String)]) -> String {
+	let mut request = format!(
+		"GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nAccept-Encoding: identity\r\n",
+		url.path(),
+		url.hostname()
+	);
+	for &(ref name, ref value) in headers {
+		request.push_str(&format!("{}: {}\r\n", name, value));
+	}
+	request.push_str("\r\n");
+	request
+}
+
 pub struct ClientLoop {
 	next_token: usize,
 	sessions: HashMap<usize, TlsClient>,
@@ -150,16 +164,12 @@ impl mio::Handler for ClientLoop {
 	fn notify(&mut self, event_loop: &mut mio::EventLoop<Self>, msg: Self::Message) {
 		match msg {
 			ClientMessage::Shutdown => event_loop.shutdown(),
-			ClientMessage::Fetch(url, writer, abort, callback) => {
+			ClientMessage::Fetch(url, headers, writer, abort, callback) => {
 				let token = self.next_token;
 				self.next_token += 1;
 
 				if let Ok(mut tlsclient) = TlsClient::new(mio::Token(token), &url, writer, abort, callback) {
-					let httpreq = format!(
-						"GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nAccept-Encoding: identity\r\n\r\n",
-						url.path(),
-						url.hostname()
-					);
+					let httpreq = http_request(&url, &headers);
 					let _ = tlsclient.write(httpreq.as_bytes());
 					tlsclient.register(event_loop);
 
@@ -200,7 +210,7 @@ fn should_successfuly_fetch_a_page() {
 		data: Cursor::new(Vec::new()),
 	};
 	let (tx, rx) = mpsc::channel();
-	client.fetch(Url::new("github.com", 443, "/").unwrap(), Box::new(writer), Arc::new(AtomicBool::new(false)), move |result| {
+	client.fetch(Url::new("github.com", 443, "/").unwrap(), Vec::new(), Box::new(writer), Arc::new(AtomicBool::new(false)), move |result| {
 		assert!(result.is_ok());
 		assert!(wrote.load(Ordering::Relaxed) > 0);
 		tx.send(result).unwrap();
@@ -208,3 +218,14 @@ fn should_successfuly_fetch_a_page() {
 	let _ = rx.recv().unwrap();
 }
 
+#[test]
+fn should_include_extra_headers_in_request() {
+	let url = Url::new("localhost", 443, "/ethcore").unwrap();
+	let headers = vec![("Authorization".to_owned(), "Basic dXNlcjpwYXNz".to_owned())];
+
+	let request = http_request(&url, &headers);
+
+	assert!(request.starts_with("GET /ethcore HTTP/1.1\r\nHost: localhost\r\n"));
+	assert!(request.contains("\r\nAuthorization: Basic dXNlcjpwYXNz\r\n"));
+	assert!(request.ends_with("\r\n\r\n"));
+}
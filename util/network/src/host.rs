@@ -486,6 +486,15 @@ impl Host {
 		Ok(())
 	}
 
+	pub fn clear_reserved_nodes(&self) {
+		self.reserved_nodes.write().clear();
+	}
+
+	pub fn reserved_nodes(&self) -> Vec<String> {
+		let nodes = self.nodes.read();
+		self.reserved_nodes.read().iter().filter_map(|id| nodes.get(id)).map(|n| n.to_string()).collect()
+	}
+
 	pub fn client_version() -> String {
 		version()
 	}
@@ -494,6 +503,11 @@ impl Host {
 		self.info.read().public_endpoint.as_ref().map(|e| format!("{}", Node::new(self.info.read().id().clone(), e.clone())))
 	}
 
+	/// Address the node accepts connections on.
+	pub fn local_address(&self) -> SocketAddr {
+		self.info.read().local_endpoint.address
+	}
+
 	pub fn local_url(&self) -> String {
 		let r = format!("{}", Node::new(self.info.read().id().clone(), self.info.read().local_endpoint.clone()));
 		println!("{}", r);
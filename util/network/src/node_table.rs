@@ -231,6 +231,11 @@ impl NodeTable {
 		self.nodes.values().map(|n| NodeEntry { endpoint: n.endpoint.clone(), id: n.id.clone() }).collect()
 	}
 
+	/// Get particular node
+	pub fn get(&self, id: &NodeId) -> Option<&Node> {
+		self.nodes.get(id)
+	}
+
 	/// Get particular node
 	pub fn get_mut(&mut self, id: &NodeId) -> Option<&mut Node> {
 		self.nodes.get_mut(id)
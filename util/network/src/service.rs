@@ -18,9 +18,12 @@ use {NetworkProtocolHandler, NetworkConfiguration, NonReservedPeerMode};
 use error::NetworkError;
 use host::{Host, NetworkContext, NetworkIoMessage, ProtocolId};
 use stats::NetworkStats;
+use node_table::Node;
 use io::*;
 use parking_lot::RwLock;
 use std::sync::Arc;
+use std::net::SocketAddr;
+use std::str::FromStr;
 use ansi_term::Colour;
 
 struct HostHandler {
@@ -48,7 +51,7 @@ pub struct NetworkService {
 	stats: Arc<NetworkStats>,
 	panic_handler: Arc<PanicHandler>,
 	host_handler: Arc<HostHandler>,
-	config: NetworkConfiguration,
+	config: RwLock<NetworkConfiguration>,
 }
 
 impl NetworkService {
@@ -67,7 +70,7 @@ impl NetworkService {
 			stats: stats,
 			panic_handler: panic_handler,
 			host: RwLock::new(None),
-			config: config,
+			config: RwLock::new(config),
 			host_handler: host_handler,
 		})
 	}
@@ -98,8 +101,25 @@ impl NetworkService {
 	}
 
 	/// Returns network configuration.
-	pub fn config(&self) -> &NetworkConfiguration {
-		&self.config
+	pub fn config(&self) -> NetworkConfiguration {
+		self.config.read().clone()
+	}
+
+	/// Returns the address accepting connections, if the network is running.
+	pub fn listen_address(&self) -> Option<SocketAddr> {
+		let host = self.host.read();
+		host.as_ref().map(|h| h.local_address())
+	}
+
+	/// Sets the port to listen on once the network is (re)started, returns the one configured before.
+	/// The port is bound only by `start`, which fails if it's taken.
+	pub fn set_listen_port(&self, port: u16) -> u16 {
+		let mut config = self.config.write();
+		let mut address = config.listen_address.unwrap_or_else(|| SocketAddr::from_str("0.0.0.0:30304").unwrap());
+		let previous = address.port();
+		address.set_port(port);
+		config.listen_address = Some(address);
+		previous
 	}
 
 	/// Returns external url if available.
@@ -118,7 +138,7 @@ impl NetworkService {
 	pub fn start(&self) -> Result<(), NetworkError> {
 		let mut host = self.host.write();
 		if host.is_none() {
-			let h = Arc::new(try!(Host::new(self.config.clone(), self.stats.clone())));
+			let h = Arc::new(try!(Host::new(self.config(), self.stats.clone())));
 			try!(self.io_service.register_handler(h.clone()));
 			*host = Some(h);
 		}
@@ -141,31 +161,57 @@ impl NetworkService {
 		Ok(())
 	}
 
-	/// Try to add a reserved peer.
+	/// Try to add a reserved peer. It's kept in the configuration, so it's still reserved
+	/// after the network is restarted.
 	pub fn add_reserved_peer(&self, peer: &str) -> Result<(), NetworkError> {
 		let host = self.host.read();
 		if let Some(ref host) = *host {
-			host.add_reserved_node(peer)
-		} else {
-			Ok(())
+			try!(host.add_reserved_node(peer));
+			let mut config = self.config.write();
+			if !config.reserved_nodes.iter().any(|node| node == peer) {
+				config.reserved_nodes.push(peer.to_owned());
+			}
 		}
+		Ok(())
 	}
 
-	/// Try to remove a reserved peer.
+	/// Try to remove a reserved peer, from the configuration as well.
 	pub fn remove_reserved_peer(&self, peer: &str) -> Result<(), NetworkError> {
 		let host = self.host.read();
 		if let Some(ref host) = *host {
-			host.remove_reserved_node(peer)
-		} else {
-			Ok(())
+			try!(host.remove_reserved_node(peer));
+			let id = try!(Node::from_str(peer)).id;
+			self.config.write().reserved_nodes.retain(|node| Node::from_str(node).map_or(true, |node| node.id != id));
 		}
+		Ok(())
+	}
+
+	/// Remove all the reserved peers, along with the configured ones so they are not restored
+	/// when the network is started again. Returns false if the network is not running.
+	pub fn clear_reserved_peers(&self) -> bool {
+		let host = self.host.read();
+		match *host {
+			Some(ref host) => {
+				host.clear_reserved_nodes();
+				self.config.write().reserved_nodes.clear();
+				true
+			},
+			None => false,
+		}
+	}
+
+	/// Get the reserved peers as enode urls.
+	pub fn reserved_peers(&self) -> Vec<String> {
+		let host = self.host.read();
+		host.as_ref().map_or_else(Vec::new, |h| h.reserved_nodes())
 	}
 
-	/// Set the non-reserved peer mode.
+	/// Set the non-reserved peer mode, kept in the configuration for restarts.
 	pub fn set_non_reserved_mode(&self, mode: NonReservedPeerMode) {
 		let host = self.host.read();
 		if let Some(ref host) = *host {
 			let io_ctxt = IoContext::new(self.io_service.channel(), 0);
+			self.config.write().non_reserved_mode = mode.clone();
 			host.set_non_reserved_mode(mode, &io_ctxt);
 		}
 	}
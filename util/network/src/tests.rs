@@ -128,6 +128,55 @@ fn net_start_stop() {
 	service.start().unwrap();
 }
 
+#[test]
+fn net_start_fails_on_taken_port() {
+	let taken = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+	let service = NetworkService::new(NetworkConfiguration::new_local()).unwrap();
+
+	service.set_listen_port(taken.local_addr().unwrap().port());
+
+	assert!(service.start().is_err());
+	assert!(service.listen_address().is_none());
+}
+
+#[test]
+fn net_clear_reserved_peers() {
+	let node = "enode://a979fb575495b8d6db44f750317d0f4622bf4c2aa3365d6af7c284339968eef29b69ad0dce72a4d8db5ebb4968de0e3bec910127f134779fbcb0cb6d3331163c@22.99.55.44:7770";
+	let mut config = NetworkConfiguration::new_local();
+	config.reserved_nodes = vec![node.to_owned()];
+	let service = NetworkService::new(config).unwrap();
+	assert!(!service.clear_reserved_peers());
+
+	service.start().unwrap();
+	assert_eq!(service.reserved_peers().len(), 1);
+	assert!(service.clear_reserved_peers());
+	assert!(service.config().reserved_nodes.is_empty());
+
+	service.stop().unwrap();
+	service.start().unwrap();
+	assert!(service.reserved_peers().is_empty());
+}
+
+#[test]
+fn net_reserved_peers_survive_restart() {
+	let node = "enode://a979fb575495b8d6db44f750317d0f4622bf4c2aa3365d6af7c284339968eef29b69ad0dce72a4d8db5ebb4968de0e3bec910127f134779fbcb0cb6d3331163c@22.99.55.44:7770";
+	let removed = "enode://a979fb575495b8d6db44f750317d0f4622bf4c2aa3365d6af7c284339968eef29b69ad0dce72a4d8db5ebb4968de0e3bec910127f134779fbcb0cb6d3331163d@22.99.55.44:7771";
+	let mut config = NetworkConfiguration::new_local();
+	config.reserved_nodes = vec![removed.to_owned()];
+	let service = NetworkService::new(config).unwrap();
+	service.start().unwrap();
+
+	service.add_reserved_peer(node).unwrap();
+	service.remove_reserved_peer(removed).unwrap();
+	service.set_non_reserved_mode(NonReservedPeerMode::Deny);
+	service.stop().unwrap();
+	service.start().unwrap();
+
+	assert_eq!(service.config().reserved_nodes, vec![node.to_owned()]);
+	assert_eq!(service.reserved_peers().len(), 1);
+	assert_eq!(service.config().non_reserved_mode, NonReservedPeerMode::Deny);
+}
+
 #[test]
 fn net_disconnect() {
 	let key1 = Random.generate().unwrap();
@@ -178,16 +178,24 @@ impl Default for DatabaseConfig {
 	}
 }
 
+enum IteratorSource {
+	Disk(DBIterator),
+	Memory(::std::vec::IntoIter<(Box<[u8]>, Box<[u8]>)>),
+}
+
 /// Database iterator for flushed data only
 pub struct DatabaseIterator {
-	iter: DBIterator,
+	iter: IteratorSource,
 }
 
 impl<'a> Iterator for DatabaseIterator {
 	type Item = (Box<[u8]>, Box<[u8]>);
 
     fn next(&mut self) -> Option<Self::Item> {
-		self.iter.next()
+		match self.iter {
+			IteratorSource::Disk(ref mut iter) => iter.next(),
+			IteratorSource::Memory(ref mut iter) => iter.next(),
+		}
 	}
 }
 
@@ -202,7 +210,10 @@ pub struct Database {
 	config: DatabaseConfig,
 	write_opts: WriteOptions,
 	overlay: RwLock<Vec<HashMap<ElasticArray32<u8>, KeyState>>>,
+	/// Data of an in-memory database, by column. Empty otherwise.
+	memory: RwLock<Vec<BTreeMap<Bytes, Bytes>>>,
 	path: String,
+	in_memory: bool,
 }
 
 impl Database {
@@ -211,6 +222,19 @@ impl Database {
 		Database::open(&DatabaseConfig::default(), path)
 	}
 
+	/// Create a database which keeps all the data in memory and never touches the disk.
+	pub fn in_memory(columns: Option<u32>) -> Database {
+		Database {
+			db: RwLock::new(None),
+			config: DatabaseConfig::with_columns(columns),
+			write_opts: WriteOptions::new(),
+			overlay: RwLock::new(Vec::new()),
+			memory: RwLock::new((0..(columns.unwrap_or(0) + 1)).map(|_| BTreeMap::new()).collect()),
+			path: String::new(),
+			in_memory: true,
+		}
+	}
+
 	/// Open database file. Creates if it does not exist.
 	pub fn open(config: &DatabaseConfig, path: &str) -> Result<Database, String> {
 		let mut opts = Options::new();
@@ -292,7 +316,9 @@ impl Database {
 			config: config.clone(),
 			write_opts: write_opts,
 			overlay: RwLock::new((0..(num_cols + 1)).map(|_| HashMap::new()).collect()),
+			memory: RwLock::new(Vec::new()),
 			path: path.to_owned(),
+			in_memory: false,
 		})
 	}
 
@@ -306,8 +332,26 @@ impl Database {
 		col.map_or(0, |c| (c + 1) as usize)
 	}
 
+	/// Apply the transaction to the data of an in-memory database. Deleted keys are dropped right away.
+	fn write_memory(&self, tr: DBTransaction) {
+		let mut memory = self.memory.write();
+		for op in tr.ops {
+			match op {
+				DBOp::Insert { col, key, value } | DBOp::InsertCompressed { col, key, value } => {
+					memory[Self::to_overlay_column(col)].insert(key.to_vec(), value);
+				},
+				DBOp::Delete { col, key } => {
+					memory[Self::to_overlay_column(col)].remove(&*key);
+				},
+			}
+		}
+	}
+
 	/// Commit transaction to database.
 	pub fn write_buffered(&self, tr: DBTransaction) {
+		if self.in_memory {
+			return self.write_memory(tr);
+		}
 		let mut overlay = self.overlay.write();
 		let ops = tr.ops;
 		for op in ops {
@@ -330,6 +374,10 @@ impl Database {
 
 	/// Commit buffered changes to database.
 	pub fn flush(&self) -> Result<(), String> {
+		if self.in_memory {
+			return Ok(());
+		}
+
 		match &*self.db.read() {
 			&Some(DBAndColumns { ref db, ref cfs }) => {
 				let batch = WriteBatch::new();
@@ -373,6 +421,11 @@ impl Database {
 
 	/// Commit transaction to database.
 	pub fn write(&self, tr: DBTransaction) -> Result<(), String> {
+		if self.in_memory {
+			self.write_memory(tr);
+			return Ok(());
+		}
+
 		match &*self.db.read() {
 			&Some(DBAndColumns { ref db, ref cfs }) => {
 				let batch = WriteBatch::new();
@@ -412,10 +465,18 @@ impl Database {
 					},
 				}
 			},
+			&None if self.in_memory => Ok(self.memory.read()[Self::to_overlay_column(col)].get(key).cloned()),
 			&None => Ok(None),
 		}
 	}
 
+	/// Key-value pairs of an in-memory database column, in key order.
+	fn memory_entries(&self, col: Option<u32>) -> Vec<(Box<[u8]>, Box<[u8]>)> {
+		self.memory.read()[Self::to_overlay_column(col)].iter()
+			.map(|(key, value)| (key.clone().into_boxed_slice(), value.clone().into_boxed_slice()))
+			.collect()
+	}
+
 	/// Get value by partial key. Prefix size should match configured prefix size. Only searches flushed values.
 	// TODO: support prefix seek for unflushed data
 	pub fn get_by_prefix(&self, col: Option<u32>, prefix: &[u8]) -> Option<Box<[u8]>> {
@@ -429,6 +490,13 @@ impl Database {
 					_ => None
 				}
 			},
+			&None if self.in_memory => {
+				let memory = &self.memory.read()[Self::to_overlay_column(col)];
+				match memory.range::<[u8], _>(prefix..).next() {
+					Some((k, v)) if k.starts_with(prefix) => Some(v.clone().into_boxed_slice()),
+					_ => None,
+				}
+			},
 			&None => None,
 		}
 	}
@@ -438,13 +506,58 @@ impl Database {
 		//TODO: iterate over overlay
 		match &*self.db.read() {
 			&Some(DBAndColumns { ref db, ref cfs }) => {
-				col.map_or_else(|| DatabaseIterator { iter: db.iterator(IteratorMode::Start) },
-					|c| DatabaseIterator { iter: db.iterator_cf(cfs[c as usize], IteratorMode::Start).unwrap() })
+				col.map_or_else(|| DatabaseIterator { iter: IteratorSource::Disk(db.iterator(IteratorMode::Start)) },
+					|c| DatabaseIterator { iter: IteratorSource::Disk(db.iterator_cf(cfs[c as usize], IteratorMode::Start).unwrap()) })
 			},
+			&None if self.in_memory => DatabaseIterator { iter: IteratorSource::Memory(self.memory_entries(col).into_iter()) },
 			&None => panic!("Not supported yet") //TODO: return an empty iterator or change return type
 		}
 	}
 
+	/// Get the value of a rocksdb property for the given column. None if the property is unknown or the database is closed.
+	pub fn property(&self, col: Option<u32>, name: &str) -> Option<String> {
+		match &*self.db.read() {
+			&Some(DBAndColumns { ref db, ref cfs }) => {
+				col.map_or_else(|| db.property_value(name), |c| db.property_value_cf(cfs[c as usize], name))
+			},
+			&None => None,
+		}
+	}
+
+	/// Get the estimated number of keys in the given column. Only accounts for flushed data.
+	pub fn estimated_num_keys(&self, col: Option<u32>) -> Result<u64, String> {
+		if self.in_memory {
+			return Ok(self.memory.read()[Self::to_overlay_column(col)].len() as u64);
+		}
+		let value = try!(self.property(col, "rocksdb.estimate-num-keys").ok_or_else(|| "Database is closed".to_owned()));
+		value.parse().map_err(|e| format!("Invalid key count estimate {:?}: {:?}", value, e))
+	}
+
+	/// Compact the flushed data of the given column in `[start, end)`, `None` bounds meaning the first and the last key.
+	/// Drops the tombstones left by deletions, so the space is reclaimed on disk. No-op for in-memory databases.
+	pub fn compact_range(&self, col: Option<u32>, start: Option<&[u8]>, end: Option<&[u8]>) -> Result<(), String> {
+		match &*self.db.read() {
+			&Some(DBAndColumns { ref db, ref cfs }) => {
+				match col {
+					None => db.compact_range(start, end),
+					Some(c) => db.compact_range_cf(cfs[c as usize], start, end),
+				}
+				Ok(())
+			},
+			&None if self.in_memory => Ok(()),
+			&None => Err("Database is closed".to_owned()),
+		}
+	}
+
+	/// Check that the database is open and readable, unlike `get` this fails once it's closed.
+	pub fn ping(&self) -> Result<(), String> {
+		match &*self.db.read() {
+			&Some(DBAndColumns { ref db, .. }) => db.get(&[]).map(|_| ()),
+			&None if self.in_memory => Ok(()),
+			&None => Err("Database is closed".to_owned()),
+		}
+	}
+
 	/// Close the database
 	fn close(&self) {
 		*self.db.write() = None;
@@ -504,6 +617,10 @@ mod tests {
 	fn test_db(config: &DatabaseConfig) {
 		let path = RandomTempPath::create_dir();
 		let db = Database::open(config, path.as_path().to_str().unwrap()).unwrap();
+		check_db(&db);
+	}
+
+	fn check_db(db: &Database) {
 		let key1 = H256::from_str("02c69be41d0b7e40352fc85be1cd65eb03d40ef8427a0ca4596b1ead9a00e9fc").unwrap();
 		let key2 = H256::from_str("03c69be41d0b7e40352fc85be1cd65eb03d40ef8427a0ca4596b1ead9a00e9fc").unwrap();
 		let key3 = H256::from_str("01c69be41d0b7e40352fc85be1cd65eb03d40ef8427a0ca4596b1ead9a00e9fc").unwrap();
@@ -560,4 +677,32 @@ mod tests {
 		let _ = Database::open_default(path.as_path().to_str().unwrap()).unwrap();
 		test_db(&DatabaseConfig::default());
 	}
+
+	#[test]
+	fn in_memory_kvdb() {
+		check_db(&Database::in_memory(None));
+	}
+
+	#[test]
+	fn in_memory_kvdb_drops_deleted_keys() {
+		let db = Database::in_memory(Some(1));
+		let mut batch = db.transaction();
+		for i in 0..10u8 {
+			batch.put(Some(0), &[i], &[i]);
+		}
+		db.write(batch).unwrap();
+
+		let mut batch = db.transaction();
+		for i in 0..5u8 {
+			batch.delete(Some(0), &[i]);
+		}
+		db.write(batch).unwrap();
+
+		assert_eq!(db.memory.read()[1].len(), 5);
+		assert_eq!(db.estimated_num_keys(Some(0)).unwrap(), 5);
+		assert_eq!(db.iter(Some(0)).map(|(k, _)| k[0]).collect::<Vec<_>>(), vec![5, 6, 7, 8, 9]);
+		assert_eq!(&*db.get_by_prefix(Some(0), &[7]).unwrap(), &[7]);
+		assert!(db.get_by_prefix(Some(0), &[2]).is_none());
+		assert!(db.iter(None).next().is_none());
+	}
 }
@@ -86,6 +86,29 @@ impl MemoryDB {
 		}
 	}
 
+	/// Create a new instance of the memory DB with space for at least `capacity` nodes.
+	pub fn with_capacity(capacity: usize) -> MemoryDB {
+		MemoryDB {
+			data: H256FastMap::with_capacity_and_hasher(capacity, Default::default()),
+			aux: HashMap::new(),
+		}
+	}
+
+	/// Number of nodes the memory DB can hold without reallocating.
+	pub fn capacity(&self) -> usize {
+		self.data.capacity()
+	}
+
+	/// Number of nodes held, including zero-referenced ones which weren't purged yet.
+	pub fn len(&self) -> usize {
+		self.data.len()
+	}
+
+	/// Whether no nodes are held at all.
+	pub fn is_empty(&self) -> bool {
+		self.data.is_empty()
+	}
+
 	/// Clear all data from the database.
 	///
 	/// # Examples
@@ -125,6 +148,11 @@ impl MemoryDB {
 		mem::replace(&mut self.aux, HashMap::new())
 	}
 
+	/// Get the keys of all the auxiliary data, in no particular order.
+	pub fn aux_keys(&self) -> Vec<Bytes> {
+		self.aux.keys().cloned().collect()
+	}
+
 	/// Grab the raw information associated with a key. Returns None if the key
 	/// doesn't exist.
 	///
@@ -22,10 +22,205 @@ use bytes::*;
 use rlp::*;
 use hashdb::*;
 use memorydb::*;
+use sha3::*;
 use std::sync::*;
-use std::collections::HashMap;
+use std::{cmp, mem};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
 use kvdb::{Database, DBTransaction};
 
+/// Number of nodes written per transaction by `bulk_load`.
+const BULK_LOAD_BATCH_SIZE: usize = 8192;
+
+/// Prefixes of the auxiliary data keys in the backing database. Node keys are always 32 bytes long,
+/// so auxiliary keys which would end up that long get the longer prefix instead.
+const AUX_PREFIX: &'static [u8] = b"\xffaux";
+const AUX_PREFIX_LONG: &'static [u8] = b"\xfeaux_";
+
+/// Backing database key of the era counter, persisted when era tracking is enabled.
+/// Prefixed apart from the auxiliary data, so it can't be overwritten through `insert_aux`.
+const ERA_KEY: &'static [u8] = b"\xfdera";
+
+/// Backing database key of the auxiliary data.
+fn aux_key(key: &[u8]) -> Bytes {
+	let prefix = match AUX_PREFIX.len() + key.len() == 32 {
+		true => AUX_PREFIX_LONG,
+		false => AUX_PREFIX,
+	};
+	let mut db_key = prefix.to_vec();
+	db_key.extend_from_slice(key);
+	db_key
+}
+
+/// Whether the backing database key holds auxiliary data rather than a node.
+fn is_aux_key(key: &[u8]) -> bool {
+	key.len() != 32 && (key.starts_with(AUX_PREFIX) || key.starts_with(AUX_PREFIX_LONG))
+}
+
+/// Auxiliary data key stored under the given backing database key, the inverse of `aux_key`.
+fn aux_key_origin(db_key: &[u8]) -> &[u8] {
+	match db_key.starts_with(AUX_PREFIX_LONG) {
+		true => &db_key[AUX_PREFIX_LONG.len()..],
+		false => &db_key[AUX_PREFIX.len()..],
+	}
+}
+
+/// Backing database payloads replaced by a single commit, `None` where the key was absent.
+type JournalEntry = Vec<(H256, Option<(Bytes, u32)>)>;
+
+/// Commit put in a batch, applied to the overlay once the batch is written.
+struct PreparedCommit {
+	start: Instant,
+	/// Number of operations, deletions included.
+	ops: u32,
+	deletions: usize,
+	/// Payloads replaced by the commit, journaled when journaling.
+	undo: JournalEntry,
+	/// Payloads put in the batch when the read cache is enabled, `None` where deleted.
+	written: Vec<(H256, Option<(Bytes, u32)>)>,
+}
+
+impl PreparedCommit {
+	fn new() -> PreparedCommit {
+		PreparedCommit {
+			start: Instant::now(),
+			ops: 0,
+			deletions: 0,
+			undo: JournalEntry::new(),
+			written: Vec::new(),
+		}
+	}
+}
+
+/// Bounded cache of backing database lookups, evicting the least recently used entries.
+/// Absent keys are cached as well.
+struct ReadCache {
+	max_entries: usize,
+	entries: HashMap<H256, (Option<(Bytes, u32)>, u64)>,
+	/// Payloads put in the batch of the last `commit_to_batch`, which the caller may not have written yet.
+	/// They take precedence over the backing database and are never evicted, until the next commit.
+	unwritten: HashMap<H256, Option<(Bytes, u32)>>,
+	/// keys by their last use
+	order: BTreeMap<u64, H256>,
+	next_use: u64,
+	misses: usize,
+}
+
+impl ReadCache {
+	fn new(max_entries: usize) -> ReadCache {
+		ReadCache {
+			max_entries: max_entries,
+			entries: HashMap::new(),
+			unwritten: HashMap::new(),
+			order: BTreeMap::new(),
+			next_use: 0,
+			misses: 0,
+		}
+	}
+
+	/// Cached payload of the key, if the key is cached at all. Counts misses.
+	fn get(&mut self, key: &H256) -> Option<Option<(Bytes, u32)>> {
+		if let Some(payload) = self.unwritten.get(key) {
+			return Some(payload.clone());
+		}
+		let use_id = self.next_use;
+		let payload = match self.entries.get_mut(key) {
+			Some(&mut (ref payload, ref mut last_use)) => {
+				self.order.remove(&*last_use);
+				*last_use = use_id;
+				payload.clone()
+			},
+			None => {
+				self.misses += 1;
+				return None;
+			},
+		};
+		self.order.insert(use_id, key.clone());
+		self.next_use += 1;
+		Some(payload)
+	}
+
+	fn insert(&mut self, key: H256, payload: Option<(Bytes, u32)>) {
+		self.invalidate(&key);
+		while self.entries.len() >= self.max_entries {
+			let oldest = match self.order.keys().next() {
+				Some(oldest) => *oldest,
+				None => break,
+			};
+			if let Some(key) = self.order.remove(&oldest) {
+				self.entries.remove(&key);
+			}
+		}
+		self.order.insert(self.next_use, key.clone());
+		self.entries.insert(key, (payload, self.next_use));
+		self.next_use += 1;
+	}
+
+	fn invalidate(&mut self, key: &H256) {
+		self.unwritten.remove(key);
+		if let Some((_, last_use)) = self.entries.remove(key) {
+			self.order.remove(&last_use);
+		}
+	}
+
+	/// Cache the unwritten payloads like the other lookups, their batch is expected to be written by now.
+	fn settle(&mut self) {
+		let unwritten = mem::replace(&mut self.unwritten, HashMap::new());
+		for (key, payload) in unwritten {
+			self.insert(key, payload);
+		}
+	}
+}
+
+/// Summary of a commit, passed to the commit hook.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitStats {
+	/// Number of nodes written to the backing database.
+	pub insertions: usize,
+	/// Number of nodes deleted from the backing database.
+	pub deletions: usize,
+	/// Time taken by the commit.
+	pub elapsed: Duration,
+}
+
+/// Callback invoked after each successful commit, see `OverlayDB::set_commit_hook`.
+pub type CommitHook = Arc<Fn(&CommitStats) + Send + Sync>;
+
+/// Overlay and backing database counters, see `OverlayDB::stats`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OverlayStats {
+	/// Number of nodes held by the overlay, including the ones whose changes cancelled out.
+	pub overlay_entries: usize,
+	/// Number of nodes with pending reference increments.
+	pub pending_insertions: usize,
+	/// Number of nodes with pending reference decrements.
+	pub pending_deletions: usize,
+	/// Estimated number of keys in the backing database column, committed data only.
+	pub backing_keys: u64,
+}
+
+/// Anomalies found by `OverlayDB::verify_consistency`.
+#[derive(Debug, Default, PartialEq)]
+pub struct ConsistencyReport {
+	/// Number of backing database entries checked.
+	pub checked: usize,
+	/// Keys of entries which are not 32 bytes long or whose payload can't be decoded.
+	pub malformed: Vec<Bytes>,
+	/// Entries persisted without any references, which commits delete unless era tracking is enabled.
+	pub zero_refs: Vec<H256>,
+	/// Entries whose value doesn't hash to their key, so references to the value don't resolve.
+	pub orphans: Vec<H256>,
+	/// Keys whose pending overlay removals exceed the references in the backing database.
+	pub negative_refs: Vec<H256>,
+}
+
+impl ConsistencyReport {
+	/// Whether no anomalies were found.
+	pub fn is_consistent(&self) -> bool {
+		self.malformed.is_empty() && self.zero_refs.is_empty() && self.orphans.is_empty() && self.negative_refs.is_empty()
+	}
+}
+
 /// Implementation of the `HashDB` trait for a disk-backed database with a memory overlay.
 ///
 /// The operations `insert()` and `remove()` take place on the memory overlay; batches of
@@ -34,17 +229,136 @@ use kvdb::{Database, DBTransaction};
 ///
 /// `lookup()` and `contains()` maintain normal behaviour - all `insert()` and `remove()`
 /// queries have an immediate effect in terms of these functions.
+///
+/// Auxiliary data is persisted by commits as well, but it's not journaled.
 #[derive(Clone)]
 pub struct OverlayDB {
 	overlay: MemoryDB,
+	removed_aux: HashSet<Bytes>,
 	backing: Arc<Database>,
 	column: Option<u32>,
+	journal: VecDeque<JournalEntry>,
+	journal_depth: usize,
+	commit_hook: Option<CommitHook>,
+	read_cache: Option<Arc<Mutex<ReadCache>>>,
+	era: u64,
+	era_tracking: bool,
 }
 
 impl OverlayDB {
 	/// Create a new instance of OverlayDB given a `backing` database.
 	pub fn new(backing: Arc<Database>, col: Option<u32>) -> OverlayDB {
-		OverlayDB{ overlay: MemoryDB::new(), backing: backing, column: col }
+		Self::new_with_journal(backing, col, 0)
+	}
+
+	/// Create a new instance of OverlayDB which remembers the last `depth` commits,
+	/// so they can be undone with `rollback_to()`.
+	pub fn new_with_journal(backing: Arc<Database>, col: Option<u32>, depth: usize) -> OverlayDB {
+		OverlayDB {
+			overlay: MemoryDB::new(),
+			removed_aux: HashSet::new(),
+			backing: backing,
+			column: col,
+			journal: VecDeque::new(),
+			journal_depth: depth,
+			commit_hook: None,
+			read_cache: None,
+			era: 0,
+			era_tracking: false,
+		}
+	}
+
+	/// Create a new instance of OverlayDB with the overlay preallocated for `capacity` nodes.
+	pub fn with_capacity(backing: Arc<Database>, col: Option<u32>, capacity: usize) -> OverlayDB {
+		let mut db = Self::new(backing, col);
+		db.overlay = MemoryDB::with_capacity(capacity);
+		db
+	}
+
+	/// Create a new instance of OverlayDB backed by an in-memory database, never touching the disk.
+	pub fn new_in_memory() -> OverlayDB {
+		Self::new(Arc::new(Database::in_memory(None)), None)
+	}
+
+	/// Fork the overlay for speculative changes, e.g. tentative block execution.
+	///
+	/// The fork starts with a copy of the pending operations over the same backing database;
+	/// changes to either overlay don't affect the other, so the fork may simply be dropped.
+	/// Note that committing a fork writes to the shared backing database, so the read cache is shared
+	/// as well. The fork doesn't inherit the commit journal, so it can't roll back the original's commits.
+	pub fn fork(&self) -> OverlayDB {
+		OverlayDB {
+			overlay: self.overlay.clone(),
+			removed_aux: self.removed_aux.clone(),
+			backing: self.backing.clone(),
+			column: self.column,
+			journal: VecDeque::new(),
+			journal_depth: self.journal_depth,
+			commit_hook: self.commit_hook.clone(),
+			read_cache: self.read_cache.clone(),
+			era: self.era,
+			era_tracking: self.era_tracking,
+		}
+	}
+
+	/// Register a callback invoked after each successful commit, once its batch is written, e.g. to feed
+	/// a metrics sink. Replaces the previous hook. For `commit_to_batch`, which leaves writing the batch
+	/// to the caller, the hook is invoked once the batch is filled.
+	pub fn set_commit_hook<F>(&mut self, hook: F) where F: Fn(&CommitStats) + Send + Sync + 'static {
+		self.commit_hook = Some(Arc::new(hook));
+	}
+
+	/// Cache up to `max_entries` backing database lookups, least recently used ones are evicted first.
+	/// Zero disables the cache. Keys are invalidated once their commit is written, but writes to the
+	/// backing database from other instances are not noticed.
+	pub fn set_read_cache_size(&mut self, max_entries: usize) {
+		self.read_cache = match max_entries {
+			0 => None,
+			_ => Some(Arc::new(Mutex::new(ReadCache::new(max_entries)))),
+		};
+	}
+
+	/// Replace the backing database, keeping the pending overlay, e.g. after the database was moved
+	/// or restored from a checkpoint.
+	///
+	/// The new backing must hold the state the overlay was built upon, i.e. whatever the old backing
+	/// held; the overlay's reference counts are relative to it and aren't checked. The commit journal
+	/// refers to the old backing, so it's dropped, and the read cache starts empty.
+	pub fn reopen(&mut self, new_backing: Arc<Database>) -> Result<(), UtilError> {
+		try!(new_backing.ping().map_err(UtilError::SimpleString));
+		self.backing = new_backing;
+		self.journal.clear();
+		if let Some(max_entries) = self.read_cache.as_ref().map(|cache| cache.lock().unwrap().max_entries) {
+			self.read_cache = Some(Arc::new(Mutex::new(ReadCache::new(max_entries))));
+		}
+		Ok(())
+	}
+
+	/// Get the era of the last commit, zero unless era tracking is enabled.
+	pub fn era(&self) -> u64 {
+		self.era
+	}
+
+	/// Start an era with every commit and stamp it on the nodes the commit writes, so nodes which are
+	/// no longer referenced can be pruned later with `prune_older_than`. Unreferenced nodes are kept
+	/// in the backing database until pruned, instead of being deleted by the commit.
+	///
+	/// Stamped nodes are stored in a different format, which plain instances still read, and the era
+	/// counter is persisted with the auxiliary data, so the counter carries on from the last commit.
+	/// Nodes stored before the tracking was enabled count as era zero.
+	pub fn enable_era_tracking(&mut self) -> Result<(), UtilError> {
+		let stored = try!(self.backing.get(self.column, ERA_KEY).map_err(UtilError::SimpleString));
+		self.era = match stored {
+			Some(era) => try!(UntrustedRlp::new(&era).as_val()),
+			None => 0,
+		};
+		self.era_tracking = true;
+		Ok(())
+	}
+
+	/// Get the number of lookups which missed the read cache and went to the backing database.
+	pub fn read_cache_misses(&self) -> Option<usize> {
+		self.read_cache.as_ref().map(|cache| cache.lock().unwrap().misses)
 	}
 
 	/// Create a new instance of OverlayDB with an anonymous temporary database.
@@ -58,17 +372,129 @@ impl OverlayDB {
 	/// Commit all operations in a single batch.
 	#[cfg(test)]
 	pub fn commit(&mut self) -> Result<u32, UtilError> {
-		let mut batch = self.backing.transaction();
-		let res = try!(self.commit_to_batch(&mut batch));
-		self.backing.write(batch).map(|_| res).map_err(|e| e.into())
+		let entries = self.overlay.drain();
+		self.write_commit(entries).map(|(ops, _)| ops)
+	}
+
+	/// Commit all operations in a single batch, then compact the backing column so the space
+	/// taken by deleted nodes is reclaimed. Returns the number of operations committed.
+	/// Compaction rewrites the column on disk, so it can take a long time for large databases.
+	pub fn commit_and_compact(&mut self) -> Result<u32, UtilError> {
+		let entries = self.overlay.drain();
+		let (ops, _) = try!(self.write_commit(entries));
+		try!(self.backing.compact_range(self.column, None, None));
+		Ok(ops)
 	}
 
 	/// Commit all operations to given batch.
+	///
+	/// The batch is expected to be written to the backing database. Writing it is up to the caller,
+	/// so the commit counts as done once the batch is filled: the era is started, the replaced
+	/// payloads are journaled and the commit hook is invoked right away. The read cache keeps the new
+	/// payloads until the next commit, so lookups don't read the replaced ones back meanwhile.
 	pub fn commit_to_batch(&mut self, batch: &mut DBTransaction) -> Result<u32, UtilError> {
-		let mut ret = 0u32;
+		let entries = self.overlay.drain();
+		let mut prepared = try!(self.prepare_commit(entries, batch));
+		self.keep_unwritten(mem::replace(&mut prepared.written, Vec::new()));
+		Ok(self.finish_commit(prepared).0)
+	}
+
+	/// Commit all operations in a single batch, returning the change set alongside the stats:
+	/// the inserted nodes with their values and the removed keys, both sorted by key.
+	/// The change set is taken from the overlay, so it lists removals of nodes still referenced in the backing database too.
+	pub fn commit_capturing(&mut self) -> Result<(CommitStats, Vec<(H256, Bytes)>, Vec<H256>), UtilError> {
+		let entries = self.overlay.drain();
+		let mut insertions = Vec::new();
+		let mut deletions = Vec::new();
+		for (key, &(ref value, rc)) in &entries {
+			if rc > 0 {
+				insertions.push((key.clone(), value.clone()));
+			} else if rc < 0 {
+				deletions.push(key.clone());
+			}
+		}
+		insertions.sort_by(|a, b| a.0.cmp(&b.0));
+		deletions.sort();
+
+		let (_, stats) = try!(self.write_commit(entries));
+		Ok((stats, insertions, deletions))
+	}
+
+	/// Commit all operations, writing at most `max_ops_per_batch` of them in a single transaction.
+	/// Returns the number of operations committed.
+	///
+	/// Reference counts are validated before anything is written, so a commit failing validation
+	/// leaves both the backing database and the overlay untouched. When journaling, all the batches
+	/// make up a single commit.
+	///
+	/// Writing isn't atomic though: the overlay is drained before the first batch, so if writing
+	/// a batch fails, the batches written before it stay in the backing database while the rest
+	/// of the operations are lost, and the commit is neither journaled nor counted as an era.
+	pub fn commit_chunked(&mut self, max_ops_per_batch: usize) -> Result<u32, UtilError> {
+		let mut prepared = PreparedCommit::new();
+		self.settle_cached();
+		try!(self.validate_commit());
+
+		let max_ops = cmp::max(max_ops_per_batch, 1);
+		let mut entries = self.overlay.drain().into_iter().filter(|&(_, (_, rc))| rc != 0).peekable();
+		while entries.peek().is_some() {
+			let mut batch = self.backing.transaction();
+			try!(self.put_entries_in_batch(entries.by_ref().take(max_ops), &mut batch, &mut prepared));
+			try!(self.backing.write(batch));
+			self.invalidate_written(&mut prepared);
+		}
+		let mut batch = self.backing.transaction();
+		self.put_aux_in_batch(&mut batch);
+		try!(self.backing.write(batch));
+		Ok(self.finish_commit(prepared).0)
+	}
+
+	/// Commit the drained overlay `entries` in a single batch and write it.
+	fn write_commit(&mut self, entries: H256FastMap<(Bytes, i32)>) -> Result<(u32, CommitStats), UtilError> {
+		let mut batch = self.backing.transaction();
+		let mut prepared = try!(self.prepare_commit(entries, &mut batch));
+		try!(self.backing.write(batch));
+		self.invalidate_written(&mut prepared);
+		Ok(self.finish_commit(prepared))
+	}
+
+	/// Put the drained overlay `entries` and the pending auxiliary data in given batch.
+	/// The commit isn't applied to this instance until `finish_commit`.
+	fn prepare_commit(&mut self, entries: H256FastMap<(Bytes, i32)>, batch: &mut DBTransaction) -> Result<PreparedCommit, UtilError> {
+		let mut prepared = PreparedCommit::new();
+		self.settle_cached();
+		try!(self.put_entries_in_batch(entries.into_iter(), batch, &mut prepared));
+		self.put_aux_in_batch(batch);
+		Ok(prepared)
+	}
+
+	/// Apply a commit once its batch is written: start the new era, journal the replaced payloads
+	/// and invoke the commit hook, if any. Returns the number of operations and the stats.
+	fn finish_commit(&mut self, prepared: PreparedCommit) -> (u32, CommitStats) {
+		if self.era_tracking {
+			self.era += 1;
+		}
+		let stats = CommitStats {
+			insertions: prepared.ops as usize - prepared.deletions,
+			deletions: prepared.deletions,
+			elapsed: prepared.start.elapsed(),
+		};
+		self.journal_commit(prepared.undo);
+		if let Some(ref hook) = self.commit_hook {
+			hook(&stats);
+		}
+		(prepared.ops, stats)
+	}
+
+	/// Put the overlay entries in the batch, stamped with the era being committed, counting
+	/// the operations and noting the replaced payloads when journaling.
+	fn put_entries_in_batch<I>(&self, entries: I, batch: &mut DBTransaction, prepared: &mut PreparedCommit) -> Result<(), UtilError>
+		where I: Iterator<Item=(H256, (Bytes, i32))> {
+		let journaling = self.journal_depth > 0;
+		let caching = self.read_cache.is_some();
+		let era = self.era + 1;
 		let mut deletes = 0usize;
-		for i in self.overlay.drain().into_iter() {
-			let (key, (value, rc)) = i;
+		for (key, (value, rc)) in entries {
 			if rc != 0 {
 				match self.payload(&key) {
 					Some(x) => {
@@ -77,31 +503,417 @@ impl OverlayDB {
 						if total_rc < 0 {
 							return Err(From::from(BaseDataError::NegativelyReferencedHash(key)));
 						}
-						deletes += if self.put_payload_in_batch(batch, &key, (back_value, total_rc as u32)) {1} else {0};
+						if journaling {
+							prepared.undo.push((key.clone(), Some((back_value.clone(), back_rc))));
+						}
+						let payload = (back_value, total_rc as u32);
+						if caching {
+							prepared.written.push((key.clone(), self.stored_payload(&payload)));
+						}
+						deletes += if self.put_payload_in_batch(batch, &key, payload, era) {1} else {0};
 					}
 					None => {
 						if rc < 0 {
 							return Err(From::from(BaseDataError::NegativelyReferencedHash(key)));
 						}
-						self.put_payload_in_batch(batch, &key, (value, rc as u32));
+						if journaling {
+							prepared.undo.push((key.clone(), None));
+						}
+						let payload = (value, rc as u32);
+						if caching {
+							prepared.written.push((key.clone(), self.stored_payload(&payload)));
+						}
+						self.put_payload_in_batch(batch, &key, payload, era);
 					}
 				};
-				ret += 1;
+				prepared.ops += 1;
 			}
 		}
 		trace!("OverlayDB::commit() deleted {} nodes", deletes);
-		Ok(ret)
+		prepared.deletions += deletes;
+		Ok(())
+	}
+
+	/// Move the pending auxiliary data changes into the batch, along with the era being committed.
+	fn put_aux_in_batch(&mut self, batch: &mut DBTransaction) {
+		for (key, value) in self.overlay.drain_aux() {
+			batch.put(self.column, &aux_key(&key), &value);
+		}
+		for key in self.removed_aux.drain() {
+			batch.delete(self.column, &aux_key(&key));
+		}
+		if self.era_tracking {
+			batch.put(self.column, ERA_KEY, &encode(&(self.era + 1)));
+		}
+	}
+
+	/// Remember the payloads replaced by a commit, forgetting the oldest commit if the journal is full.
+	fn journal_commit(&mut self, undo: JournalEntry) {
+		if self.journal_depth == 0 { return; }
+		if self.journal.len() == self.journal_depth {
+			self.journal.pop_front();
+		}
+		self.journal.push_back(undo);
+	}
+
+	/// Undo the last `n` commits by restoring the backing database entries they replaced.
+	/// Fails without changes if fewer than `n` commits are journaled. Uncommitted operations are kept.
+	pub fn rollback_to(&mut self, n: usize) -> Result<(), UtilError> {
+		if n > self.journal.len() {
+			return Err(UtilError::SimpleString(format!("Cannot roll back {} commits, only {} journaled", n, self.journal.len())));
+		}
+
+		let mut batch = self.backing.transaction();
+		let mut restored = Vec::new();
+		for _ in 0..n {
+			let undo = self.journal.pop_back().expect("journal length checked above; qed");
+			for (key, payload) in undo {
+				match payload {
+					Some(payload) => { self.put_payload_in_batch(&mut batch, &key, payload, self.era); },
+					None => batch.delete(self.column, &key),
+				}
+				restored.push(key);
+			}
+		}
+		try!(self.backing.write(batch).map_err(UtilError::SimpleString));
+		for key in &restored {
+			self.invalidate_cached(key);
+		}
+		Ok(())
+	}
+
+	/// Delete the backing nodes which are no longer referenced and were last inserted or removed by
+	/// a commit before `era`. Referenced nodes are never deleted, whatever their era, and neither are
+	/// the nodes with pending overlay operations. Unreferenced nodes are only kept by commits when
+	/// era tracking is enabled, see `enable_era_tracking`. Not journaled. Returns the number of nodes deleted.
+	pub fn prune_older_than(&mut self, era: u64) -> Result<usize, UtilError> {
+		let pending = self.overlay.keys();
+		let mut batch = self.backing.transaction();
+		let mut pruned = Vec::new();
+		for (key, value) in self.backing.iter(self.column) {
+			if is_aux_key(&key) || key.len() != 32 {
+				continue;
+			}
+			let payload = UntrustedRlp::new(&value);
+			let refs: u32 = try!(payload.val_at(0));
+			let last_era: u64 = payload.val_at(2).unwrap_or(0);
+			let key = H256::from_slice(&*key);
+			if refs > 0 || last_era >= era || pending.contains_key(&key) {
+				continue;
+			}
+			batch.delete(self.column, &key);
+			pruned.push(key);
+		}
+		try!(self.backing.write(batch));
+		for key in &pruned {
+			self.invalidate_cached(key);
+		}
+		Ok(pruned.len())
+	}
+
+	/// Write nodes straight to the backing database in large batches, bypassing the overlay.
+	/// Returns the number of nodes written.
+	///
+	/// Each node is stored with a single reference, overwriting whatever was stored under its key,
+	/// so this must only be used on an empty or otherwise known-consistent backing database.
+	pub fn bulk_load<I>(&mut self, entries: I) -> Result<usize, UtilError> where I: Iterator<Item=(H256, Bytes)> {
+		let mut written = 0;
+		let mut batch = self.backing.transaction();
+		let mut keys = Vec::new();
+		for (key, value) in entries {
+			self.put_payload_in_batch(&mut batch, &key, (value, 1), self.era);
+			keys.push(key);
+			written += 1;
+			if written % BULK_LOAD_BATCH_SIZE == 0 {
+				let full = mem::replace(&mut batch, self.backing.transaction());
+				try!(self.backing.write(full));
+				for key in keys.drain(..) {
+					self.invalidate_cached(&key);
+				}
+			}
+		}
+		try!(self.backing.write(batch));
+		for key in &keys {
+			self.invalidate_cached(key);
+		}
+		Ok(written)
+	}
+
+	/// Insert a node like `insert`, also telling whether it's new, i.e. it was neither in the overlay
+	/// nor in the backing database. Saves a separate `contains` call when deduplicating.
+	pub fn insert_returning_new(&mut self, value: &[u8]) -> (H256, bool) {
+		let key = value.sha3();
+		let is_new = !self.contains(&key);
+		self.overlay.emplace(key.clone(), value.to_vec());
+		(key, is_new)
+	}
+
+	/// Insert all the nodes with known keys into the overlay, same as calling `emplace` for each.
+	pub fn emplace_batch<I>(&mut self, entries: I) where I: IntoIterator<Item=(H256, Bytes)> {
+		for (key, value) in entries {
+			self.overlay.emplace(key, value);
+		}
+	}
+
+	/// Remove a reference to each of the nodes from the overlay, same as calling `remove` for each.
+	/// Keys listed more than once lose a reference for every occurrence.
+	pub fn remove_batch(&mut self, keys: &[H256]) {
+		for key in keys {
+			self.overlay.remove(key);
+		}
+	}
+
+	/// Check whether committing the pending operations would succeed, without writing anything.
+	/// Runs the same reference count checks as `commit_to_batch` against the backing database.
+	pub fn validate_commit(&self) -> Result<(), UtilError> {
+		for (key, rc) in self.overlay.keys() {
+			let back_rc = self.payload(&key).map_or(0, |(_, rc)| rc as i32);
+			if back_rc + rc < 0 {
+				return Err(From::from(BaseDataError::NegativelyReferencedHash(key)));
+			}
+		}
+		Ok(())
 	}
 
 	/// Revert all operations on this object (i.e. `insert()`s and `remove()`s) since the
 	/// last `commit()`.
-	pub fn revert(&mut self) { self.overlay.clear(); }
+	pub fn revert(&mut self) {
+		self.overlay.clear();
+		self.removed_aux.clear();
+	}
 
 	/// Get the number of references that would be committed.
 	pub fn commit_refs(&self, key: &H256) -> i32 { self.overlay.raw(key).map_or(0, |(_, refs)| refs) }
 
-	/// Get the refs and value of the given key.
+	/// Get the estimated number of bytes held by the overlay, i.e. what a commit would release.
+	pub fn mem_used(&self) -> usize { self.overlay.mem_used() }
+
+	/// Check whether the given key has been persisted to the backing database, ignoring the overlay.
+	pub fn contains_in_backing(&self, key: &H256) -> Result<bool, UtilError> {
+		let value = try!(self.backing.get(self.column, key).map_err(UtilError::SimpleString));
+		Ok(value.is_some())
+	}
+
+	/// Get the payload stored in the backing database for the key exactly as persisted, i.e. the rlp
+	/// of the reference count, the node and, with era tracking, its era. Ignores the overlay, so pending
+	/// removals don't hide it.
+	/// Meant for repair tools, prefer `get` otherwise.
+	pub fn raw_backing_get(&self, key: &H256) -> Result<Option<Bytes>, UtilError> {
+		self.backing.get(self.column, key).map_err(UtilError::SimpleString)
+	}
+
+	/// Check that the backing database is reachable, telling a broken database from an empty one.
+	pub fn ping_backing(&self) -> Result<(), UtilError> {
+		self.backing.ping().map_err(UtilError::SimpleString)
+	}
+
+	/// Get the overlay counters together with the estimated size of the backing database,
+	/// without reading any nodes from it.
+	pub fn stats(&self) -> Result<OverlayStats, UtilError> {
+		let backing_keys = try!(self.backing.estimated_num_keys(self.column).map_err(UtilError::SimpleString));
+		let keys = self.overlay.keys();
+		Ok(OverlayStats {
+			overlay_entries: self.overlay.len(),
+			pending_insertions: keys.values().filter(|&&rc| rc > 0).count(),
+			pending_deletions: keys.values().filter(|&&rc| rc < 0).count(),
+			backing_keys: backing_keys,
+		})
+	}
+
+	/// Get the number of nodes the backing database would hold once the overlay is committed.
+	///
+	/// The persisted part is rocksdb's own key count estimate, so the result is approximate.
+	pub fn backing_len(&self) -> Result<usize, UtilError> {
+		let persisted = try!(self.backing.estimated_num_keys(self.column).map_err(UtilError::SimpleString)) as i64;
+		let mut delta = 0i64;
+		for (key, rc) in self.overlay.keys() {
+			let back_rc = self.payload(&key).map_or(0, |(_, rc)| rc as i32);
+			match (back_rc > 0, back_rc + rc > 0) {
+				(false, true) => delta += 1,
+				(true, false) => delta -= 1,
+				_ => {},
+			}
+		}
+		Ok(cmp::max(persisted + delta, 0) as usize)
+	}
+
+	/// Get all the keys with their reference counts, like `keys()`, failing on malformed backing entries.
+	pub fn try_keys(&self) -> Result<HashMap<H256, i32>, UtilError> {
+		let mut ret: HashMap<H256, i32> = HashMap::new();
+		for (key, value) in self.backing.iter(self.column) {
+			if is_aux_key(&key) || &*key == ERA_KEY {
+				continue;
+			}
+			if key.len() != 32 {
+				return Err(UtilError::BadSize);
+			}
+			let refs: u32 = try!(UntrustedRlp::new(&value).val_at(0));
+			ret.insert(H256::from_slice(&*key), refs as i32);
+		}
+
+		for (key, refs) in self.overlay.keys().into_iter() {
+			let refs = *ret.get(&key).unwrap_or(&0) + refs;
+			ret.insert(key, refs);
+		}
+		Ok(ret)
+	}
+
+	/// Get the keys of all the auxiliary data, sorted. These are the keys inserted into the overlay
+	/// plus the ones committed to the backing database, less the pending removals.
+	/// Reads the whole backing column.
+	pub fn aux_keys(&self) -> Vec<Bytes> {
+		let mut keys: BTreeSet<Bytes> = self.backing.iter(self.column)
+			.filter(|&(ref key, _)| is_aux_key(key))
+			.map(|(key, _)| aux_key_origin(&key).to_vec())
+			.filter(|key| !self.removed_aux.contains(key))
+			.collect();
+		keys.extend(self.overlay.aux_keys());
+		keys.into_iter().collect()
+	}
+
+	/// Audit the reference counts of all the nodes, e.g. after a crash. Nothing is repaired.
+	pub fn verify_consistency(&self) -> Result<ConsistencyReport, UtilError> {
+		let mut report = ConsistencyReport::default();
+		let mut refs: HashMap<H256, i32> = HashMap::new();
+		for (key, value) in self.backing.iter(self.column) {
+			if is_aux_key(&key) || &*key == ERA_KEY {
+				continue;
+			}
+			report.checked += 1;
+			if key.len() != 32 {
+				report.malformed.push(key.to_vec());
+				continue;
+			}
+			let key = H256::from_slice(&*key);
+			let payload = UntrustedRlp::new(&value);
+			let (rc, node): (u32, Bytes) = match (payload.val_at(0), payload.val_at(1)) {
+				(Ok(rc), Ok(node)) => (rc, node),
+				_ => {
+					report.malformed.push(key.to_vec());
+					continue;
+				},
+			};
+			if rc == 0 && !self.era_tracking {
+				report.zero_refs.push(key.clone());
+			}
+			if node.sha3() != key {
+				report.orphans.push(key.clone());
+			}
+			refs.insert(key, rc as i32);
+		}
+
+		for (key, delta) in self.overlay.keys() {
+			if refs.get(&key).map_or(0, |rc| *rc) + delta < 0 {
+				report.negative_refs.push(key);
+			}
+		}
+		Ok(report)
+	}
+
+	/// Get at most `limit` keys with their reference counts, in key order, starting after `start`.
+	/// Pass the last key of the previous page as `start` to continue paging. Overlay deltas are
+	/// merged in, so paging through all the keys yields the same result as `keys()`.
+	/// Backing database entries whose keys aren't 32 bytes long aren't nodes, so they are skipped.
+	pub fn keys_paged(&self, start: Option<H256>, limit: usize) -> Vec<(H256, i32)> {
+		let after_start = |key: &H256| start.as_ref().map_or(true, |s| key > s);
+
+		let mut overlay: Vec<(H256, i32)> = self.overlay.keys().into_iter()
+			.filter(|&(ref key, _)| after_start(key))
+			.collect();
+		overlay.sort_by(|a, b| a.0.cmp(&b.0));
+		let mut overlay = overlay.into_iter().peekable();
+
+		// auxiliary data and other non-node entries, e.g. journal records sharing the column, are skipped
+		let mut backing = self.backing.iter(self.column)
+			.filter(|&(ref key, _)| key.len() == 32)
+			.map(|(key, _)| H256::from_slice(&*key))
+			.filter(|key| after_start(key))
+			.peekable();
+
+		let mut ret = Vec::with_capacity(cmp::min(limit, 1024));
+		while ret.len() < limit {
+			let order = match (backing.peek(), overlay.peek()) {
+				(None, None) => break,
+				(Some(_), None) => cmp::Ordering::Less,
+				(None, Some(_)) => cmp::Ordering::Greater,
+				(Some(b), Some(&(ref o, _))) => b.cmp(o),
+			};
+			let entry = match order {
+				cmp::Ordering::Less => {
+					let key = backing.next().expect("peeked above; qed");
+					let refs = self.payload(&key).map_or(0, |(_, refs)| refs as i32);
+					(key, refs)
+				},
+				cmp::Ordering::Greater => overlay.next().expect("peeked above; qed"),
+				cmp::Ordering::Equal => {
+					let key = backing.next().expect("peeked above; qed");
+					let (_, delta) = overlay.next().expect("peeked above; qed");
+					let refs = self.payload(&key).map_or(0, |(_, refs)| refs as i32);
+					(key, refs + delta)
+				},
+			};
+			ret.push(entry);
+		}
+		ret
+	}
+
+	/// Get the refs and value of the given key, from the read cache if enabled.
 	fn payload(&self, key: &H256) -> Option<(Bytes, u32)> {
+		let cache = match self.read_cache {
+			Some(ref cache) => cache,
+			None => return self.backing_payload(key),
+		};
+		let mut cache = cache.lock().unwrap();
+		if let Some(payload) = cache.get(key) {
+			return payload;
+		}
+		let payload = self.backing_payload(key);
+		cache.insert(key.clone(), payload.clone());
+		payload
+	}
+
+	/// Forget the cached lookup of a key just written.
+	fn invalidate_cached(&self, key: &H256) {
+		if let Some(ref cache) = self.read_cache {
+			cache.lock().unwrap().invalidate(key);
+		}
+	}
+
+	/// Forget the cached lookups of the keys whose batch was just written.
+	fn invalidate_written(&self, prepared: &mut PreparedCommit) {
+		for (key, _) in prepared.written.drain(..) {
+			self.invalidate_cached(&key);
+		}
+	}
+
+	/// Keep the payloads of a batch which isn't written yet in the read cache, until the next commit.
+	fn keep_unwritten(&self, written: Vec<(H256, Option<(Bytes, u32)>)>) {
+		if let Some(ref cache) = self.read_cache {
+			let mut cache = cache.lock().unwrap();
+			for (key, payload) in written {
+				cache.invalidate(&key);
+				cache.unwritten.insert(key, payload);
+			}
+		}
+	}
+
+	/// Cache the payloads kept by the last `commit_to_batch` like the other lookups.
+	fn settle_cached(&self) {
+		if let Some(ref cache) = self.read_cache {
+			cache.lock().unwrap().settle();
+		}
+	}
+
+	/// Payload the backing database holds once `payload` is put in it, `None` if it's deleted.
+	fn stored_payload(&self, payload: &(Bytes, u32)) -> Option<(Bytes, u32)> {
+		match self.era_tracking || payload.1 > 0 {
+			true => Some(payload.clone()),
+			false => None,
+		}
+	}
+
+	/// Get the refs and value of the given key from the backing database.
+	fn backing_payload(&self, key: &H256) -> Option<(Bytes, u32)> {
 		self.backing.get(self.column, key)
 			.expect("Low-level database error. Some issue with your hard disk?")
 			.map(|d| {
@@ -111,8 +923,17 @@ impl OverlayDB {
 	}
 
 	/// Put the refs and value of the given key, possibly deleting it from the db.
-	fn put_payload_in_batch(&self, batch: &mut DBTransaction, key: &H256, payload: (Bytes, u32)) -> bool {
-		if payload.1 > 0 {
+	/// With era tracking the node is stamped with `era`. Returns whether the node is no longer referenced.
+	fn put_payload_in_batch(&self, batch: &mut DBTransaction, key: &H256, payload: (Bytes, u32), era: u64) -> bool {
+		if self.era_tracking {
+			// unreferenced nodes are kept until pruned
+			let mut s = RlpStream::new_list(3);
+			s.append(&payload.1);
+			s.append(&payload.0);
+			s.append(&era);
+			batch.put(self.column, key, s.as_raw());
+			payload.1 == 0
+		} else if payload.1 > 0 {
 			let mut s = RlpStream::new_list(2);
 			s.append(&payload.1);
 			s.append(&payload.0);
@@ -127,18 +948,7 @@ impl OverlayDB {
 
 impl HashDB for OverlayDB {
 	fn keys(&self) -> HashMap<H256, i32> {
-		let mut ret: HashMap<H256, i32> = HashMap::new();
-		for (key, _) in self.backing.iter(self.column) {
-			let h = H256::from_slice(&*key);
-			let r = self.payload(&h).unwrap().1;
-			ret.insert(h, r as i32);
-		}
-
-		for (key, refs) in self.overlay.keys().into_iter() {
-			let refs = *ret.get(&key).unwrap_or(&0) + refs;
-			ret.insert(key, refs);
-		}
-		ret
+		self.try_keys().expect("Low-level database error. Some issue with your hard disk?")
 	}
 	fn get(&self, key: &H256) -> Option<&[u8]> {
 		// return ok if positive; if negative, check backing - might be enough references there to make
@@ -188,6 +998,29 @@ impl HashDB for OverlayDB {
 	fn insert(&mut self, value: &[u8]) -> H256 { self.overlay.insert(value) }
 	fn emplace(&mut self, key: H256, value: Bytes) { self.overlay.emplace(key, value); }
 	fn remove(&mut self, key: &H256) { self.overlay.remove(key); }
+
+	fn insert_aux(&mut self, hash: Vec<u8>, value: Vec<u8>) {
+		self.removed_aux.remove(&hash);
+		self.overlay.insert_aux(hash, value);
+	}
+
+	fn get_aux(&self, hash: &[u8]) -> Option<Vec<u8>> {
+		if let Some(res) = self.overlay.get_aux(hash) {
+			return Some(res)
+		}
+		if self.removed_aux.contains(hash) {
+			return None
+		}
+
+		self.backing.get(self.column, &aux_key(hash))
+			.expect("Low-level database error. Some issue with your hard disk?")
+			.map(|v| v.to_vec())
+	}
+
+	fn remove_aux(&mut self, hash: &[u8]) {
+		self.overlay.remove_aux(hash);
+		self.removed_aux.insert(hash.to_vec());
+	}
 }
 
 #[test]
@@ -262,6 +1095,74 @@ fn overlaydb_negative() {
 	assert!(trie.commit().is_err());
 }
 
+#[test]
+fn overlaydb_validate_commit() {
+	let mut trie = OverlayDB::new_temp();
+	let h = trie.insert(b"hello world");
+	assert!(trie.validate_commit().is_ok());
+	trie.commit().unwrap();
+	trie.remove(&h);
+	assert!(trie.validate_commit().is_ok());
+	trie.remove(&h);	//bad - sends us into negative refs.
+	assert!(trie.validate_commit().is_err());
+	assert_eq!(trie.commit_refs(&h), -2);
+	trie.revert();
+	assert_eq!(trie.get(&h).unwrap(), b"hello world");
+}
+
+#[test]
+fn overlaydb_bulk_load() {
+	use sha3::Hashable;
+
+	let mut trie = OverlayDB::new_temp();
+	let values: Vec<Bytes> = (0..10000).map(|i| format!("value{}", i).into_bytes()).collect();
+	let loaded = trie.bulk_load(values.iter().map(|v| (v.sha3(), v.clone()))).unwrap();
+	assert_eq!(loaded, values.len());
+	for v in &values {
+		assert_eq!(trie.get(&v.sha3()).unwrap(), &v[..]);
+	}
+	assert_eq!(trie.commit().unwrap(), 0);
+}
+
+#[test]
+fn overlaydb_contains_in_backing() {
+	let mut trie = OverlayDB::new_temp();
+	let h = trie.insert(b"hello world");
+	assert!(trie.contains(&h));
+	assert!(!trie.contains_in_backing(&h).unwrap());
+	trie.commit().unwrap();
+	assert!(trie.contains_in_backing(&h).unwrap());
+	trie.remove(&h);
+	assert!(!trie.contains(&h));
+	assert!(trie.contains_in_backing(&h).unwrap());
+}
+
+#[test]
+fn overlaydb_rollback_to() {
+	let mut dir = ::std::env::temp_dir();
+	dir.push(H32::random().hex());
+	let backing = Arc::new(Database::open_default(dir.to_str().unwrap()).unwrap());
+	let mut trie = OverlayDB::new_with_journal(backing, None, 2);
+
+	let hfoo = trie.insert(b"foo");
+	trie.commit().unwrap();
+	let snapshot = trie.keys();
+
+	let hbar = trie.insert(b"bar");
+	trie.insert(b"foo");
+	trie.commit().unwrap();
+	assert_eq!(trie.keys().get(&hfoo), Some(&2));
+
+	trie.rollback_to(1).unwrap();
+	assert_eq!(trie.keys(), snapshot);
+	assert_eq!(trie.get(&hfoo).unwrap(), b"foo");
+	assert_eq!(trie.get(&hbar), None);
+
+	assert!(trie.rollback_to(2).is_err());
+	trie.rollback_to(1).unwrap();
+	assert!(trie.keys().is_empty());
+}
+
 #[test]
 fn overlaydb_complex() {
 	let mut trie = OverlayDB::new_temp();
@@ -295,6 +1196,591 @@ fn overlaydb_complex() {
 	assert_eq!(trie.get(&hfoo), None);
 }
 
+#[test]
+fn overlaydb_in_memory() {
+	let mut disk = OverlayDB::new_temp();
+	let mut memory = OverlayDB::new_in_memory();
+	for trie in vec![&mut disk, &mut memory] {
+		let hfoo = trie.insert(b"foo");
+		let hbar = trie.insert(b"bar");
+		trie.commit().unwrap();
+		trie.insert(b"foo");	// two refs
+		trie.remove(&hbar);		// zero refs - delete
+		trie.commit().unwrap();
+		assert_eq!(trie.get(&hfoo).unwrap(), b"foo");
+		assert_eq!(trie.get(&hbar), None);
+		assert!(trie.contains_in_backing(&hfoo).unwrap());
+		assert!(!trie.contains_in_backing(&hbar).unwrap());
+	}
+	assert_eq!(disk.keys(), memory.keys());
+}
+
+#[test]
+fn overlaydb_backing_len() {
+	let mut trie = OverlayDB::new_temp();
+	for i in 0..100 {
+		trie.insert(format!("value{}", i).as_bytes());
+	}
+	trie.commit().unwrap();
+	let len = trie.backing_len().unwrap();
+	assert!(len >= 90 && len <= 110);
+
+	trie.insert(b"one more");
+	assert!(trie.backing_len().unwrap() >= len);
+}
+
+#[test]
+fn overlaydb_keys_paged() {
+	let mut trie = OverlayDB::new_temp();
+	let mut committed = Vec::new();
+	for i in 0..200 {
+		committed.push(trie.insert(format!("value{}", i).as_bytes()));
+	}
+	trie.commit().unwrap();
+	for i in 0..50 {
+		trie.remove(&committed[i]);
+		trie.insert(format!("overlay{}", i).as_bytes());
+	}
+
+	let mut paged = HashMap::new();
+	let mut start = None;
+	loop {
+		let page = trie.keys_paged(start, 32);
+		assert!(page.len() <= 32);
+		if page.is_empty() { break; }
+		start = Some(page.last().unwrap().0);
+		for (key, refs) in page {
+			assert!(paged.insert(key, refs).is_none());
+		}
+	}
+	assert_eq!(paged, trie.keys());
+}
+
+#[test]
+fn overlaydb_keys_paged_skips_non_node_entries() {
+	let backing = Arc::new(Database::in_memory(None));
+	let mut batch = backing.transaction();
+	batch.put(None, b"LATEST_ERA", &encode(&7u64));
+	backing.write(batch).unwrap();
+
+	let mut trie = OverlayDB::new(backing, None);
+	let h = trie.insert(b"hello world");
+	trie.insert_aux(b"block".to_vec(), b"chunk".to_vec());
+	trie.commit().unwrap();
+	assert_eq!(trie.keys_paged(None, 10), vec![(h, 1)]);
+}
+
+#[test]
+fn overlaydb_with_capacity() {
+	let mut trie = OverlayDB::with_capacity(Arc::new(Database::in_memory(None)), None, 1000);
+	assert!(trie.overlay.capacity() >= 1000);
+
+	let keys: Vec<H256> = (0..1000).map(|i| trie.insert(format!("value{}", i).as_bytes())).collect();
+	assert!(trie.overlay.capacity() >= 1000);
+	for (i, key) in keys.iter().enumerate() {
+		assert_eq!(trie.get(key).unwrap(), format!("value{}", i).as_bytes());
+	}
+
+	trie.commit().unwrap();
+	for (i, key) in keys.iter().enumerate() {
+		assert_eq!(trie.get(key).unwrap(), format!("value{}", i).as_bytes());
+	}
+	assert_eq!(trie.keys().len(), 1000);
+}
+
+#[test]
+fn overlaydb_fork() {
+	let mut trie = OverlayDB::new_temp();
+	let h = trie.insert(b"hello world");
+	trie.commit().unwrap();
+	let pending = trie.insert(b"pending");
+
+	let mut fork = trie.fork();
+	let tentative = fork.insert(b"tentative");
+	fork.remove(&h);
+	fork.remove(&pending);
+
+	assert!(fork.contains(&tentative));
+	assert!(!fork.contains(&h));
+	assert!(!fork.contains(&pending));
+	drop(fork);
+
+	assert!(!trie.contains(&tentative));
+	assert!(trie.contains(&h));
+	assert!(trie.contains(&pending));
+	assert_eq!(trie.commit_refs(&pending), 1);
+	assert_eq!(trie.commit_refs(&tentative), 0);
+}
+
+#[test]
+fn overlaydb_mem_used() {
+	let mut trie = OverlayDB::new_temp();
+	let empty = trie.mem_used();
+	trie.insert(&[0u8; 1000]);
+	trie.insert(&[1u8; 3000]);
+	assert!(trie.mem_used() >= empty + 4000);
+
+	trie.commit().unwrap();
+	assert!(trie.mem_used() < 4000);
+}
+
+#[test]
+fn overlaydb_commit_chunked() {
+	let mut trie = OverlayDB::new_temp();
+	let keys: Vec<H256> = (0..100).map(|i| trie.insert(format!("value{}", i).as_bytes())).collect();
+	assert_eq!(trie.commit_chunked(7).unwrap(), 100);
+	for key in &keys {
+		assert!(trie.contains_in_backing(key).unwrap());
+	}
+	assert_eq!(trie.keys().len(), 100);
+
+	let pending = trie.insert(b"pending");
+	trie.remove(&pending);
+	trie.remove(&pending);
+	let other = trie.insert(b"other");
+	assert!(trie.commit_chunked(1).is_err());
+	assert!(!trie.contains_in_backing(&other).unwrap());
+}
+
+#[test]
+fn overlaydb_try_keys_reports_malformed_backing() {
+	let backing = Arc::new(Database::in_memory(None));
+	let mut trie = OverlayDB::new(backing.clone(), None);
+	let h = trie.insert(b"hello world");
+	trie.commit().unwrap();
+	assert_eq!(trie.try_keys().unwrap().get(&h), Some(&1));
+
+	let mut batch = backing.transaction();
+	batch.put(None, &H256::from(1), b"not rlp");
+	backing.write(batch).unwrap();
+	assert!(trie.try_keys().is_err());
+
+	let mut batch = backing.transaction();
+	batch.delete(None, &H256::from(1));
+	batch.put(None, b"short key", b"");
+	backing.write(batch).unwrap();
+	assert!(trie.try_keys().is_err());
+}
+
+#[test]
+fn overlaydb_aux_is_persisted() {
+	let backing = Arc::new(Database::in_memory(None));
+	let mut trie = OverlayDB::new(backing.clone(), None);
+	let h = trie.insert(b"hello world");
+	trie.insert_aux(b"block".to_vec(), b"chunk".to_vec());
+	trie.insert_aux(vec![7u8; 28], b"long".to_vec());
+	trie.insert_aux(b"removed".to_vec(), b"gone".to_vec());
+	trie.commit().unwrap();
+
+	let mut fresh = OverlayDB::new(backing.clone(), None);
+	assert_eq!(fresh.get_aux(b"block"), Some(b"chunk".to_vec()));
+	assert_eq!(fresh.get_aux(&[7u8; 28]), Some(b"long".to_vec()));
+	assert_eq!(fresh.get_aux(b"missing"), None);
+	assert_eq!(fresh.keys().len(), 1);
+	assert_eq!(fresh.keys_paged(None, 10), vec![(h, 1)]);
+
+	fresh.remove_aux(b"removed");
+	assert_eq!(fresh.get_aux(b"removed"), None);
+	fresh.commit().unwrap();
+	assert_eq!(OverlayDB::new(backing, None).get_aux(b"removed"), None);
+}
+
+#[test]
+fn overlaydb_read_cache() {
+	let backing = Arc::new(Database::in_memory(None));
+	let mut trie = OverlayDB::new(backing.clone(), None);
+	trie.set_read_cache_size(2);
+	let h = trie.insert(b"hello world");
+	trie.commit().unwrap();
+	assert_eq!(trie.read_cache_misses(), Some(1));
+
+	for _ in 0..5 {
+		assert!(trie.contains(&h));
+	}
+	assert_eq!(trie.read_cache_misses(), Some(2));
+
+	// committed keys are read again
+	trie.remove(&h);
+	trie.commit().unwrap();
+	assert!(!trie.contains(&h));
+	assert_eq!(trie.read_cache_misses(), Some(3));
+
+	// least recently used keys are evicted
+	let a = trie.insert(b"a");
+	let b = trie.insert(b"b");
+	trie.commit().unwrap();
+	trie.contains(&a);
+	trie.contains(&b);
+	trie.contains(&h);
+	let misses = trie.read_cache_misses().unwrap();
+	trie.contains(&h);
+	trie.contains(&b);
+	assert_eq!(trie.read_cache_misses(), Some(misses));
+	trie.contains(&a);
+	assert_eq!(trie.read_cache_misses(), Some(misses + 1));
+}
+
+#[test]
+fn overlaydb_read_cache_before_batch_is_written() {
+	let backing = Arc::new(Database::in_memory(None));
+	let mut trie = OverlayDB::new(backing.clone(), None);
+	trie.set_read_cache_size(16);
+	let h = trie.insert(b"hello world");
+	trie.commit().unwrap();
+	assert!(trie.contains(&h));
+
+	// the removal is seen before the batch is written, so the replaced payload isn't cached again
+	trie.remove(&h);
+	let mut batch = backing.transaction();
+	trie.commit_to_batch(&mut batch).unwrap();
+	assert!(!trie.contains(&h));
+	backing.write(batch).unwrap();
+	assert!(!trie.contains(&h));
+
+	let g = trie.insert(b"goodbye");
+	let mut batch = backing.transaction();
+	trie.commit_to_batch(&mut batch).unwrap();
+	assert_eq!(trie.get(&g).unwrap(), b"goodbye");
+	backing.write(batch).unwrap();
+
+	// the next commit caches the written payloads like any other lookup
+	trie.commit().unwrap();
+	assert_eq!(trie.get(&g).unwrap(), b"goodbye");
+	assert!(!trie.contains(&h));
+	assert!(!OverlayDB::new(backing, None).contains(&h));
+}
+
+#[test]
+fn overlaydb_ping_backing() {
+	let path = ::devtools::RandomTempPath::create_dir();
+	let backing = Arc::new(Database::open_default(path.as_str()).unwrap());
+	let trie = OverlayDB::new(backing.clone(), None);
+	assert!(trie.ping_backing().is_ok());
+	assert!(OverlayDB::new_in_memory().ping_backing().is_ok());
+
+	// a failed restore leaves the database closed
+	let mut missing = ::std::env::temp_dir();
+	missing.push(H32::random().hex());
+	assert!(backing.restore(missing.to_str().unwrap()).is_err());
+	assert!(trie.ping_backing().is_err());
+}
+
+#[test]
+fn overlaydb_reopen() {
+	let path = ::devtools::RandomTempPath::create_dir();
+	let backing = Arc::new(Database::open_default(path.as_str()).unwrap());
+	let mut trie = OverlayDB::new(backing.clone(), None);
+	trie.set_read_cache_size(16);
+	let a = trie.insert(b"a");
+	trie.commit().unwrap();
+
+	// copy of the backing, which got another node meanwhile
+	let copy_path = ::devtools::RandomTempPath::create_dir();
+	let copy = Arc::new(Database::open_default(copy_path.as_str()).unwrap());
+	let mut batch = copy.transaction();
+	for (key, value) in backing.iter(None) {
+		batch.put(None, &key, &value);
+	}
+	let b = b"b".sha3();
+	let mut stream = RlpStream::new_list(2);
+	stream.append(&1u32);
+	stream.append(&&b"b"[..]);
+	batch.put(None, &b, &stream.drain());
+	copy.write(batch).unwrap();
+
+	let c = trie.insert(b"c");
+	assert!(!trie.contains(&b));
+	trie.reopen(copy.clone()).unwrap();
+	assert_eq!(trie.get(&a).unwrap(), b"a");
+	assert_eq!(trie.get(&b).unwrap(), b"b");
+	assert_eq!(trie.get(&c).unwrap(), b"c");
+
+	trie.commit().unwrap();
+	assert!(copy.get(None, &c).unwrap().is_some());
+	assert!(backing.get(None, &c).unwrap().is_none());
+}
+
+#[test]
+fn overlaydb_stats() {
+	let mut trie = OverlayDB::new_in_memory();
+	let committed = trie.insert(b"committed");
+	trie.insert(b"other");
+	trie.commit().unwrap();
+
+	let a = trie.insert(b"a");
+	trie.insert(b"b");
+	trie.insert(b"b");
+	trie.remove(&committed);
+	trie.insert(b"cancelled");
+	trie.remove(&b"cancelled".sha3());
+	trie.remove(&a);
+	trie.insert(b"a");
+
+	assert_eq!(trie.stats().unwrap(), OverlayStats {
+		overlay_entries: 4,
+		pending_insertions: 2,
+		pending_deletions: 1,
+		backing_keys: 2,
+	});
+}
+
+#[test]
+fn overlaydb_emplace_and_remove_batch() {
+	let mut trie = OverlayDB::new_temp();
+	let values: Vec<Bytes> = (0..16u8).map(|i| vec![i; 8]).collect();
+	let keys: Vec<H256> = values.iter().map(|v| v.sha3()).collect();
+
+	trie.emplace_batch(keys.iter().cloned().zip(values.iter().cloned()));
+	assert!(keys.iter().all(|k| trie.contains(k)));
+	assert_eq!(trie.stats().unwrap().pending_insertions, 16);
+
+	trie.remove_batch(&keys);
+	let stats = trie.stats().unwrap();
+	assert_eq!(stats.pending_insertions, 0);
+	assert_eq!(stats.pending_deletions, 0);
+	assert_eq!(trie.commit().unwrap(), 0);
+}
+
+#[test]
+fn overlaydb_insert_returning_new() {
+	let mut trie = OverlayDB::new_temp();
+	let (h, is_new) = trie.insert_returning_new(b"hello world");
+	assert_eq!(h, b"hello world".sha3());
+	assert!(is_new);
+	assert_eq!(trie.insert_returning_new(b"hello world"), (h, false));
+	assert_eq!(trie.commit_refs(&h), 2);
+
+	trie.commit().unwrap();
+	assert_eq!(trie.insert_returning_new(b"hello world"), (h, false));
+	trie.remove(&h);
+	trie.remove(&h);
+	trie.remove(&h);
+	assert_eq!(trie.insert_returning_new(b"hello world"), (h, true));
+}
+
+#[test]
+fn overlaydb_commit_and_compact() {
+	let mut trie = OverlayDB::new_temp();
+	let keys: Vec<H256> = (0..64u8).map(|i| trie.insert(&[i; 32])).collect();
+	assert_eq!(trie.commit().unwrap(), 64);
+
+	for key in &keys[..48] {
+		trie.remove(key);
+	}
+	assert_eq!(trie.commit_and_compact().unwrap(), 48);
+	assert!(keys[..48].iter().all(|k| trie.get(k).is_none()));
+	for (i, key) in keys.iter().enumerate().skip(48) {
+		assert_eq!(trie.get(key).unwrap().to_vec(), vec![i as u8; 32]);
+	}
+}
+
+#[test]
+fn overlaydb_aux_keys() {
+	let backing = Arc::new(Database::in_memory(None));
+	let mut trie = OverlayDB::new(backing.clone(), None);
+	trie.insert(b"hello world");
+	trie.insert_aux(b"block".to_vec(), b"chunk".to_vec());
+	trie.insert_aux(vec![7u8; 28], b"long".to_vec());
+	trie.insert_aux(b"removed".to_vec(), b"gone".to_vec());
+	trie.commit().unwrap();
+
+	trie.insert_aux(b"pending".to_vec(), b"new".to_vec());
+	trie.remove_aux(b"removed");
+	assert_eq!(trie.aux_keys(), vec![vec![7u8; 28], b"block".to_vec(), b"pending".to_vec()]);
+
+	trie.commit().unwrap();
+	assert_eq!(OverlayDB::new(backing, None).aux_keys(), vec![vec![7u8; 28], b"block".to_vec(), b"pending".to_vec()]);
+}
+
+#[test]
+fn overlaydb_prune_older_than() {
+	let backing = Arc::new(Database::in_memory(None));
+	let mut trie = OverlayDB::new(backing.clone(), None);
+	trie.enable_era_tracking().unwrap();
+	let a = trie.insert(b"a");
+	let k = trie.insert(b"kept");
+	trie.commit().unwrap();
+	trie.remove(&a);
+	trie.commit().unwrap();
+	let b = trie.insert(b"b");
+	trie.commit().unwrap();
+	trie.remove(&b);
+	trie.commit().unwrap();
+	assert_eq!(trie.era(), 4);
+	assert!(!trie.contains(&a));
+	assert!(trie.contains_in_backing(&a).unwrap());
+
+	// `a` is unreferenced since era 2, `b` since era 4 and `k` is old but still referenced
+	assert_eq!(trie.prune_older_than(4).unwrap(), 1);
+	assert!(!trie.contains_in_backing(&a).unwrap());
+	assert!(trie.contains_in_backing(&b).unwrap());
+	assert_eq!(trie.get(&k).unwrap(), b"kept");
+
+	// referencing `b` again makes it recent
+	trie.insert(b"b");
+	trie.commit().unwrap();
+	assert_eq!(trie.prune_older_than(6).unwrap(), 0);
+	assert_eq!(trie.get(&b).unwrap(), b"b");
+	assert_eq!(trie.get(&k).unwrap(), b"kept");
+
+	// pending operations keep the node
+	trie.remove(&k);
+	trie.commit().unwrap();
+	trie.insert(b"kept");
+	assert_eq!(trie.prune_older_than(7).unwrap(), 0);
+	assert!(trie.contains_in_backing(&k).unwrap());
+
+	// the era carries on after reopening
+	let mut reopened = OverlayDB::new(backing, None);
+	assert_eq!(reopened.era(), 0);
+	reopened.enable_era_tracking().unwrap();
+	assert_eq!(reopened.era(), 6);
+	assert!(reopened.aux_keys().is_empty());
+}
+
+#[test]
+fn overlaydb_era_apart_from_aux_data() {
+	let backing = Arc::new(Database::in_memory(None));
+	let mut trie = OverlayDB::new(backing.clone(), None);
+	trie.enable_era_tracking().unwrap();
+	trie.insert_aux(b"overlaydb_era".to_vec(), b"user data".to_vec());
+	trie.commit().unwrap();
+	trie.commit().unwrap();
+
+	let mut reopened = OverlayDB::new(backing, None);
+	assert_eq!(reopened.aux_keys(), vec![b"overlaydb_era".to_vec()]);
+	reopened.enable_era_tracking().unwrap();
+	assert_eq!(reopened.era(), 2);
+	assert_eq!(reopened.get_aux(b"overlaydb_era").unwrap(), b"user data".to_vec());
+	assert!(reopened.verify_consistency().unwrap().malformed.is_empty());
+}
+
+#[test]
+fn overlaydb_raw_backing_get() {
+	let mut trie = OverlayDB::new_temp();
+	let h = trie.insert(b"hello world");
+	assert_eq!(trie.raw_backing_get(&h).unwrap(), None);
+	trie.commit().unwrap();
+
+	trie.remove(&h);
+	assert_eq!(trie.get(&h), None);
+	let raw = trie.raw_backing_get(&h).unwrap().unwrap();
+	let payload = UntrustedRlp::new(&raw);
+	assert_eq!(payload.val_at::<u32>(0).unwrap(), 1);
+	assert_eq!(payload.val_at::<Bytes>(1).unwrap(), b"hello world".to_vec());
+}
+
+#[test]
+fn overlaydb_commit_capturing() {
+	let mut trie = OverlayDB::new_in_memory();
+	let h = trie.insert(b"hello world");
+	trie.commit().unwrap();
+
+	let g = trie.insert(b"goodbye");
+	let f = trie.insert(b"farewell");
+	trie.remove(&h);
+	let (stats, insertions, deletions) = trie.commit_capturing().unwrap();
+
+	let mut expected = vec![(g, b"goodbye".to_vec()), (f, b"farewell".to_vec())];
+	expected.sort_by(|a, b| a.0.cmp(&b.0));
+	assert_eq!(insertions, expected);
+	assert_eq!(deletions, vec![h]);
+	assert_eq!((stats.insertions, stats.deletions), (2, 1));
+	assert_eq!(trie.get(&h), None);
+	assert_eq!(trie.get(&g).unwrap(), b"goodbye");
+}
+
+#[test]
+fn overlaydb_commit_hook() {
+	let stats = Arc::new(Mutex::new(Vec::new()));
+	let mut trie = OverlayDB::new_in_memory();
+	let recorded = stats.clone();
+	trie.set_commit_hook(move |s: &CommitStats| recorded.lock().unwrap().push(s.clone()));
+
+	let h = trie.insert(b"hello world");
+	trie.insert(b"goodbye");
+	trie.commit().unwrap();
+	trie.remove(&h);
+	trie.commit().unwrap();
+
+	let stats = stats.lock().unwrap();
+	assert_eq!(stats.len(), 2);
+	assert_eq!((stats[0].insertions, stats[0].deletions), (2, 0));
+	assert_eq!((stats[1].insertions, stats[1].deletions), (0, 1));
+}
+
+#[test]
+fn overlaydb_failed_commit_is_not_applied() {
+	let path = ::devtools::RandomTempPath::create_dir();
+	let backing = Arc::new(Database::open_default(path.as_str()).unwrap());
+	let mut trie = OverlayDB::new_with_journal(backing.clone(), None, 4);
+	trie.enable_era_tracking().unwrap();
+	let calls = Arc::new(Mutex::new(0));
+	let counted = calls.clone();
+	trie.set_commit_hook(move |_: &CommitStats| *counted.lock().unwrap() += 1);
+	trie.set_read_cache_size(16);
+	trie.insert(b"hello world");
+	// caches the lookup, so the commit doesn't read the closed database
+	trie.validate_commit().unwrap();
+
+	// a failed restore leaves the database closed
+	let mut missing = ::std::env::temp_dir();
+	missing.push(H32::random().hex());
+	assert!(backing.restore(missing.to_str().unwrap()).is_err());
+
+	assert!(trie.commit().is_err());
+	assert_eq!(*calls.lock().unwrap(), 0);
+	assert_eq!(trie.era(), 0);
+	assert!(trie.rollback_to(1).is_err());
+}
+
+#[test]
+fn overlaydb_verify_consistency() {
+	let backing = Arc::new(Database::in_memory(None));
+	let mut trie = OverlayDB::new(backing.clone(), None);
+	let h = trie.insert(b"hello world");
+	trie.insert(b"hello world");
+	trie.insert(b"goodbye");
+	trie.insert_aux(b"block".to_vec(), b"chunk".to_vec());
+	trie.commit().unwrap();
+
+	let report = trie.verify_consistency().unwrap();
+	assert!(report.is_consistent());
+	assert_eq!(report.checked, 2);
+
+	trie.remove(&h);
+	trie.remove(&h);
+	assert!(trie.verify_consistency().unwrap().is_consistent());
+	trie.remove(&h);
+	assert_eq!(trie.verify_consistency().unwrap().negative_refs, vec![h.clone()]);
+}
+
+#[test]
+fn overlaydb_verify_consistency_flags_anomalies() {
+	let backing = Arc::new(Database::in_memory(None));
+	let trie = OverlayDB::new(backing.clone(), None);
+
+	let mut batch = backing.transaction();
+	let mut zero = RlpStream::new_list(2);
+	zero.append(&0u32);
+	zero.append(&b"nothing".to_vec());
+	batch.put(None, &b"nothing".sha3(), zero.as_raw());
+	let mut orphan = RlpStream::new_list(2);
+	orphan.append(&1u32);
+	orphan.append(&b"something else".to_vec());
+	batch.put(None, &H256::from(1), orphan.as_raw());
+	batch.put(None, &H256::from(2), b"not rlp");
+	backing.write(batch).unwrap();
+
+	let report = trie.verify_consistency().unwrap();
+	assert!(!report.is_consistent());
+	assert_eq!(report.checked, 3);
+	assert_eq!(report.zero_refs, vec![b"nothing".sha3()]);
+	assert_eq!(report.orphans, vec![H256::from(1)]);
+	assert_eq!(report.malformed, vec![H256::from(2).to_vec()]);
+	assert!(report.negative_refs.is_empty());
+}
+
 #[test]
 fn playpen() {
 	use std::fs;